@@ -0,0 +1,197 @@
+//! Prints or applies the registry entries of a Rust ASIO driver.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use asio_driver::registration::reg_script;
+use asio_driver::registry::{self, parse_guid};
+use asio_driver::DriverRegistration;
+
+const USAGE: &str = "\
+usage: asio_register --name <name> --clsid <guid> --dll <path> [options]
+
+Prints the .reg script registering the driver.
+
+options:
+  --description <text>     description, defaults to the name
+  --threading-model <mdl>  COM threading model, defaults to Apartment
+  --uninstall              remove the entries instead
+  --ops                    list the registry operations instead of the script
+  --apply                  write to the registry of this machine";
+
+#[derive(Default)]
+struct Args {
+    name: Option<String>,
+    clsid: Option<String>,
+    dll: Option<PathBuf>,
+    description: Option<String>,
+    threading_model: Option<String>,
+    uninstall: bool,
+    ops: bool,
+    apply: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--name" => parsed.name = Some(value()?),
+            "--clsid" => parsed.clsid = Some(value()?),
+            "--dll" => parsed.dll = Some(PathBuf::from(value()?)),
+            "--description" => parsed.description = Some(value()?),
+            "--threading-model" => parsed.threading_model = Some(value()?),
+            "--uninstall" => parsed.uninstall = true,
+            "--ops" => parsed.ops = true,
+            "--apply" => parsed.apply = true,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn registration(args: &Args) -> Result<DriverRegistration, String> {
+    let name = args.name.as_deref().ok_or("--name is required")?;
+    let clsid = args.clsid.as_deref().ok_or("--clsid is required")?;
+    let clsid = parse_guid(clsid).ok_or(format!("invalid CLSID {}", clsid))?;
+    // Uninstalling does not need to know where the DLL was
+    let dll = match (&args.dll, args.uninstall) {
+        (Some(dll), _) => dll.clone(),
+        (None, true) => PathBuf::new(),
+        (None, false) => return Err(String::from("--dll is required")),
+    };
+    let mut registration = DriverRegistration::new(name, clsid, dll);
+    if let Some(description) = &args.description {
+        registration.description = description.clone();
+    }
+    if let Some(threading_model) = &args.threading_model {
+        registration.threading_model = threading_model.clone();
+    }
+    Ok(registration)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let registration = match registration(&args) {
+        Ok(registration) => registration,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let ops = if args.uninstall {
+        registration.uninstall_ops()
+    } else {
+        registration.install_ops()
+    };
+    let ops = match ops {
+        Ok(ops) => ops,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.apply {
+        if let Err(err) = registry::apply(&ops) {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    } else if args.ops {
+        for op in &ops {
+            println!("{}", op);
+        }
+    } else {
+        print!("{}", reg_script(&ops));
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    const CLSID: &str = "{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}";
+
+    #[test]
+    fn parses_every_option() {
+        let parsed = args(&[
+            "--name",
+            "Loopback",
+            "--clsid",
+            CLSID,
+            "--dll",
+            "C:\\loopback.dll",
+            "--description",
+            "Virtual Loopback",
+            "--threading-model",
+            "Both",
+            "--uninstall",
+            "--ops",
+            "--apply",
+        ])
+        .unwrap();
+        assert_eq!(parsed.name.as_deref(), Some("Loopback"));
+        assert_eq!(parsed.clsid.as_deref(), Some(CLSID));
+        assert_eq!(parsed.dll, Some(PathBuf::from("C:\\loopback.dll")));
+        assert_eq!(parsed.description.as_deref(), Some("Virtual Loopback"));
+        assert_eq!(parsed.threading_model.as_deref(), Some("Both"));
+        assert!(parsed.uninstall && parsed.ops && parsed.apply);
+
+        assert_eq!(args(&["--name"]).err().unwrap(), "--name needs a value");
+        assert_eq!(
+            args(&["--force"]).err().unwrap(),
+            "unknown argument --force"
+        );
+    }
+
+    #[test]
+    fn builds_the_registration() {
+        let parsed = args(&[
+            "--name",
+            "Loopback",
+            "--clsid",
+            CLSID,
+            "--dll",
+            "loopback.dll",
+            "--description",
+            "Virtual Loopback",
+        ])
+        .unwrap();
+        let built = registration(&parsed).unwrap();
+        assert_eq!(built.name, "Loopback");
+        assert_eq!(built.clsid, parse_guid(CLSID).unwrap());
+        assert_eq!(built.dll_path, PathBuf::from("loopback.dll"));
+        assert_eq!(built.description, "Virtual Loopback");
+        assert_eq!(built.threading_model, "Apartment");
+
+        // Uninstalling does not need the DLL
+        let uninstall = args(&["--name", "Loopback", "--clsid", CLSID, "--uninstall"]).unwrap();
+        assert!(registration(&uninstall).unwrap().uninstall_ops().is_ok());
+
+        let missing = |argv: &[&str]| registration(&args(argv).unwrap()).err().unwrap();
+        assert_eq!(missing(&["--clsid", CLSID]), "--name is required");
+        assert_eq!(missing(&["--name", "Loopback"]), "--clsid is required");
+        assert_eq!(
+            missing(&["--name", "Loopback", "--clsid", CLSID]),
+            "--dll is required"
+        );
+        assert_eq!(
+            missing(&["--name", "Loopback", "--clsid", "{nope}", "--dll", "x"]),
+            "invalid CLSID {nope}"
+        );
+
+        // Names are checked when the operations are built
+        let nested = args(&["--name", "A\\B", "--clsid", CLSID, "--dll", "x"]).unwrap();
+        assert!(registration(&nested).unwrap().install_ops().is_err());
+    }
+}
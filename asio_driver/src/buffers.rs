@@ -0,0 +1,446 @@
+//! Typed, bounds checked views of the driver-owned double buffers.
+//!
+//! After `create_buffers` every `AsioBufferInfo` only carries two untyped
+//! pointers. A [`BufferSet`] pairs them with the buffer size and each
+//! channel's `AsioSampleType`, and hands out [`ChannelBuffer`] views of the
+//! half selected by `double_buffer_idx`.
+//!
+//! Every view holds the buffers in use, and disposing them waits until no view
+//! is left, so a view can never outlive the memory it points into.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::convert::{self, ClipPolicy, ConvertError};
+use crate::{AsioBufferInfo, AsioError, AsioSampleType, Driver};
+
+// Set while the buffers exist, below it the number of views in use
+const ALIVE: usize = 1 << (usize::BITS - 1);
+
+/// Whether the driver still holds the buffers, and how many views use them.
+pub(crate) struct BufferLiveness {
+    state: AtomicUsize,
+}
+
+impl BufferLiveness {
+    pub(crate) fn new() -> Arc<BufferLiveness> {
+        Arc::new(BufferLiveness {
+            state: AtomicUsize::new(ALIVE),
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        self.state.load(Ordering::Acquire) & ALIVE != 0
+    }
+
+    fn enter(&self) -> Option<InUse<'_>> {
+        if self.state.fetch_add(1, Ordering::Acquire) & ALIVE == 0 {
+            self.state.fetch_sub(1, Ordering::Release);
+            return None;
+        }
+        Some(InUse(self))
+    }
+
+    /// Stops handing out views and waits until the ones in use are dropped.
+    pub(crate) fn retire(&self) {
+        self.state.fetch_and(!ALIVE, Ordering::AcqRel);
+        while self.state.load(Ordering::Acquire) != 0 {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Hands out views again after disposing the buffers failed.
+    pub(crate) fn revive(&self) {
+        self.state.fetch_or(ALIVE, Ordering::Release);
+    }
+}
+
+// A view keeping the buffers from being disposed
+struct InUse<'a>(&'a BufferLiveness);
+
+impl Drop for InUse<'_> {
+    fn drop(&mut self) {
+        self.0.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+struct Channel {
+    is_input: bool,
+    channel: i32,
+    sample_type: AsioSampleType,
+    buffers: [*mut u8; 2],
+}
+
+/// The buffers created for a `PreparedDriver`.
+///
+/// Views are only handed out while the buffers exist; once the driver
+/// disposes them [`BufferSet::channel`] returns `None`. Disposing waits for
+/// the views already handed out to be dropped, so holding one on the thread
+/// that disposes the buffers never returns. The set is meant to be moved into
+/// the [`crate::AsioHandler`], which stops being called before the buffers
+/// are disposed.
+pub struct BufferSet {
+    buffer_size: usize,
+    channels: Vec<Channel>,
+    alive: Arc<BufferLiveness>,
+}
+
+// The pointers are only dereferenced through views borrowing the set mutably
+unsafe impl Send for BufferSet {}
+
+impl BufferSet {
+    /// Looks up the sample type of every channel in `buffer_infos`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer_infos` must have been filled by `create_buffers` with
+    /// `buffer_size`, and the buffers must stay allocated until `alive` is
+    /// retired.
+    pub(crate) unsafe fn new(
+        driver: &Driver,
+        buffer_infos: &[AsioBufferInfo],
+        buffer_size: i32,
+        alive: Arc<BufferLiveness>,
+    ) -> Result<BufferSet, AsioError> {
+        let buffer_size = usize::try_from(buffer_size).map_err(|_| AsioError::InvalidParameter)?;
+        let mut channels = Vec::with_capacity(buffer_infos.len());
+        for info in buffer_infos {
+            if info.buffers.iter().any(|buffer| buffer.is_null()) {
+                return Err(AsioError::InvalidParameter);
+            }
+            let is_input = info.is_input.to_bool();
+            let channel_info = driver.get_channel_info(info.channel_num, is_input)?;
+            channels.push(Channel {
+                is_input,
+                channel: info.channel_num,
+                sample_type: channel_info.sample_type,
+                buffers: info.buffers.map(|buffer| buffer.cast()),
+            });
+        }
+        Ok(BufferSet {
+            buffer_size,
+            channels,
+            alive,
+        })
+    }
+
+    /// Samples per channel and half.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+    /// Number of channels, in the order of the `AsioBufferInfo`s.
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+    pub fn sample_type(&self, index: usize) -> Option<AsioSampleType> {
+        self.channels.get(index).map(|channel| channel.sample_type)
+    }
+    /// Whether the buffers are still allocated by the driver.
+    pub fn is_alive(&self) -> bool {
+        self.alive.is_alive()
+    }
+
+    /// View of channel `index` in the half selected by `double_buffer_idx`.
+    pub fn channel(&mut self, index: usize, double_buffer_idx: i32) -> Option<ChannelBuffer<'_>> {
+        let half = half(double_buffer_idx)?;
+        let channel = self.channels.get(index)?;
+        let in_use = self.alive.enter()?;
+        Some(ChannelBuffer::new(channel, half, self.buffer_size, in_use))
+    }
+
+    /// Views of every channel in the half selected by `double_buffer_idx`.
+    pub fn channels(
+        &mut self,
+        double_buffer_idx: i32,
+    ) -> impl Iterator<Item = ChannelBuffer<'_>> + '_ {
+        let half = half(double_buffer_idx);
+        let (alive, buffer_size) = (&*self.alive, self.buffer_size);
+        self.channels.iter().filter_map(move |channel| {
+            let in_use = alive.enter()?;
+            Some(ChannelBuffer::new(channel, half?, buffer_size, in_use))
+        })
+    }
+    pub fn inputs(&mut self, double_buffer_idx: i32) -> impl Iterator<Item = ChannelBuffer<'_>> {
+        self.channels(double_buffer_idx)
+            .filter(|buffer| buffer.is_input())
+    }
+    pub fn outputs(&mut self, double_buffer_idx: i32) -> impl Iterator<Item = ChannelBuffer<'_>> {
+        self.channels(double_buffer_idx)
+            .filter(|buffer| !buffer.is_input())
+    }
+}
+
+fn half(double_buffer_idx: i32) -> Option<usize> {
+    match double_buffer_idx {
+        0 => Some(0),
+        1 => Some(1),
+        _ => None,
+    }
+}
+
+/// One channel's half of the double buffer.
+pub struct ChannelBuffer<'a> {
+    ptr: *mut u8,
+    samples: usize,
+    sample_type: AsioSampleType,
+    is_input: bool,
+    channel: i32,
+    _in_use: InUse<'a>,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> ChannelBuffer<'a> {
+    fn new(channel: &Channel, half: usize, samples: usize, in_use: InUse<'a>) -> ChannelBuffer<'a> {
+        ChannelBuffer {
+            ptr: channel.buffers[half],
+            samples,
+            sample_type: channel.sample_type,
+            is_input: channel.is_input,
+            channel: channel.channel,
+            _in_use: in_use,
+            _buffer: PhantomData,
+        }
+    }
+
+    pub fn sample_type(&self) -> AsioSampleType {
+        self.sample_type
+    }
+    pub fn is_input(&self) -> bool {
+        self.is_input
+    }
+    /// The driver's channel number.
+    pub fn channel(&self) -> i32 {
+        self.channel
+    }
+    /// Number of samples in the buffer.
+    pub fn len(&self) -> usize {
+        self.samples
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // Safety: the set guarantees `samples` samples of `sample_type`
+        unsafe { std::slice::from_raw_parts(self.ptr, self.byte_len()) }
+    }
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.byte_len()) }
+    }
+
+    /// The samples as native `i32`, `None` unless the channel uses
+    /// native-endian 32 bit integer containers.
+    pub fn as_i32(&self) -> Option<&[i32]> {
+        self.typed::<i32>(is_native_i32(self.sample_type))
+            .map(|ptr| unsafe { std::slice::from_raw_parts(ptr, self.samples) })
+    }
+    pub fn as_i32_mut(&mut self) -> Option<&mut [i32]> {
+        self.typed::<i32>(is_native_i32(self.sample_type))
+            .map(|ptr| unsafe { std::slice::from_raw_parts_mut(ptr, self.samples) })
+    }
+    /// The samples as native `f32`, `None` unless the channel uses
+    /// native-endian 32 bit floats.
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        self.typed::<f32>(is_native_f32(self.sample_type))
+            .map(|ptr| unsafe { std::slice::from_raw_parts(ptr, self.samples) })
+    }
+    pub fn as_f32_mut(&mut self) -> Option<&mut [f32]> {
+        self.typed::<f32>(is_native_f32(self.sample_type))
+            .map(|ptr| unsafe { std::slice::from_raw_parts_mut(ptr, self.samples) })
+    }
+
+    /// Decodes the buffer into `dst`, which must hold [`ChannelBuffer::len`] samples.
+    pub fn read_f32(&self, dst: &mut [f32]) -> Result<(), ConvertError> {
+        convert::decode_f32(self.sample_type, self.as_bytes(), dst)
+    }
+    /// Encodes `src`, which must hold [`ChannelBuffer::len`] samples, into the buffer.
+    pub fn write_f32(&mut self, src: &[f32], clip: ClipPolicy) -> Result<(), ConvertError> {
+        let sample_type = self.sample_type;
+        convert::encode_f32(sample_type, src, self.as_bytes_mut(), clip)
+    }
+    /// Writes silence, which is all zero bits for every sample type.
+    pub fn clear(&mut self) {
+        self.as_bytes_mut().fill(0);
+    }
+
+    fn byte_len(&self) -> usize {
+        self.samples * self.sample_type.bytes_per_sample()
+    }
+
+    fn typed<T>(&self, matches: bool) -> Option<*mut T> {
+        let ptr = self.ptr.cast::<T>();
+        (matches && ptr.is_aligned()).then_some(ptr)
+    }
+}
+
+fn is_native_i32(sample_type: AsioSampleType) -> bool {
+    use AsioSampleType::*;
+    if cfg!(target_endian = "little") {
+        matches!(
+            sample_type,
+            AsioSTInt32LSB
+                | AsioSTInt32LSB16
+                | AsioSTInt32LSB18
+                | AsioSTInt32LSB20
+                | AsioSTInt32LSB24
+        )
+    } else {
+        matches!(
+            sample_type,
+            AsioSTInt32MSB
+                | AsioSTInt32MSB16
+                | AsioSTInt32MSB18
+                | AsioSTInt32MSB20
+                | AsioSTInt32MSB24
+        )
+    }
+}
+
+fn is_native_f32(sample_type: AsioSampleType) -> bool {
+    if cfg!(target_endian = "little") {
+        sample_type == AsioSampleType::AsioSTFloat32LSB
+    } else {
+        sample_type == AsioSampleType::AsioSTFloat32MSB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AsioSampleType::*;
+
+    // Backs a set with host memory, as a driver would after `create_buffers`
+    fn set(
+        buffer_size: usize,
+        sample_types: &[(bool, AsioSampleType)],
+        storage: &mut Vec<Vec<u64>>,
+    ) -> (BufferSet, Arc<BufferLiveness>) {
+        let alive = BufferLiveness::new();
+        let mut channels = Vec::new();
+        for (index, (is_input, sample_type)) in sample_types.iter().enumerate() {
+            let mut halves = [std::ptr::null_mut(); 2];
+            for half in &mut halves {
+                let words = (buffer_size * sample_type.bytes_per_sample()).div_ceil(8);
+                storage.push(vec![0u64; words]);
+                *half = storage.last_mut().unwrap().as_mut_ptr().cast();
+            }
+            channels.push(Channel {
+                is_input: *is_input,
+                channel: index as i32,
+                sample_type: *sample_type,
+                buffers: halves,
+            });
+        }
+        let set = BufferSet {
+            buffer_size,
+            channels,
+            alive: alive.clone(),
+        };
+        (set, alive)
+    }
+
+    #[test]
+    fn views_cover_exactly_one_half() {
+        let mut storage = Vec::new();
+        let (mut set, _alive) = set(
+            32,
+            &[(false, AsioSTInt32LSB), (false, AsioSTInt24LSB)],
+            &mut storage,
+        );
+        assert_eq!(set.len(), 2);
+        let mut out = set.channel(0, 1).unwrap();
+        assert_eq!(out.len(), 32);
+        out.as_i32_mut().unwrap().fill(-1);
+        assert!(out.as_f32_mut().is_none());
+        drop(out);
+        let mut packed = set.channel(1, 0).unwrap();
+        assert_eq!(packed.as_bytes().len(), 96);
+        assert!(packed.as_i32_mut().is_none());
+        packed.as_bytes_mut().fill(0xff);
+        // Only the selected halves were written
+        assert!(storage[0].iter().all(|word| *word == 0));
+        assert!(storage[1][..16].iter().all(|word| *word == u64::MAX));
+        assert!(storage[2][..12].iter().all(|word| *word == u64::MAX));
+        assert!(storage[3].iter().all(|word| *word == 0));
+    }
+
+    #[test]
+    fn rejects_bad_indices() {
+        let mut storage = Vec::new();
+        let (mut set, _alive) = set(8, &[(true, AsioSTFloat32LSB)], &mut storage);
+        assert!(set.channel(1, 0).is_none());
+        assert!(set.channel(0, 2).is_none());
+        assert!(set.channel(0, -1).is_none());
+        assert_eq!(set.channels(2).count(), 0);
+        assert_eq!(set.channel(0, 0).unwrap().as_f32().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn converts_through_the_channel_type() {
+        let mut storage = Vec::new();
+        let (mut set, _alive) = set(
+            4,
+            &[(true, AsioSTInt16LSB), (false, AsioSTInt16MSB)],
+            &mut storage,
+        );
+        let src = [0.5, -0.5, 0.25, 0.0];
+        for mut buffer in set.channels(0) {
+            buffer.write_f32(&src, ClipPolicy::Saturate).unwrap();
+        }
+        let mut dst = [0.0f32; 4];
+        set.channel(1, 0).unwrap().read_f32(&mut dst).unwrap();
+        assert_eq!(dst, src);
+        assert_eq!(set.outputs(0).next().unwrap().as_bytes()[..2], [0x40, 0x00]);
+        assert_eq!(set.inputs(0).next().unwrap().as_bytes()[..2], [0x00, 0x40]);
+        assert_eq!(
+            set.channel(0, 0)
+                .unwrap()
+                .write_f32(&[0.0; 5], ClipPolicy::Saturate),
+            Err(ConvertError::LengthMismatch {
+                samples: 5,
+                bytes: 8
+            })
+        );
+        let mut buffer = set.channel(0, 0).unwrap();
+        buffer.clear();
+        assert!(buffer.as_bytes().iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn no_views_after_dispose() {
+        let mut storage = Vec::new();
+        let (mut set, alive) = set(16, &[(false, AsioSTInt32LSB)], &mut storage);
+        alive.retire();
+        assert!(!set.is_alive());
+        assert!(set.channel(0, 0).is_none());
+        assert_eq!(set.outputs(1).count(), 0);
+        alive.revive();
+        assert_eq!(set.outputs(1).count(), 1);
+    }
+
+    #[test]
+    fn dispose_waits_for_views_in_use() {
+        let mut storage = Vec::new();
+        let (mut set, alive) = set(16, &[(false, AsioSTInt32LSB)], &mut storage);
+        let mut view = set.channel(0, 0).unwrap();
+        let retired = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let disposer = std::thread::spawn({
+            let retired = retired.clone();
+            move || {
+                alive.retire();
+                retired.store(true, Ordering::Release);
+            }
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!retired.load(Ordering::Acquire));
+        view.clear();
+        drop(view);
+        disposer.join().unwrap();
+        assert!(retired.load(Ordering::Acquire));
+        assert!(set.channel(0, 0).is_none());
+    }
+}
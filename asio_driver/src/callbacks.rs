@@ -0,0 +1,280 @@
+//! Routing of the ASIO callbacks into a Rust trait object.
+//!
+//! `AsioCallbacks` carries bare function pointers without a user-data argument.
+//! ASIO only allows a single loaded driver per process, so the trampolines in
+//! this module dispatch to the one handler held by the process-wide slot.
+
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError, TryLockError};
+
+use crate::messages::{HostCapabilities, HostMessage};
+use crate::{AsioBool, AsioCallbacks, AsioMessageSelector, AsioSampleRate, AsioTime};
+
+/// Receives the driver callbacks.
+///
+/// Calls are serialized, the driver may however invoke them from different
+/// threads. `buffer_switch` runs on the audio thread and must not block.
+///
+/// The audio thread never waits for the handler: a buffer switch arriving
+/// while another callback is running is skipped and counted in
+/// [`CallbackSlot::skipped_switches`]. `asio_message` and
+/// `sample_rate_did_change` wait for a running buffer switch instead, so they
+/// should return quickly too.
+pub trait AsioHandler: Send {
+    fn buffer_switch(&mut self, double_buffer_idx: i32, direct_process: bool);
+
+    /// Called instead of `buffer_switch` when the host answered
+    /// `SupportsTimeInfo` and the driver supports it.
+    fn buffer_switch_time_info(
+        &mut self,
+        _time: &mut AsioTime,
+        double_buffer_idx: i32,
+        direct_process: bool,
+    ) {
+        self.buffer_switch(double_buffer_idx, direct_process)
+    }
+
+    fn sample_rate_did_change(&mut self, _sample_rate: AsioSampleRate) {}
+
+    /// Answers host queries and requests from the driver. The default only
+    /// claims support for the mandatory selectors and reports engine version 2.
+    fn asio_message(
+        &mut self,
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut std::ffi::c_void,
+        opt: *mut f64,
+    ) -> i32 {
+        HostCapabilities::default().reply(&HostMessage::decode(selector, value, message, opt))
+    }
+}
+
+/// Another stream already occupies the process-wide callback slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotBusy;
+
+impl std::fmt::Display for SlotBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("only one ASIO driver can be active per process")
+    }
+}
+
+impl std::error::Error for SlotBusy {}
+
+struct HandlerSlot {
+    handler: Mutex<Box<dyn AsioHandler>>,
+    skipped: AtomicU64,
+}
+
+static ACTIVE_SLOT: AtomicPtr<HandlerSlot> = AtomicPtr::new(std::ptr::null_mut());
+
+// Tests acquiring the process-wide slot run one at a time
+#[cfg(test)]
+pub(crate) static TEST_SLOT: Mutex<()> = Mutex::new(());
+
+/// Ownership of the process-wide callback slot.
+///
+/// Dropping it frees the slot, which must only happen once the driver has
+/// stopped calling back, i.e. after `dispose_buffers` returned.
+pub struct CallbackSlot {
+    slot: *mut HandlerSlot,
+}
+
+// The slot only hands out the handler under its mutex
+unsafe impl Send for CallbackSlot {}
+
+impl CallbackSlot {
+    pub fn acquire<H: AsioHandler + 'static>(handler: H) -> Result<CallbackSlot, SlotBusy> {
+        Self::acquire_boxed(Box::new(handler))
+    }
+    pub fn acquire_boxed(handler: Box<dyn AsioHandler>) -> Result<CallbackSlot, SlotBusy> {
+        let slot = Box::into_raw(Box::new(HandlerSlot {
+            handler: Mutex::new(handler),
+            skipped: AtomicU64::new(0),
+        }));
+        match ACTIVE_SLOT.compare_exchange(
+            std::ptr::null_mut(),
+            slot,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => Ok(CallbackSlot { slot }),
+            Err(_) => {
+                drop(unsafe { Box::from_raw(slot) });
+                Err(SlotBusy)
+            }
+        }
+    }
+
+    /// Callback table whose trampolines dispatch to this slot's handler.
+    pub fn callbacks(&self) -> AsioCallbacks {
+        AsioCallbacks {
+            buffer_switch,
+            sample_rate_did_change,
+            asio_message,
+            buffer_switch_time_info,
+        }
+    }
+
+    /// Buffer switches dropped because the handler was busy with another
+    /// callback.
+    pub fn skipped_switches(&self) -> u64 {
+        // Safety: the slot lives as long as `self`
+        unsafe { &*self.slot }.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Frees the slot and hands the handler back.
+    pub fn release(self) -> Box<dyn AsioHandler> {
+        let this = std::mem::ManuallyDrop::new(self);
+        unregister(this.slot)
+            .handler
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for CallbackSlot {
+    fn drop(&mut self) {
+        drop(unregister(self.slot));
+    }
+}
+
+fn unregister(slot: *mut HandlerSlot) -> Box<HandlerSlot> {
+    let _ = ACTIVE_SLOT.compare_exchange(
+        slot,
+        std::ptr::null_mut(),
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    unsafe { Box::from_raw(slot) }
+}
+
+fn active_slot() -> Option<&'static HandlerSlot> {
+    let slot = ACTIVE_SLOT.load(Ordering::Acquire);
+    // Safety: the slot is only freed after the driver stopped calling back.
+    unsafe { slot.as_ref() }
+}
+
+// For the non-audio callbacks, which may wait for a buffer switch to finish
+fn with_handler<R>(f: impl FnOnce(&mut dyn AsioHandler) -> R) -> Option<R> {
+    let slot = active_slot()?;
+    let mut handler = slot.handler.lock().unwrap_or_else(PoisonError::into_inner);
+    Some(f(handler.as_mut()))
+}
+
+// For the buffer switches, skipped rather than waiting on another callback
+fn try_with_handler(f: impl FnOnce(&mut dyn AsioHandler)) {
+    let Some(slot) = active_slot() else {
+        return;
+    };
+    let mut handler = match slot.handler.try_lock() {
+        Ok(handler) => handler,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            slot.skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    f(handler.as_mut())
+}
+
+unsafe extern "C" fn buffer_switch(double_buffer_idx: i32, direct_process: AsioBool) {
+    try_with_handler(|handler| handler.buffer_switch(double_buffer_idx, direct_process.to_bool()));
+}
+
+unsafe extern "C" fn sample_rate_did_change(sample_rate: AsioSampleRate) {
+    with_handler(|handler| handler.sample_rate_did_change(sample_rate));
+}
+
+unsafe extern "C" fn asio_message(
+    selector: AsioMessageSelector,
+    value: i32,
+    message: *mut std::ffi::c_void,
+    opt: *mut f64,
+) -> i32 {
+    with_handler(|handler| handler.asio_message(selector, value, message, opt)).unwrap_or(0)
+}
+
+unsafe extern "C" fn buffer_switch_time_info(
+    params: *mut AsioTime,
+    double_buffer_index: i32,
+    direct_process: AsioBool,
+) -> *mut AsioTime {
+    try_with_handler(|handler| match params.as_mut() {
+        Some(time) => {
+            handler.buffer_switch_time_info(time, double_buffer_index, direct_process.to_bool())
+        }
+        None => handler.buffer_switch(double_buffer_index, direct_process.to_bool()),
+    });
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Arc;
+
+    // Holds the handler inside `asio_message` until told to return
+    struct Stalling {
+        switches: Arc<AtomicUsize>,
+        entered: Sender<()>,
+        release: Receiver<()>,
+    }
+
+    impl AsioHandler for Stalling {
+        fn buffer_switch(&mut self, _double_buffer_idx: i32, _direct_process: bool) {
+            self.switches.fetch_add(1, Ordering::Relaxed);
+        }
+        fn asio_message(
+            &mut self,
+            _selector: AsioMessageSelector,
+            _value: i32,
+            _message: *mut std::ffi::c_void,
+            _opt: *mut f64,
+        ) -> i32 {
+            self.entered.send(()).unwrap();
+            self.release.recv().unwrap();
+            1
+        }
+    }
+
+    #[test]
+    fn buffer_switches_skip_a_busy_handler() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let switches = Arc::new(AtomicUsize::new(0));
+        let (entered, entered_rx) = channel();
+        let (release_tx, release) = channel();
+        let slot = CallbackSlot::acquire(Stalling {
+            switches: switches.clone(),
+            entered,
+            release,
+        })
+        .unwrap();
+        let callbacks = slot.callbacks();
+
+        let asio_message = callbacks.asio_message;
+        let message = std::thread::spawn(move || unsafe {
+            asio_message(
+                AsioMessageSelector::ResetRequest,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        });
+        entered_rx.recv().unwrap();
+        unsafe {
+            (callbacks.buffer_switch)(0, AsioBool::True);
+            (callbacks.buffer_switch_time_info)(std::ptr::null_mut(), 1, AsioBool::True);
+        }
+        assert_eq!(switches.load(Ordering::Relaxed), 0);
+        assert_eq!(slot.skipped_switches(), 2);
+
+        release_tx.send(()).unwrap();
+        assert_eq!(message.join().unwrap(), 1);
+        unsafe { (callbacks.buffer_switch)(0, AsioBool::True) };
+        assert_eq!(switches.load(Ordering::Relaxed), 1);
+        assert_eq!(slot.skipped_switches(), 2);
+    }
+}
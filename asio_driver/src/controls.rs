@@ -0,0 +1,367 @@
+//! Hardware gain and metering on top of the `AsioChannelControls` future calls.
+//!
+//! Gains use the same scale as input monitoring, 0x20000000 being 0 dB.
+//! Meters run from 0 to 0x7fffffff at full scale. [`MeterService`] samples
+//! every metered channel on a background thread.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::driver::SharedDriver;
+use crate::monitor::{db_to_linear, linear_to_db, UNITY_GAIN};
+use crate::{AsioError, AsioFutureSelector, Driver};
+
+/// Side of the interface a channel belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    pub fn is_input(self) -> bool {
+        self == Direction::Input
+    }
+    pub(crate) fn gain_selector(self) -> AsioFutureSelector {
+        match self {
+            Direction::Input => AsioFutureSelector::CanInputGain,
+            Direction::Output => AsioFutureSelector::CanOutputGain,
+        }
+    }
+    pub(crate) fn meter_selector(self) -> AsioFutureSelector {
+        match self {
+            Direction::Input => AsioFutureSelector::CanInputMeter,
+            Direction::Output => AsioFutureSelector::CanOutputMeter,
+        }
+    }
+}
+
+/// Hardware gain, clamped to what the SDK can express: silence up to about
+/// +12 dB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Gain(i32);
+
+impl Gain {
+    pub const MUTE: Gain = Gain(0);
+    pub const UNITY: Gain = Gain(UNITY_GAIN);
+
+    pub fn from_raw(raw: i32) -> Gain {
+        Gain(raw.max(0))
+    }
+    /// Not a number mutes.
+    pub fn from_linear(gain: f64) -> Gain {
+        // Saturating casts clamp to the range and map NaN to 0
+        Gain::from_raw((gain * UNITY_GAIN as f64).round() as i32)
+    }
+    pub fn from_db(db: f64) -> Gain {
+        Gain::from_linear(db_to_linear(db))
+    }
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+    pub fn linear(self) -> f64 {
+        self.0 as f64 / UNITY_GAIN as f64
+    }
+    pub fn db(self) -> f64 {
+        linear_to_db(self.linear())
+    }
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Gain::UNITY
+    }
+}
+
+/// Meter reading of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MeterLevel(i32);
+
+impl MeterLevel {
+    pub fn from_raw(raw: i32) -> MeterLevel {
+        MeterLevel(raw.max(0))
+    }
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+    /// 1.0 at full scale.
+    pub fn linear(self) -> f64 {
+        self.0 as f64 / i32::MAX as f64
+    }
+    /// Decibels relative to full scale, `f64::NEG_INFINITY` for silence.
+    pub fn dbfs(self) -> f64 {
+        linear_to_db(self.linear())
+    }
+}
+
+/// Why a gain or meter call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlError {
+    /// The driver does not answer the given `CanXXX` selector.
+    Unsupported(AsioFutureSelector),
+    /// The driver answers neither `CanInputMeter` nor `CanOutputMeter`.
+    NoMeters,
+    InvalidChannel(Direction, i32),
+    Driver(AsioError),
+    /// The OS refused to start the [`MeterService`] thread.
+    Spawn(std::io::ErrorKind),
+}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlError::Unsupported(selector) => {
+                write!(f, "the driver does not support {:?}", selector)
+            }
+            ControlError::NoMeters => f.write_str("the driver cannot meter any channel"),
+            ControlError::InvalidChannel(direction, channel) => {
+                write!(f, "no {:?} channel {}", direction, channel)
+            }
+            ControlError::Driver(error) => write!(f, "the driver call failed: {}", error),
+            ControlError::Spawn(kind) => write!(f, "starting the meter thread failed: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// Levels from the latest sweep of a [`MeterService`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MeterReadings {
+    /// One level per channel, empty if that side is not metered.
+    pub inputs: Vec<MeterLevel>,
+    pub outputs: Vec<MeterLevel>,
+    /// Completed sweeps over all channels.
+    pub sweeps: u64,
+    /// The most recent failed reading. The channel keeps its previous level.
+    pub last_error: Option<ControlError>,
+}
+
+impl MeterReadings {
+    fn silent(inputs: usize, outputs: usize) -> MeterReadings {
+        MeterReadings {
+            inputs: vec![MeterLevel::default(); inputs],
+            outputs: vec![MeterLevel::default(); outputs],
+            ..MeterReadings::default()
+        }
+    }
+}
+
+struct ServiceState {
+    readings: MeterReadings,
+    interval: Duration,
+    stop: bool,
+}
+
+struct ServiceShared {
+    state: Mutex<ServiceState>,
+    wake: Condvar,
+}
+
+impl ServiceShared {
+    fn lock(&self) -> MutexGuard<'_, ServiceState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Background thread polling the meters of every channel.
+///
+/// It stops when dropped.
+pub struct MeterService {
+    shared: Arc<ServiceShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Keeps a stopped or misconfigured service from spinning
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+impl MeterService {
+    /// Starts polling every `interval` the sides the driver can meter.
+    ///
+    /// # Safety
+    ///
+    /// The driver is called from the service's thread, concurrently with the
+    /// host's calls. The service must be dropped before `driver` is released.
+    pub unsafe fn spawn(driver: &Driver, interval: Duration) -> Result<MeterService, ControlError> {
+        let metered = |direction: Direction| driver.can(direction.meter_selector());
+        let (inputs_metered, outputs_metered) =
+            (metered(Direction::Input), metered(Direction::Output));
+        if !inputs_metered && !outputs_metered {
+            return Err(ControlError::NoMeters);
+        }
+        let channels = driver.get_channels().map_err(ControlError::Driver)?;
+        let count =
+            |metered: bool, channels: i32| if metered { channels.max(0) as usize } else { 0 };
+        let inputs = count(inputs_metered, channels.inputs);
+        let outputs = count(outputs_metered, channels.outputs);
+        let shared = Arc::new(ServiceShared {
+            state: Mutex::new(ServiceState {
+                readings: MeterReadings::silent(inputs, outputs),
+                interval: interval.max(MIN_INTERVAL),
+                stop: false,
+            }),
+            wake: Condvar::new(),
+        });
+        let target = SharedDriver::new(driver);
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name(String::from("asio-meters"))
+            .spawn(move || poll_meters(&target, &thread_shared, inputs, outputs))
+            .map_err(|error| ControlError::Spawn(error.kind()))?;
+        Ok(MeterService {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn readings(&self) -> MeterReadings {
+        self.shared.lock().readings.clone()
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        // Takes effect after the current wait
+        self.shared.lock().interval = interval.max(MIN_INTERVAL);
+    }
+}
+
+impl Drop for MeterService {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn poll_meters(driver: &SharedDriver, shared: &ServiceShared, inputs: usize, outputs: usize) {
+    let mut levels = MeterReadings::silent(inputs, outputs);
+    loop {
+        let mut last_error = None;
+        for (direction, levels) in [
+            (Direction::Input, &mut levels.inputs),
+            (Direction::Output, &mut levels.outputs),
+        ] {
+            for (channel, level) in levels.iter_mut().enumerate() {
+                match driver.read_meter_raw(channel as i32, direction) {
+                    Ok(reading) => *level = reading,
+                    Err(error) => last_error = Some(error),
+                }
+            }
+        }
+        // The driver is only called with the lock released
+        let mut state = shared.lock();
+        let readings = &mut state.readings;
+        readings.inputs.clone_from(&levels.inputs);
+        readings.outputs.clone_from(&levels.outputs);
+        readings.sweeps += 1;
+        if last_error.is_some() {
+            readings.last_error = last_error;
+        }
+        let interval = state.interval;
+        let (state, _) = shared
+            .wake
+            .wait_timeout_while(state, interval, |state| !state.stop)
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.stop {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized, MockCall, MockConfig, MockDriver};
+    use std::time::Instant;
+
+    #[test]
+    fn gain_and_meter_scales() {
+        assert_eq!(Gain::from_db(0.0), Gain::UNITY);
+        assert_eq!(Gain::from_linear(0.5).raw(), 0x1000_0000);
+        assert_eq!(Gain::from_db(f64::NEG_INFINITY), Gain::MUTE);
+        assert_eq!(Gain::from_db(20.0).raw(), i32::MAX);
+        assert_eq!(Gain::from_linear(f64::NAN), Gain::MUTE);
+        assert_eq!(Gain::from_raw(-5), Gain::MUTE);
+        assert!((Gain::from_db(-6.0).db() + 6.0).abs() < 1e-6);
+
+        assert_eq!(MeterLevel::from_raw(i32::MAX).dbfs(), 0.0);
+        assert!((MeterLevel::from_raw(0x4000_0000).dbfs() + 6.0206).abs() < 1e-4);
+        assert_eq!(MeterLevel::default().dbfs(), f64::NEG_INFINITY);
+    }
+
+    fn controlled(capabilities: Vec<AsioFutureSelector>) -> MockDriver {
+        MockDriver::new(MockConfig {
+            capabilities,
+            ..MockConfig::default()
+        })
+    }
+
+    #[test]
+    fn gain_and_meter_calls_are_checked() {
+        let mock = controlled(vec![
+            AsioFutureSelector::CanInputGain,
+            AsioFutureSelector::CanOutputMeter,
+        ]);
+        let driver = initialized(&mock);
+        driver
+            .set_gain(1, Direction::Input, Gain::from_db(-6.0))
+            .unwrap();
+        assert_eq!(mock.gain(true, 1), Some(Gain::from_db(-6.0).raw()));
+        assert_eq!(
+            driver.set_gain(0, Direction::Output, Gain::UNITY),
+            Err(ControlError::Unsupported(AsioFutureSelector::CanOutputGain))
+        );
+        assert_eq!(
+            driver.set_gain(2, Direction::Input, Gain::UNITY),
+            Err(ControlError::InvalidChannel(Direction::Input, 2))
+        );
+
+        mock.set_meter(false, 1, 0x4000_0000);
+        assert_eq!(
+            driver.read_meter(1, Direction::Output),
+            Ok(MeterLevel::from_raw(0x4000_0000))
+        );
+        assert_eq!(
+            driver.read_meter(0, Direction::Input),
+            Err(ControlError::Unsupported(AsioFutureSelector::CanInputMeter))
+        );
+        mock.fail_once(MockCall::GetMeter, AsioError::HwMalfunction);
+        assert_eq!(
+            driver.read_meter(1, Direction::Output),
+            Err(ControlError::Driver(AsioError::HwMalfunction))
+        );
+    }
+
+    #[test]
+    fn service_polls_every_metered_channel() {
+        let mock = controlled(vec![AsioFutureSelector::CanInputMeter]);
+        let driver = initialized(&mock);
+        mock.set_meter(true, 0, i32::MAX);
+        mock.set_meter(true, 1, 0x100);
+        let service = unsafe { MeterService::spawn(&driver, Duration::from_millis(2)) }.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while service.readings().sweeps < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let readings = service.readings();
+        assert!(readings.sweeps >= 2);
+        assert_eq!(
+            readings.inputs,
+            [MeterLevel::from_raw(i32::MAX), MeterLevel::from_raw(0x100)]
+        );
+        assert!(readings.outputs.is_empty());
+        assert_eq!(readings.last_error, None);
+        let refs = mock.ref_count();
+        drop(service);
+        assert_eq!(mock.ref_count(), refs - 1);
+
+        let unmetered = controlled(Vec::new());
+        let driver = initialized(&unmetered);
+        assert!(matches!(
+            unsafe { MeterService::spawn(&driver, Duration::from_millis(2)) },
+            Err(ControlError::NoMeters)
+        ));
+    }
+}
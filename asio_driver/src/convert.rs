@@ -0,0 +1,519 @@
+//! Encoding and decoding of the PCM `AsioSampleType` formats.
+//!
+//! Floating point samples are normalized to `[-1.0, 1.0]`, integer samples
+//! passed as `i32` are full scale, i.e. left-justified in 32 bits. The
+//! `AsioSTInt32xxx16/18/20/24` formats store a sign-extended value of the
+//! given width right-justified in a 32 bit container. DSD formats carry no
+//! PCM samples and are rejected, see [`crate::dsd`] for those.
+
+use crate::AsioSampleType;
+
+/// What to do with samples outside `[-1.0, 1.0]` when encoding to an integer
+/// format. Float formats carry headroom and are never clipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ClipPolicy {
+    /// Clamp to the format's full scale, NaN becomes silence.
+    #[default]
+    Saturate,
+    /// Fail with [`ConvertError::Clipped`] on the first out of range sample.
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertError {
+    Unsupported(AsioSampleType),
+    LengthMismatch {
+        samples: usize,
+        bytes: usize,
+    },
+    /// Interleaved samples that do not split into whole frames.
+    FrameMismatch {
+        samples: usize,
+        channels: usize,
+    },
+    Clipped {
+        index: usize,
+    },
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::Unsupported(sample_type) => {
+                write!(f, "{:?} is not a PCM sample type", sample_type)
+            }
+            ConvertError::LengthMismatch { samples, bytes } => {
+                write!(f, "{} bytes do not hold {} samples", bytes, samples)
+            }
+            ConvertError::FrameMismatch { samples, channels } => write!(
+                f,
+                "{} samples do not form whole frames of {} channels",
+                samples, channels
+            ),
+            ConvertError::Clipped { index } => write!(f, "sample {} is out of range", index),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Layout {
+    // `bits` significant bits, right-justified in the sample's bytes
+    Int { bits: u32 },
+    Float32,
+    Float64,
+}
+
+pub(crate) fn layout(sample_type: AsioSampleType) -> Result<Layout, ConvertError> {
+    use AsioSampleType::*;
+    Ok(match sample_type {
+        AsioSTInt16MSB | AsioSTInt16LSB => Layout::Int { bits: 16 },
+        AsioSTInt24MSB | AsioSTInt24LSB => Layout::Int { bits: 24 },
+        AsioSTInt32MSB | AsioSTInt32LSB => Layout::Int { bits: 32 },
+        AsioSTInt32MSB16 | AsioSTInt32LSB16 => Layout::Int { bits: 16 },
+        AsioSTInt32MSB18 | AsioSTInt32LSB18 => Layout::Int { bits: 18 },
+        AsioSTInt32MSB20 | AsioSTInt32LSB20 => Layout::Int { bits: 20 },
+        AsioSTInt32MSB24 | AsioSTInt32LSB24 => Layout::Int { bits: 24 },
+        AsioSTFloat32MSB | AsioSTFloat32LSB => Layout::Float32,
+        AsioSTFloat64MSB | AsioSTFloat64LSB => Layout::Float64,
+        AsioSTDSDInt8LSB1 | AsioSTDSDInt8MSB1 | AsioSTDSDInt8NER8 => {
+            return Err(ConvertError::Unsupported(sample_type))
+        }
+    })
+}
+
+fn check_len(
+    sample_type: AsioSampleType,
+    samples: usize,
+    bytes: usize,
+) -> Result<Layout, ConvertError> {
+    let layout = layout(sample_type)?;
+    if samples * sample_type.bytes_per_sample() != bytes {
+        return Err(ConvertError::LengthMismatch { samples, bytes });
+    }
+    Ok(layout)
+}
+
+fn read_int(bytes: &[u8], bits: u32, big_endian: bool) -> i32 {
+    // Left-justify the most significant byte so the shifts sign-extend
+    let mut container = [0u8; 4];
+    for (index, byte) in bytes.iter().enumerate() {
+        let index = if big_endian {
+            index
+        } else {
+            bytes.len() - 1 - index
+        };
+        container[index] = *byte;
+    }
+    let value = i32::from_be_bytes(container) >> (32 - 8 * bytes.len() as u32);
+    let unused = 32 - bits;
+    (value << unused) >> unused
+}
+
+fn write_int(value: i32, bytes: &mut [u8], big_endian: bool) {
+    let len = bytes.len();
+    if big_endian {
+        bytes.copy_from_slice(&value.to_be_bytes()[4 - len..]);
+    } else {
+        bytes.copy_from_slice(&value.to_le_bytes()[..len]);
+    }
+}
+
+pub(crate) fn read_f64(bytes: &[u8], layout: Layout, big_endian: bool) -> f64 {
+    match layout {
+        Layout::Int { bits } => {
+            read_int(bytes, bits, big_endian) as f64 / (1u64 << (bits - 1)) as f64
+        }
+        Layout::Float32 => {
+            let raw: [u8; 4] = bytes.try_into().unwrap();
+            if big_endian {
+                f32::from_be_bytes(raw) as f64
+            } else {
+                f32::from_le_bytes(raw) as f64
+            }
+        }
+        Layout::Float64 => {
+            let raw: [u8; 8] = bytes.try_into().unwrap();
+            if big_endian {
+                f64::from_be_bytes(raw)
+            } else {
+                f64::from_le_bytes(raw)
+            }
+        }
+    }
+}
+
+pub(crate) fn write_f64(
+    value: f64,
+    bytes: &mut [u8],
+    layout: Layout,
+    big_endian: bool,
+    clip: ClipPolicy,
+) -> Result<(), ()> {
+    match layout {
+        Layout::Int { bits } => {
+            if clip == ClipPolicy::Reject && !(-1.0..=1.0).contains(&value) {
+                return Err(());
+            }
+            let scale = (1u64 << (bits - 1)) as f64;
+            let max = scale - 1.0;
+            // Ties round to even like the SIMD paths, `as` maps NaN to zero
+            let scaled = (value * scale).round_ties_even().clamp(-scale, max);
+            write_int(scaled as i32, bytes, big_endian);
+        }
+        Layout::Float32 => {
+            let raw = if big_endian {
+                (value as f32).to_be_bytes()
+            } else {
+                (value as f32).to_le_bytes()
+            };
+            bytes.copy_from_slice(&raw);
+        }
+        Layout::Float64 => {
+            let raw = if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            bytes.copy_from_slice(&raw);
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_f64(
+    sample_type: AsioSampleType,
+    src: &[u8],
+    dst: &mut [f64],
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, dst.len(), src.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (sample, bytes) in dst
+        .iter_mut()
+        .zip(src.chunks_exact(sample_type.bytes_per_sample()))
+    {
+        *sample = read_f64(bytes, layout, big_endian);
+    }
+    Ok(())
+}
+
+pub fn decode_f32(
+    sample_type: AsioSampleType,
+    src: &[u8],
+    dst: &mut [f32],
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, dst.len(), src.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (sample, bytes) in dst
+        .iter_mut()
+        .zip(src.chunks_exact(sample_type.bytes_per_sample()))
+    {
+        *sample = read_f64(bytes, layout, big_endian) as f32;
+    }
+    Ok(())
+}
+
+pub fn decode_i32(
+    sample_type: AsioSampleType,
+    src: &[u8],
+    dst: &mut [i32],
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, dst.len(), src.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (sample, bytes) in dst
+        .iter_mut()
+        .zip(src.chunks_exact(sample_type.bytes_per_sample()))
+    {
+        *sample = match layout {
+            Layout::Int { bits } => read_int(bytes, bits, big_endian) << (32 - bits),
+            _ => float_to_i32(read_f64(bytes, layout, big_endian)),
+        };
+    }
+    Ok(())
+}
+
+pub fn encode_f64(
+    sample_type: AsioSampleType,
+    src: &[f64],
+    dst: &mut [u8],
+    clip: ClipPolicy,
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, src.len(), dst.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (index, (sample, bytes)) in src
+        .iter()
+        .zip(dst.chunks_exact_mut(sample_type.bytes_per_sample()))
+        .enumerate()
+    {
+        write_f64(*sample, bytes, layout, big_endian, clip)
+            .map_err(|()| ConvertError::Clipped { index })?;
+    }
+    Ok(())
+}
+
+pub fn encode_f32(
+    sample_type: AsioSampleType,
+    src: &[f32],
+    dst: &mut [u8],
+    clip: ClipPolicy,
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, src.len(), dst.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (index, (sample, bytes)) in src
+        .iter()
+        .zip(dst.chunks_exact_mut(sample_type.bytes_per_sample()))
+        .enumerate()
+    {
+        write_f64(*sample as f64, bytes, layout, big_endian, clip)
+            .map_err(|()| ConvertError::Clipped { index })?;
+    }
+    Ok(())
+}
+
+/// Encodes full scale samples; narrower formats keep the most significant bits.
+pub fn encode_i32(
+    sample_type: AsioSampleType,
+    src: &[i32],
+    dst: &mut [u8],
+) -> Result<(), ConvertError> {
+    let layout = check_len(sample_type, src.len(), dst.len())?;
+    let big_endian = sample_type.is_big_endian();
+    for (sample, bytes) in src
+        .iter()
+        .zip(dst.chunks_exact_mut(sample_type.bytes_per_sample()))
+    {
+        match layout {
+            Layout::Int { bits } => write_int(*sample >> (32 - bits), bytes, big_endian),
+            _ => {
+                let value = *sample as f64 / (1u64 << 31) as f64;
+                let _ = write_f64(value, bytes, layout, big_endian, ClipPolicy::Saturate);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn float_to_i32(value: f64) -> i32 {
+    const SCALE: f64 = (1u64 << 31) as f64;
+    (value * SCALE).round_ties_even().clamp(-SCALE, SCALE - 1.0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AsioSampleType::*;
+
+    fn pcm_types() -> impl Iterator<Item = AsioSampleType> {
+        AsioSampleType::ALL.into_iter().filter(|t| !t.is_dsd())
+    }
+
+    fn bits(sample_type: AsioSampleType) -> u32 {
+        match layout(sample_type).unwrap() {
+            Layout::Int { bits } => bits,
+            Layout::Float32 => 24,
+            Layout::Float64 => 53,
+        }
+    }
+
+    // Sample values exactly representable in an n bit integer format
+    fn grid(bits: u32) -> Vec<i64> {
+        let max = (1i64 << (bits - 1)) - 1;
+        let min = -(1i64 << (bits - 1));
+        let mut values = vec![min, min + 1, -1, 0, 1, max - 1, max, max / 2, min / 3];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..256 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            values.push(min + (state % (1u64 << bits)) as i64);
+        }
+        values
+    }
+
+    #[test]
+    fn every_pcm_type_round_trips_f64() {
+        for sample_type in pcm_types() {
+            let bits = bits(sample_type).min(32);
+            let src: Vec<f64> = grid(bits)
+                .into_iter()
+                .map(|v| v as f64 / (1u64 << (bits - 1)) as f64)
+                .collect();
+            let mut bytes = vec![0u8; src.len() * sample_type.bytes_per_sample()];
+            encode_f64(sample_type, &src, &mut bytes, ClipPolicy::Reject).unwrap();
+            let mut dst = vec![0.0f64; src.len()];
+            decode_f64(sample_type, &bytes, &mut dst).unwrap();
+            assert_eq!(src, dst, "{:?}", sample_type);
+        }
+    }
+
+    #[test]
+    fn every_pcm_type_round_trips_f32() {
+        for sample_type in pcm_types() {
+            let bits = bits(sample_type).min(24);
+            let src: Vec<f32> = grid(bits)
+                .into_iter()
+                .map(|v| v as f32 / (1u32 << (bits - 1)) as f32)
+                .collect();
+            let mut bytes = vec![0u8; src.len() * sample_type.bytes_per_sample()];
+            encode_f32(sample_type, &src, &mut bytes, ClipPolicy::Reject).unwrap();
+            let mut dst = vec![0.0f32; src.len()];
+            decode_f32(sample_type, &bytes, &mut dst).unwrap();
+            assert_eq!(src, dst, "{:?}", sample_type);
+        }
+    }
+
+    #[test]
+    fn every_pcm_type_round_trips_i32() {
+        for sample_type in pcm_types() {
+            let bits = bits(sample_type).min(32);
+            let src: Vec<i32> = grid(bits)
+                .into_iter()
+                .map(|v| (v as i32) << (32 - bits))
+                .collect();
+            let mut bytes = vec![0u8; src.len() * sample_type.bytes_per_sample()];
+            encode_i32(sample_type, &src, &mut bytes).unwrap();
+            let mut dst = vec![0i32; src.len()];
+            decode_i32(sample_type, &bytes, &mut dst).unwrap();
+            assert_eq!(src, dst, "{:?}", sample_type);
+        }
+    }
+
+    #[test]
+    fn every_pcm_type_round_trips_bytes() {
+        for sample_type in pcm_types().filter(|t| !t.is_float()) {
+            let bits = bits(sample_type);
+            let big_endian = sample_type.is_big_endian();
+            let width = sample_type.bytes_per_sample();
+            let mut bytes = vec![0u8; 64 * width];
+            for (value, chunk) in grid(bits).into_iter().zip(bytes.chunks_exact_mut(width)) {
+                write_int(value as i32, chunk, big_endian);
+            }
+            let mut samples = vec![0i32; 64];
+            decode_i32(sample_type, &bytes, &mut samples).unwrap();
+            let mut encoded = vec![0u8; bytes.len()];
+            encode_i32(sample_type, &samples, &mut encoded).unwrap();
+            assert_eq!(bytes, encoded, "{:?}", sample_type);
+        }
+    }
+
+    #[test]
+    fn byte_order_and_alignment() {
+        let encode = |sample_type, value: f64| {
+            let mut bytes = vec![0u8; AsioSampleType::bytes_per_sample(&sample_type)];
+            encode_f64(sample_type, &[value], &mut bytes, ClipPolicy::Saturate).unwrap();
+            bytes
+        };
+        assert_eq!(encode(AsioSTInt16MSB, 0.5), [0x40, 0x00]);
+        assert_eq!(encode(AsioSTInt16LSB, 0.5), [0x00, 0x40]);
+        assert_eq!(encode(AsioSTInt24MSB, -0.5), [0xc0, 0x00, 0x00]);
+        assert_eq!(encode(AsioSTInt24LSB, -0.5), [0x00, 0x00, 0xc0]);
+        assert_eq!(encode(AsioSTInt32MSB, 0.5), [0x40, 0, 0, 0]);
+        assert_eq!(encode(AsioSTInt32LSB, 0.5), [0, 0, 0, 0x40]);
+        assert_eq!(encode(AsioSTInt32MSB16, 0.5), [0, 0, 0x40, 0x00]);
+        assert_eq!(encode(AsioSTInt32LSB16, -0.5), [0x00, 0xc0, 0xff, 0xff]);
+        assert_eq!(encode(AsioSTInt32LSB18, 0.5), [0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(encode(AsioSTInt32LSB20, 0.5), [0x00, 0x00, 0x04, 0x00]);
+        assert_eq!(encode(AsioSTInt32MSB24, 0.5), [0x00, 0x40, 0x00, 0x00]);
+        assert_eq!(encode(AsioSTFloat32MSB, 0.5), 0.5f32.to_be_bytes());
+        assert_eq!(encode(AsioSTFloat32LSB, 0.5), 0.5f32.to_le_bytes());
+        assert_eq!(encode(AsioSTFloat64MSB, 0.5), 0.5f64.to_be_bytes());
+        assert_eq!(encode(AsioSTFloat64LSB, 0.5), 0.5f64.to_le_bytes());
+    }
+
+    #[test]
+    fn saturates_out_of_range_samples() {
+        for sample_type in pcm_types().filter(|t| !t.is_float()) {
+            let src = [1.0f64, 1.5, -1.0, -1.5, f64::NAN, f64::INFINITY];
+            let mut bytes = vec![0u8; src.len() * sample_type.bytes_per_sample()];
+            encode_f64(sample_type, &src, &mut bytes, ClipPolicy::Saturate).unwrap();
+            let mut dst = [0i32; 6];
+            decode_i32(sample_type, &bytes, &mut dst).unwrap();
+            let max = i32::MAX >> (32 - bits(sample_type)) << (32 - bits(sample_type));
+            assert_eq!(
+                dst,
+                [max, max, i32::MIN, i32::MIN, 0, max],
+                "{:?}",
+                sample_type
+            );
+        }
+    }
+
+    #[test]
+    fn reject_reports_first_clipped_sample() {
+        let mut bytes = [0u8; 6];
+        assert_eq!(
+            encode_f32(
+                AsioSTInt16LSB,
+                &[0.25, 1.0, -1.01],
+                &mut bytes,
+                ClipPolicy::Reject
+            ),
+            Err(ConvertError::Clipped { index: 2 })
+        );
+        assert_eq!(
+            encode_f32(
+                AsioSTInt16LSB,
+                &[0.25, f32::NAN, 0.0],
+                &mut bytes,
+                ClipPolicy::Reject
+            ),
+            Err(ConvertError::Clipped { index: 1 })
+        );
+        let mut floats = [0u8; 8];
+        encode_f32(
+            AsioSTFloat32LSB,
+            &[2.0, -3.0],
+            &mut floats,
+            ClipPolicy::Reject,
+        )
+        .unwrap();
+        let mut decoded = [0.0f32; 2];
+        decode_f32(AsioSTFloat32LSB, &floats, &mut decoded).unwrap();
+        assert_eq!(decoded, [2.0, -3.0]);
+    }
+
+    #[test]
+    fn float_formats_decode_to_saturated_i32() {
+        let mut bytes = [0u8; 12];
+        encode_f32(
+            AsioSTFloat32LSB,
+            &[1.0, -1.0, 0.5],
+            &mut bytes,
+            ClipPolicy::Saturate,
+        )
+        .unwrap();
+        let mut dst = [0i32; 3];
+        decode_i32(AsioSTFloat32LSB, &bytes, &mut dst).unwrap();
+        assert_eq!(dst, [i32::MAX, i32::MIN, 1 << 30]);
+    }
+
+    #[test]
+    fn dsd_and_length_errors() {
+        for sample_type in [AsioSTDSDInt8LSB1, AsioSTDSDInt8MSB1, AsioSTDSDInt8NER8] {
+            assert_eq!(
+                decode_f32(sample_type, &[0u8; 4], &mut [0.0; 4]),
+                Err(ConvertError::Unsupported(sample_type))
+            );
+        }
+        assert_eq!(
+            decode_i32(AsioSTInt24LSB, &[0u8; 8], &mut [0; 3]),
+            Err(ConvertError::LengthMismatch {
+                samples: 3,
+                bytes: 8
+            })
+        );
+        assert_eq!(
+            encode_f64(
+                AsioSTInt16MSB,
+                &[0.0; 2],
+                &mut [0u8; 3],
+                ClipPolicy::Saturate
+            ),
+            Err(ConvertError::LengthMismatch {
+                samples: 2,
+                bytes: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+
+use crate::controls::{ControlError, Direction, Gain, MeterLevel};
+use crate::format::ChannelLayout;
+use crate::monitor::{InputMonitorRoute, MonitorError};
+use crate::registry::OpenError;
+use crate::transport::{TransportCommand, TransportError};
+use crate::{
+    AsioBufferInfo, AsioCallbacks, AsioChannelControls, AsioChannelInfo, AsioClockSources,
+    AsioDriver, AsioError, AsioErrorMsg, AsioFutureSelector, AsioInputMonitor, AsioIoFormat,
+    AsioIoFormatType, AsioName, AsioSampleRate, AsioSamples, AsioTimestamp, GUID,
+};
+
+/// Number of input and output channels reported by the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ChannelCounts {
+    pub inputs: i32,
+    pub outputs: i32,
+}
+
+impl ChannelCounts {
+    pub fn total(&self) -> i32 {
+        self.inputs + self.outputs
+    }
+}
+
+/// Buffer sizes supported by the driver, in samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BufferSizeInfo {
+    pub min: i32,
+    pub max: i32,
+    pub preferred: i32,
+    // -1 means powers of two between min and max
+    pub granularity: i32,
+}
+
+/// Input and output latencies, in samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Latencies {
+    pub input: i32,
+    pub output: i32,
+}
+
+/// Sample position together with the system time it was sampled at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SamplePosition {
+    pub samples: u64,
+    /// System time in nanoseconds.
+    pub timestamp: u64,
+}
+
+/// A driver refusing `init`, which reports no error code, with the reason it
+/// gave through `GetErrorMessage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitError {
+    pub message: String,
+}
+
+impl std::fmt::Display for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.message.is_empty() {
+            f.write_str("the driver refused to initialize")
+        } else {
+            write!(f, "the driver refused to initialize: {}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+/// Safe facade over [`AsioDriver`].
+///
+/// Out-parameters are owned by the wrapper and every `AsioError` is mapped to a
+/// `Result`, with `AsioError::Ok` and `AsioError::Success` both treated as success.
+/// The raw vtable calls remain reachable through [`Driver::raw`].
+///
+/// `init`, `create_buffers`, `start`, `stop` and `dispose_buffers` are driven
+/// through the typestate wrappers in [`crate::lifecycle`], starting from
+/// [`crate::LoadedDriver`].
+pub struct Driver {
+    raw: AsioDriver,
+}
+
+impl Driver {
+    pub fn new(driver_guid: GUID) -> Result<Driver, String> {
+        unsafe { AsioDriver::new(driver_guid) }.map(Driver::from_raw)
+    }
+    pub fn open_by_name(name: &str) -> Result<Driver, OpenError> {
+        unsafe { AsioDriver::open_by_name(name) }.map(Driver::from_raw)
+    }
+    pub fn from_raw(raw: AsioDriver) -> Driver {
+        Driver { raw }
+    }
+    pub fn raw(&self) -> &AsioDriver {
+        &self.raw
+    }
+    pub fn into_raw(self) -> AsioDriver {
+        self.raw
+    }
+
+    /// Initializes the driver. A refusal carries the driver's
+    /// [`Driver::error_message`].
+    ///
+    /// # Safety
+    ///
+    /// `sys_handle` must be null or the application's main window handle.
+    pub(crate) unsafe fn init(&self, sys_handle: *mut std::ffi::c_void) -> Result<(), InitError> {
+        if self.raw.init(sys_handle).to_bool() {
+            Ok(())
+        } else {
+            Err(InitError {
+                message: self.error_message(),
+            })
+        }
+    }
+    pub fn driver_name(&self) -> String {
+        let mut name = AsioName::new();
+        unsafe { self.raw.get_driver_name(&mut name) };
+        c_chars_to_string(&name.inner)
+    }
+    pub fn driver_version(&self) -> i32 {
+        unsafe { self.raw.get_driver_version() }
+    }
+    pub fn error_message(&self) -> String {
+        let mut msg = AsioErrorMsg::new();
+        unsafe { self.raw.get_error_message(&mut msg) };
+        c_chars_to_string(&msg.inner)
+    }
+
+    pub(crate) fn start(&self) -> Result<(), AsioError> {
+        unsafe { self.raw.start() }.to_result()
+    }
+    pub(crate) fn stop(&self) -> Result<(), AsioError> {
+        unsafe { self.raw.stop() }.to_result()
+    }
+
+    pub fn get_channels(&self) -> Result<ChannelCounts, AsioError> {
+        let mut counts = ChannelCounts::default();
+        unsafe {
+            self.raw
+                .get_channels(&mut counts.inputs, &mut counts.outputs)
+        }
+        .to_result()?;
+        Ok(counts)
+    }
+    pub fn get_latencies(&self) -> Result<Latencies, AsioError> {
+        let mut latencies = Latencies::default();
+        unsafe {
+            self.raw
+                .get_latencies(&mut latencies.input, &mut latencies.output)
+        }
+        .to_result()?;
+        Ok(latencies)
+    }
+    pub fn get_buffer_size(&self) -> Result<BufferSizeInfo, AsioError> {
+        let mut info = BufferSizeInfo::default();
+        unsafe {
+            self.raw.get_buffer_size(
+                &mut info.min,
+                &mut info.max,
+                &mut info.preferred,
+                &mut info.granularity,
+            )
+        }
+        .to_result()?;
+        Ok(info)
+    }
+
+    pub fn can_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+        unsafe { self.raw.can_sample_rate(sample_rate) }.to_result()
+    }
+    pub fn get_sample_rate(&self) -> Result<AsioSampleRate, AsioError> {
+        let mut sample_rate: AsioSampleRate = 0.0;
+        unsafe { self.raw.get_sample_rate(&mut sample_rate) }.to_result()?;
+        Ok(sample_rate)
+    }
+    pub fn set_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+        unsafe { self.raw.set_sample_rate(sample_rate) }.to_result()
+    }
+
+    pub fn get_clock_sources(&self) -> Result<AsioClockSources, AsioError> {
+        let mut clock_sources = AsioClockSources::new();
+        clock_sources.length = clock_sources.array.len() as i32;
+        unsafe {
+            self.raw
+                .get_clock_sources_raw(clock_sources.array.as_mut_ptr(), &mut clock_sources.length)
+        }
+        .to_result()?;
+        clock_sources.length = clock_sources
+            .length
+            .clamp(0, clock_sources.array.len() as i32);
+        Ok(clock_sources)
+    }
+    pub fn set_clock_source(&self, reference: i32) -> Result<(), AsioError> {
+        unsafe { self.raw.set_clock_source(reference) }.to_result()
+    }
+
+    pub fn get_sample_position(&self) -> Result<SamplePosition, AsioError> {
+        let mut samples = AsioSamples::default();
+        let mut timestamp = AsioTimestamp::default();
+        unsafe { self.raw.get_sample_position(&mut samples, &mut timestamp) }.to_result()?;
+        Ok(SamplePosition {
+            samples: samples.to_u64(),
+            timestamp: timestamp.to_u64(),
+        })
+    }
+
+    pub fn get_channel_info(
+        &self,
+        channel: i32,
+        is_input: bool,
+    ) -> Result<AsioChannelInfo, AsioError> {
+        let mut info = AsioChannelInfo::new(channel, is_input);
+        unsafe { self.raw.get_channel_info(&mut info) }.to_result()?;
+        Ok(info)
+    }
+    pub fn input_channel_info(&self, channel: i32) -> Result<AsioChannelInfo, AsioError> {
+        self.get_channel_info(channel, true)
+    }
+    pub fn output_channel_info(&self, channel: i32) -> Result<AsioChannelInfo, AsioError> {
+        self.get_channel_info(channel, false)
+    }
+
+    /// Channel info of every input and output. Drivers without `GetIoFormat`
+    /// stream PCM, which `format_type` then reports.
+    pub fn channel_layout(&self) -> Result<ChannelLayout, AsioError> {
+        let counts = self.get_channels()?;
+        let infos = |count: i32, is_input: bool| {
+            (0..count)
+                .map(|channel| self.get_channel_info(channel, is_input))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(ChannelLayout {
+            format_type: self.get_io_format().unwrap_or(AsioIoFormatType::PCM),
+            inputs: infos(counts.inputs, true)?,
+            outputs: infos(counts.outputs, false)?,
+        })
+    }
+
+    /// The current format. Drivers without `GetIoFormat` return an error,
+    /// usually `NotPresent`.
+    pub fn get_io_format(&self) -> Result<AsioIoFormatType, AsioError> {
+        let mut format = AsioIoFormat::new(AsioIoFormatType::FormatInvalid);
+        unsafe { self.raw.get_io_format(&mut format) }.to_result()?;
+        Ok(format.format_type)
+    }
+    pub fn can_do_io_format(&self, format_type: AsioIoFormatType) -> Result<(), AsioError> {
+        let mut format = AsioIoFormat::new(format_type);
+        unsafe { self.raw.can_do_io_format(&mut format) }.to_result()
+    }
+    pub(crate) fn set_io_format(&self, format_type: AsioIoFormatType) -> Result<(), AsioError> {
+        let mut format = AsioIoFormat::new(format_type);
+        unsafe { self.raw.set_io_format(&mut format) }.to_result()
+    }
+
+    /// Creates the double buffers for `buffer_infos`.
+    ///
+    /// # Safety
+    ///
+    /// `callbacks` must stay at the same address and its functions must remain
+    /// callable until [`Driver::dispose_buffers`] has returned. The buffer
+    /// pointers written into `buffer_infos` are owned by the driver and only
+    /// valid until then as well.
+    pub(crate) unsafe fn create_buffers(
+        &self,
+        buffer_infos: &mut [AsioBufferInfo],
+        buffer_size: i32,
+        callbacks: &mut AsioCallbacks,
+    ) -> Result<(), AsioError> {
+        self.raw
+            .create_buffers(
+                buffer_infos.as_mut_ptr(),
+                buffer_infos.len() as i32,
+                buffer_size,
+                callbacks,
+            )
+            .to_result()
+    }
+    pub(crate) fn dispose_buffers(&self) -> Result<(), AsioError> {
+        unsafe { self.raw.dispose_buffers() }.to_result()
+    }
+
+    pub fn control_panel(&self) -> Result<(), AsioError> {
+        unsafe { self.raw.control_panel() }.to_result()
+    }
+    pub fn output_ready(&self) -> Result<(), AsioError> {
+        unsafe { self.raw.output_ready() }.to_result()
+    }
+
+    /// Applies one monitoring route, see [`crate::InputMonitorMatrix`] for
+    /// several.
+    pub fn set_input_monitor(&self, route: &InputMonitorRoute) -> Result<(), MonitorError> {
+        let channels = self.input_monitor_channels()?;
+        self.set_input_monitor_raw(route.to_raw(&channels)?)
+    }
+    pub(crate) fn input_monitor_channels(&self) -> Result<ChannelCounts, MonitorError> {
+        if !self.can_input_monitor() {
+            return Err(MonitorError::Unsupported);
+        }
+        self.get_channels().map_err(MonitorError::Driver)
+    }
+    pub(crate) fn set_input_monitor_raw(
+        &self,
+        mut monitor: AsioInputMonitor,
+    ) -> Result<(), MonitorError> {
+        unsafe { self.raw.set_input_monitor(&mut monitor) }
+            .to_result()
+            .map_err(MonitorError::Driver)
+    }
+
+    /// Sets the hardware gain of a channel, gated on `CanInputGain` or
+    /// `CanOutputGain`.
+    pub fn set_gain(
+        &self,
+        channel: i32,
+        direction: Direction,
+        gain: Gain,
+    ) -> Result<(), ControlError> {
+        self.check_control(direction.gain_selector(), channel, direction)?;
+        let mut controls = AsioChannelControls::new(channel, direction.is_input());
+        controls.gain = gain.raw();
+        match direction {
+            Direction::Input => unsafe { self.raw.set_input_gain(&mut controls) },
+            Direction::Output => unsafe { self.raw.set_output_gain(&mut controls) },
+        }
+        .to_result()
+        .map_err(ControlError::Driver)
+    }
+    /// Reads the meter of a channel, gated on `CanInputMeter` or
+    /// `CanOutputMeter`.
+    pub fn read_meter(
+        &self,
+        channel: i32,
+        direction: Direction,
+    ) -> Result<MeterLevel, ControlError> {
+        self.check_control(direction.meter_selector(), channel, direction)?;
+        self.read_meter_raw(channel, direction)
+    }
+    pub(crate) fn read_meter_raw(
+        &self,
+        channel: i32,
+        direction: Direction,
+    ) -> Result<MeterLevel, ControlError> {
+        let mut controls = AsioChannelControls::new(channel, direction.is_input());
+        match direction {
+            Direction::Input => unsafe { self.raw.get_input_meter(&mut controls) },
+            Direction::Output => unsafe { self.raw.get_output_meter(&mut controls) },
+        }
+        .to_result()
+        .map_err(ControlError::Driver)?;
+        Ok(MeterLevel::from_raw(controls.meter))
+    }
+    fn check_control(
+        &self,
+        selector: AsioFutureSelector,
+        channel: i32,
+        direction: Direction,
+    ) -> Result<(), ControlError> {
+        if !self.can(selector) {
+            return Err(ControlError::Unsupported(selector));
+        }
+        let channels = self.get_channels().map_err(ControlError::Driver)?;
+        let count = match direction {
+            Direction::Input => channels.inputs,
+            Direction::Output => channels.outputs,
+        };
+        if !(0..count).contains(&channel) {
+            return Err(ControlError::InvalidChannel(direction, channel));
+        }
+        Ok(())
+    }
+
+    /// Sends a transport command, gated on `CanTransport`.
+    pub fn transport(&self, command: &TransportCommand) -> Result<(), TransportError> {
+        if !self.can_transport() {
+            return Err(TransportError::Unsupported);
+        }
+        let mut params = command.to_raw();
+        unsafe { self.raw.transport(&mut params) }
+            .to_result()
+            .map_err(TransportError::Driver)
+    }
+
+    pub fn can_input_monitor(&self) -> bool {
+        self.can(AsioFutureSelector::CanInputMonitor)
+    }
+    pub fn can_time_info(&self) -> bool {
+        self.can(AsioFutureSelector::CanTimeInfo)
+    }
+    pub fn can_time_code(&self) -> bool {
+        self.can(AsioFutureSelector::CanTimeCode)
+    }
+    pub fn can_transport(&self) -> bool {
+        self.can(AsioFutureSelector::CanTransport)
+    }
+    pub fn can_input_gain(&self) -> bool {
+        self.can(AsioFutureSelector::CanInputGain)
+    }
+    pub fn can_input_meter(&self) -> bool {
+        self.can(AsioFutureSelector::CanInputMeter)
+    }
+    pub fn can_output_gain(&self) -> bool {
+        self.can(AsioFutureSelector::CanOutputGain)
+    }
+    pub fn can_output_meter(&self) -> bool {
+        self.can(AsioFutureSelector::CanOutputMeter)
+    }
+    pub fn can_report_overload(&self) -> bool {
+        self.can(AsioFutureSelector::CanReportOverload)
+    }
+    // CanXXX selectors take no arguments and report support through ASE_SUCCESS
+    pub(crate) fn can(&self, selector: AsioFutureSelector) -> bool {
+        let result = unsafe { self.raw.future(selector, std::ptr::null_mut()) };
+        result == AsioError::Success
+    }
+}
+
+impl From<AsioDriver> for Driver {
+    fn from(raw: AsioDriver) -> Self {
+        Driver::from_raw(raw)
+    }
+}
+
+/// Second reference to a driver object, for calling it from another thread.
+///
+/// It only holds a COM reference, the `CoInitialize` stays balanced by the
+/// [`Driver`] it was made from.
+pub(crate) struct SharedDriver(ManuallyDrop<Driver>);
+
+// Callers of `SharedDriver::new` vouch for the driver tolerating other threads
+unsafe impl Send for SharedDriver {}
+
+impl SharedDriver {
+    pub(crate) fn new(driver: &Driver) -> SharedDriver {
+        SharedDriver(ManuallyDrop::new(Driver::from_raw(driver.raw.add_ref())))
+    }
+}
+
+impl Deref for SharedDriver {
+    type Target = Driver;
+    fn deref(&self) -> &Driver {
+        &self.0
+    }
+}
+
+impl Drop for SharedDriver {
+    fn drop(&mut self) {
+        // Safety: the driver is not used again, only its reference is released
+        unsafe { self.0.raw.release() }
+    }
+}
+
+// Drivers are not required to nul-terminate names that fill the whole buffer
+pub(crate) fn c_chars_to_string(chars: &[std::ffi::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -0,0 +1,467 @@
+//! DSD buffers: repacking between the three DSD sample types and DSD over
+//! PCM (DoP).
+//!
+//! Lengths are counted in 1 bit samples. `AsioSTDSDInt8MSB1` and
+//! `AsioSTDSDInt8LSB1` pack 8 samples per byte, the first one in the most or
+//! least significant bit. `AsioSTDSDInt8NER8` holds one sample per byte,
+//! written as 0 or 1 and read as set when non-zero; its buffers must hold a
+//! multiple of 8 samples to be repacked.
+//!
+//! DoP carries 16 samples per PCM sample for drivers that only stream PCM:
+//! the two DSD bytes, oldest first, below a marker byte alternating between
+//! 0x05 and 0xFA, at a sixteenth of the DSD rate.
+
+use crate::AsioSampleType;
+
+/// Idle pattern in `AsioSTDSDInt8MSB1` order, equal numbers of ones and
+/// zeros.
+pub const SILENCE: u8 = 0x69;
+
+const MARKERS: [u8; 2] = [0x05, 0xFA];
+
+/// Standard DSD rates, multiples of 44.1 kHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DsdRate {
+    Dsd64,
+    Dsd128,
+    Dsd256,
+}
+
+impl DsdRate {
+    pub const ALL: [DsdRate; 3] = [DsdRate::Dsd64, DsdRate::Dsd128, DsdRate::Dsd256];
+
+    pub fn multiple(self) -> u32 {
+        match self {
+            DsdRate::Dsd64 => 64,
+            DsdRate::Dsd128 => 128,
+            DsdRate::Dsd256 => 256,
+        }
+    }
+    /// Rate of the 1 bit samples, what a driver streaming native DSD runs at.
+    pub fn sample_rate(self) -> f64 {
+        self.multiple() as f64 * 44100.0
+    }
+    /// PCM rate carrying this rate over DoP.
+    pub fn dop_sample_rate(self) -> f64 {
+        self.sample_rate() / 16.0
+    }
+    pub fn from_sample_rate(sample_rate: f64) -> Option<DsdRate> {
+        DsdRate::ALL
+            .into_iter()
+            .find(|rate| rate.sample_rate() == sample_rate)
+    }
+    pub fn from_dop_sample_rate(sample_rate: f64) -> Option<DsdRate> {
+        DsdRate::ALL
+            .into_iter()
+            .find(|rate| rate.dop_sample_rate() == sample_rate)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsdError {
+    /// Not a DSD type, or for DoP not one of the PCM types it is carried in.
+    Unsupported(AsioSampleType),
+    /// Buffers not holding the same number of samples, or an
+    /// `AsioSTDSDInt8NER8` buffer not filling whole bytes, in which case
+    /// `samples` is rounded up to the next byte.
+    LengthMismatch { samples: usize, bytes: usize },
+    /// A DoP sample without the expected marker.
+    Marker { index: usize },
+}
+
+impl std::fmt::Display for DsdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DsdError::Unsupported(sample_type) => {
+                write!(f, "{:?} cannot be used here", sample_type)
+            }
+            DsdError::LengthMismatch { samples, bytes } => {
+                write!(f, "{} bytes do not hold {} samples", bytes, samples)
+            }
+            DsdError::Marker { index } => write!(f, "sample {} has no DoP marker", index),
+        }
+    }
+}
+
+impl std::error::Error for DsdError {}
+
+/// Number of 1 bit samples in `bytes` bytes of `sample_type`.
+pub fn samples_in(sample_type: AsioSampleType, bytes: usize) -> Result<usize, DsdError> {
+    match sample_type {
+        AsioSampleType::AsioSTDSDInt8LSB1 | AsioSampleType::AsioSTDSDInt8MSB1 => Ok(bytes * 8),
+        AsioSampleType::AsioSTDSDInt8NER8 => Ok(bytes),
+        _ => Err(DsdError::Unsupported(sample_type)),
+    }
+}
+
+/// Bytes of `sample_type` holding `samples` samples, rounded up to whole
+/// bytes for the 1 bit types.
+pub fn bytes_for(sample_type: AsioSampleType, samples: usize) -> Result<usize, DsdError> {
+    match sample_type {
+        AsioSampleType::AsioSTDSDInt8LSB1 | AsioSampleType::AsioSTDSDInt8MSB1 => {
+            Ok(samples.div_ceil(8))
+        }
+        AsioSampleType::AsioSTDSDInt8NER8 => Ok(samples),
+        _ => Err(DsdError::Unsupported(sample_type)),
+    }
+}
+
+// Samples in `bytes` bytes, which must fill whole bytes of 1 bit samples
+fn whole_samples(sample_type: AsioSampleType, bytes: usize) -> Result<usize, DsdError> {
+    let samples = samples_in(sample_type, bytes)?;
+    if samples % 8 != 0 {
+        return Err(DsdError::LengthMismatch {
+            samples: samples.next_multiple_of(8),
+            bytes,
+        });
+    }
+    Ok(samples)
+}
+
+/// Repacks `src` into `dst`, which must hold as many samples.
+pub fn convert(
+    src_type: AsioSampleType,
+    src: &[u8],
+    dst_type: AsioSampleType,
+    dst: &mut [u8],
+) -> Result<(), DsdError> {
+    let samples = whole_samples(src_type, src.len())?;
+    if whole_samples(dst_type, dst.len())? != samples {
+        return Err(DsdError::LengthMismatch {
+            samples,
+            bytes: dst.len(),
+        });
+    }
+    write_msb(dst_type, msb_bytes(src_type, src), dst);
+    Ok(())
+}
+
+/// Fills `dst` with [`SILENCE`].
+pub fn fill_silence(sample_type: AsioSampleType, dst: &mut [u8]) -> Result<(), DsdError> {
+    let bytes = whole_samples(sample_type, dst.len())? / 8;
+    write_msb(sample_type, std::iter::repeat_n(SILENCE, bytes), dst);
+    Ok(())
+}
+
+// The source as MSB first bytes
+fn msb_bytes(sample_type: AsioSampleType, src: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    let step = match sample_type {
+        AsioSampleType::AsioSTDSDInt8NER8 => 8,
+        _ => 1,
+    };
+    src.chunks_exact(step).map(move |chunk| match sample_type {
+        AsioSampleType::AsioSTDSDInt8LSB1 => chunk[0].reverse_bits(),
+        AsioSampleType::AsioSTDSDInt8NER8 => chunk
+            .iter()
+            .fold(0, |byte, sample| (byte << 1) | (*sample != 0) as u8),
+        _ => chunk[0],
+    })
+}
+
+fn write_msb(sample_type: AsioSampleType, src: impl Iterator<Item = u8>, dst: &mut [u8]) {
+    match sample_type {
+        AsioSampleType::AsioSTDSDInt8NER8 => {
+            for (byte, samples) in src.zip(dst.chunks_exact_mut(8)) {
+                for (bit, sample) in samples.iter_mut().enumerate() {
+                    *sample = (byte >> (7 - bit)) & 1;
+                }
+            }
+        }
+        AsioSampleType::AsioSTDSDInt8LSB1 => {
+            for (byte, out) in src.zip(dst.iter_mut()) {
+                *out = byte.reverse_bits();
+            }
+        }
+        _ => {
+            for (byte, out) in src.zip(dst.iter_mut()) {
+                *out = byte;
+            }
+        }
+    }
+}
+
+fn dop_width(sample_type: AsioSampleType) -> Result<usize, DsdError> {
+    match sample_type {
+        AsioSampleType::AsioSTInt24LSB => Ok(3),
+        AsioSampleType::AsioSTInt32LSB => Ok(4),
+        _ => Err(DsdError::Unsupported(sample_type)),
+    }
+}
+
+fn check_dop_len(
+    dsd_type: AsioSampleType,
+    dsd: usize,
+    pcm_type: AsioSampleType,
+    pcm: usize,
+) -> Result<usize, DsdError> {
+    let samples = whole_samples(dsd_type, dsd)?;
+    let width = dop_width(pcm_type)?;
+    if samples % 16 != 0 || pcm != samples / 16 * width {
+        return Err(DsdError::LengthMismatch {
+            samples,
+            bytes: pcm,
+        });
+    }
+    Ok(width)
+}
+
+/// Packs one channel into DoP, keeping the marker sequence going across
+/// buffers.
+#[derive(Debug, Clone, Default)]
+pub struct DopEncoder {
+    next_marker: usize,
+}
+
+impl DopEncoder {
+    pub fn new() -> DopEncoder {
+        DopEncoder::default()
+    }
+
+    /// Encodes the samples in `dsd` into `pcm`, `AsioSTInt24LSB` or
+    /// `AsioSTInt32LSB`, 16 samples to each PCM sample.
+    pub fn encode(
+        &mut self,
+        dsd_type: AsioSampleType,
+        dsd: &[u8],
+        pcm_type: AsioSampleType,
+        pcm: &mut [u8],
+    ) -> Result<(), DsdError> {
+        let width = check_dop_len(dsd_type, dsd.len(), pcm_type, pcm.len())?;
+        let mut bytes = msb_bytes(dsd_type, dsd);
+        for out in pcm.chunks_exact_mut(width) {
+            let (Some(first), Some(second)) = (bytes.next(), bytes.next()) else {
+                break;
+            };
+            let marker = MARKERS[self.next_marker];
+            self.next_marker ^= 1;
+            // 24 significant bits, left-justified in the 32 bit container
+            out[width - 3..].copy_from_slice(&[second, first, marker]);
+            out[..width - 3].fill(0);
+        }
+        Ok(())
+    }
+}
+
+/// Unpacks DoP from one channel, checking that the markers alternate.
+#[derive(Debug, Clone, Default)]
+pub struct DopDecoder {
+    // Unknown until the first sample
+    next_marker: Option<usize>,
+}
+
+impl DopDecoder {
+    pub fn new() -> DopDecoder {
+        DopDecoder::default()
+    }
+
+    /// Decodes `pcm` into `dsd`. A sample without the expected marker, e.g.
+    /// after the stream switched back to PCM, fails the whole buffer and
+    /// leaves `dsd` untouched.
+    pub fn decode(
+        &mut self,
+        pcm_type: AsioSampleType,
+        pcm: &[u8],
+        dsd_type: AsioSampleType,
+        dsd: &mut [u8],
+    ) -> Result<(), DsdError> {
+        let width = check_dop_len(dsd_type, dsd.len(), pcm_type, pcm.len())?;
+        // Checks every marker before writing anything
+        let mut next_marker = self.next_marker;
+        for (index, sample) in pcm.chunks_exact(width).enumerate() {
+            let marker = sample[width - 1];
+            let expected = match next_marker {
+                Some(next) => next,
+                None => match MARKERS.iter().position(|known| *known == marker) {
+                    Some(position) => position,
+                    None => return Err(DsdError::Marker { index }),
+                },
+            };
+            if marker != MARKERS[expected] {
+                self.next_marker = None;
+                return Err(DsdError::Marker { index });
+            }
+            next_marker = Some(expected ^ 1);
+        }
+        self.next_marker = next_marker;
+        let msb = pcm
+            .chunks_exact(width)
+            .flat_map(|sample| [sample[width - 2], sample[width - 3]]);
+        write_msb(dsd_type, msb, dsd);
+        Ok(())
+    }
+
+    /// Forgets the marker sequence, e.g. after a dropout.
+    pub fn reset(&mut self) {
+        self.next_marker = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AsioSampleType::*;
+
+    const DSD_TYPES: [AsioSampleType; 3] =
+        [AsioSTDSDInt8LSB1, AsioSTDSDInt8MSB1, AsioSTDSDInt8NER8];
+
+    #[test]
+    fn repacks_bit_exact() {
+        let msb = [0x80, 0x69, 0x01, 0xA5];
+        let mut lsb = [0; 4];
+        convert(AsioSTDSDInt8MSB1, &msb, AsioSTDSDInt8LSB1, &mut lsb).unwrap();
+        assert_eq!(lsb, [0x01, 0x96, 0x80, 0xA5]);
+
+        let mut ner8 = [0xFF; 32];
+        convert(AsioSTDSDInt8LSB1, &lsb, AsioSTDSDInt8NER8, &mut ner8).unwrap();
+        assert_eq!(ner8[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(ner8[8..16], [0, 1, 1, 0, 1, 0, 0, 1]);
+        assert_eq!(ner8[16..24], [0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Any non-zero NER8 byte is a set sample
+        ner8[0] = 0xFF;
+        let mut back = [0; 4];
+        convert(AsioSTDSDInt8NER8, &ner8, AsioSTDSDInt8MSB1, &mut back).unwrap();
+        assert_eq!(back, msb);
+
+        // Every pair of layouts round trips
+        let bits: Vec<u8> = (0..=255).collect();
+        for from in DSD_TYPES {
+            for to in DSD_TYPES {
+                let samples = bits.len() * 8;
+                let mut src = vec![0; bytes_for(from, samples).unwrap()];
+                convert(AsioSTDSDInt8MSB1, &bits, from, &mut src).unwrap();
+                let mut dst = vec![0; bytes_for(to, samples).unwrap()];
+                convert(from, &src, to, &mut dst).unwrap();
+                let mut out = vec![0; bits.len()];
+                convert(to, &dst, AsioSTDSDInt8MSB1, &mut out).unwrap();
+                assert_eq!(out, bits, "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_and_length_errors() {
+        let mut ner8 = [0; 16];
+        fill_silence(AsioSTDSDInt8NER8, &mut ner8).unwrap();
+        assert_eq!(ner8[..8], [0, 1, 1, 0, 1, 0, 0, 1]);
+        let mut lsb = [0; 2];
+        fill_silence(AsioSTDSDInt8LSB1, &mut lsb).unwrap();
+        assert_eq!(lsb, [0x96; 2]);
+
+        assert_eq!(
+            convert(AsioSTDSDInt8MSB1, &[0; 2], AsioSTDSDInt8NER8, &mut [0; 8]),
+            Err(DsdError::LengthMismatch {
+                samples: 16,
+                bytes: 8
+            })
+        );
+        assert_eq!(
+            convert(AsioSTInt32LSB, &[0; 4], AsioSTDSDInt8MSB1, &mut [0; 4]),
+            Err(DsdError::Unsupported(AsioSTInt32LSB))
+        );
+
+        // NER8 buffers must fill whole bytes, nothing is written otherwise
+        let partial = Err(DsdError::LengthMismatch {
+            samples: 16,
+            bytes: 12,
+        });
+        let mut dst = [7; 12];
+        assert_eq!(
+            convert(AsioSTDSDInt8NER8, &[1; 12], AsioSTDSDInt8NER8, &mut dst),
+            partial
+        );
+        assert_eq!(fill_silence(AsioSTDSDInt8NER8, &mut dst), partial);
+        assert_eq!(dst, [7; 12]);
+        assert_eq!(
+            convert(AsioSTDSDInt8NER8, &[1; 12], AsioSTDSDInt8MSB1, &mut [0; 2]),
+            partial
+        );
+        assert_eq!(
+            DopEncoder::new().encode(AsioSTDSDInt8NER8, &[1; 12], AsioSTInt24LSB, &mut [0; 3]),
+            partial
+        );
+    }
+
+    #[test]
+    fn dop_layout_is_bit_exact() {
+        let dsd = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+        let mut encoder = DopEncoder::new();
+        let mut pcm = [0; 6];
+        encoder
+            .encode(AsioSTDSDInt8MSB1, &dsd[..4], AsioSTInt24LSB, &mut pcm)
+            .unwrap();
+        assert_eq!(pcm, [0x34, 0x12, 0x05, 0x78, 0x56, 0xFA]);
+        // The marker sequence carries over into the next buffer
+        let mut pcm = [0xFF; 4];
+        encoder
+            .encode(AsioSTDSDInt8MSB1, &dsd[4..], AsioSTInt32LSB, &mut pcm)
+            .unwrap();
+        assert_eq!(pcm, [0x00, 0xBC, 0x9A, 0x05]);
+
+        let mut lsb = [0; 2];
+        convert(AsioSTDSDInt8MSB1, &dsd[..2], AsioSTDSDInt8LSB1, &mut lsb).unwrap();
+        let mut pcm = [0; 3];
+        DopEncoder::new()
+            .encode(AsioSTDSDInt8LSB1, &lsb, AsioSTInt24LSB, &mut pcm)
+            .unwrap();
+        assert_eq!(pcm, [0x34, 0x12, 0x05]);
+    }
+
+    #[test]
+    fn dop_round_trips_and_checks_markers() {
+        let dsd: Vec<u8> = (0..64).map(|i| (i * 37) as u8).collect();
+        for pcm_type in [AsioSTInt24LSB, AsioSTInt32LSB] {
+            let width = pcm_type.bytes_per_sample();
+            let mut pcm = vec![0; dsd.len() / 2 * width];
+            DopEncoder::new()
+                .encode(AsioSTDSDInt8MSB1, &dsd, pcm_type, &mut pcm)
+                .unwrap();
+            let mut ner8 = vec![0; dsd.len() * 8];
+            let mut decoder = DopDecoder::new();
+            decoder
+                .decode(pcm_type, &pcm, AsioSTDSDInt8NER8, &mut ner8)
+                .unwrap();
+            let mut back = vec![0; dsd.len()];
+            convert(AsioSTDSDInt8NER8, &ner8, AsioSTDSDInt8MSB1, &mut back).unwrap();
+            assert_eq!(back, dsd);
+
+            // The next buffer must continue with 0x05, not repeat 0xFA
+            let last = pcm[pcm.len() - width..].to_vec();
+            assert_eq!(
+                decoder.decode(pcm_type, &last, AsioSTDSDInt8MSB1, &mut [0; 2]),
+                Err(DsdError::Marker { index: 0 })
+            );
+        }
+        assert_eq!(
+            DopDecoder::new().decode(AsioSTInt24LSB, &[0; 3], AsioSTDSDInt8MSB1, &mut [0; 2]),
+            Err(DsdError::Marker { index: 0 })
+        );
+        assert_eq!(
+            DopEncoder::new().encode(AsioSTDSDInt8MSB1, &[0; 2], AsioSTInt16LSB, &mut [0; 2]),
+            Err(DsdError::Unsupported(AsioSTInt16LSB))
+        );
+        assert_eq!(
+            DopEncoder::new().encode(AsioSTDSDInt8MSB1, &[0; 3], AsioSTInt24LSB, &mut [0; 3]),
+            Err(DsdError::LengthMismatch {
+                samples: 24,
+                bytes: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(DsdRate::Dsd64.sample_rate(), 2_822_400.0);
+        assert_eq!(DsdRate::Dsd64.dop_sample_rate(), 176_400.0);
+        assert_eq!(DsdRate::Dsd256.dop_sample_rate(), 705_600.0);
+        assert_eq!(
+            DsdRate::from_sample_rate(5_644_800.0),
+            Some(DsdRate::Dsd128)
+        );
+        assert_eq!(
+            DsdRate::from_dop_sample_rate(352_800.0),
+            Some(DsdRate::Dsd128)
+        );
+        assert_eq!(DsdRate::from_dop_sample_rate(192_000.0), None);
+    }
+}
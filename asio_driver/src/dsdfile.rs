@@ -0,0 +1,918 @@
+//! Reading and writing DSD streams as DSF and DSDIFF files.
+//!
+//! [`DsdReader`] and [`DsdWriter`] move 1 bit samples straight between a file
+//! and planar channel buffers of any `AsioSTDSDInt8*` type, see
+//! [`crate::dsd`]. Only uncompressed DSDIFF is supported, not DST. The ID3v2
+//! tag is kept as raw bytes: DSF stores it at the end of the file, DSDIFF in an
+//! `ID3 ` chunk as most tools do.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::buffers::ChannelBuffer;
+use crate::dsd::{self, DsdError, DsdRate};
+use crate::AsioSampleType;
+
+/// Bytes per channel in each DSF block.
+const DSF_BLOCK: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DsdContainer {
+    /// Sony DSD Stream File, `.dsf`.
+    Dsf,
+    /// Philips DSD Interchange File Format, `.dff`.
+    Dff,
+}
+
+impl DsdContainer {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DsdContainer::Dsf => "dsf",
+            DsdContainer::Dff => "dff",
+        }
+    }
+}
+
+/// Stream properties, read from the header or passed to
+/// [`DsdWriter::create`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsdFileInfo {
+    pub container: DsdContainer,
+    /// Rate of the 1 bit samples, e.g. 2822400 for DSD64.
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Samples per channel. Ignored by [`DsdWriter::create`].
+    pub samples: u64,
+    /// Raw ID3v2 tag, header included.
+    pub id3: Option<Vec<u8>>,
+}
+
+impl DsdFileInfo {
+    pub fn new(container: DsdContainer, rate: DsdRate, channels: usize) -> DsdFileInfo {
+        DsdFileInfo {
+            container,
+            sample_rate: rate.sample_rate() as u32,
+            channels,
+            samples: 0,
+            id3: None,
+        }
+    }
+    pub fn with_id3(mut self, id3: Vec<u8>) -> Self {
+        self.id3 = Some(id3);
+        self
+    }
+    /// `None` for rates other than DSD64, DSD128 and DSD256.
+    pub fn rate(&self) -> Option<DsdRate> {
+        DsdRate::from_sample_rate(self.sample_rate as f64)
+    }
+    pub fn duration(&self) -> Duration {
+        match self.sample_rate {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(self.samples as f64 / rate as f64),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DsdFileError {
+    Io(io::Error),
+    /// Not a DSF or DSDIFF stream, or a damaged header.
+    Invalid(&'static str),
+    /// A valid file this module cannot stream, e.g. DST compressed DSDIFF.
+    Unsupported(&'static str),
+    /// Buffers passed for a different number of channels than the file has.
+    ChannelCount {
+        expected: usize,
+        found: usize,
+    },
+    Dsd(DsdError),
+}
+
+impl std::fmt::Display for DsdFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DsdFileError::Io(error) => write!(f, "accessing the file failed: {}", error),
+            DsdFileError::Invalid(what) => write!(f, "invalid DSD file: {}", what),
+            DsdFileError::Unsupported(what) => write!(f, "unsupported DSD file: {}", what),
+            DsdFileError::ChannelCount { expected, found } => {
+                write!(f, "{} buffers passed for {} channels", found, expected)
+            }
+            DsdFileError::Dsd(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DsdFileError {}
+
+impl From<io::Error> for DsdFileError {
+    fn from(error: io::Error) -> Self {
+        DsdFileError::Io(error)
+    }
+}
+
+impl From<DsdError> for DsdFileError {
+    fn from(error: DsdError) -> Self {
+        DsdFileError::Dsd(error)
+    }
+}
+
+fn check_channels(expected: usize, found: usize) -> Result<(), DsdFileError> {
+    if expected != found {
+        return Err(DsdFileError::ChannelCount { expected, found });
+    }
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn le_u32(reader: &mut impl Read) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn le_u64(reader: &mut impl Read) -> io::Result<u64> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+fn be_u16(reader: &mut impl Read) -> io::Result<u16> {
+    read_array(reader).map(u16::from_be_bytes)
+}
+
+fn be_u32(reader: &mut impl Read) -> io::Result<u32> {
+    read_array(reader).map(u32::from_be_bytes)
+}
+
+fn be_u64(reader: &mut impl Read) -> io::Result<u64> {
+    read_array(reader).map(u64::from_be_bytes)
+}
+
+// DSF data is split in blocks of `block_size` bytes per channel
+struct DsfBlocks {
+    lsb_first: bool,
+    block_size: usize,
+    data: Vec<u8>,
+    // Bytes of each channel's block already read
+    pos: usize,
+}
+
+/// Streams the samples of a DSF or DSDIFF file into channel buffers.
+pub struct DsdReader<R> {
+    reader: R,
+    info: DsdFileInfo,
+    remaining: u64,
+    dsf: Option<DsfBlocks>,
+}
+
+impl<R: Read + Seek> DsdReader<R> {
+    /// Parses the header, telling the container from its magic, and leaves
+    /// the reader at the first sample.
+    pub fn open(mut reader: R) -> Result<DsdReader<R>, DsdFileError> {
+        let start = reader.stream_position()?;
+        let (info, dsf) = match &read_array(&mut reader)? {
+            b"DSD " => open_dsf(&mut reader, start)?,
+            b"FRM8" => (open_dff(&mut reader, start)?, None),
+            _ => return Err(DsdFileError::Invalid("not a DSF or DSDIFF stream")),
+        };
+        Ok(DsdReader {
+            reader,
+            remaining: info.samples,
+            info,
+            dsf,
+        })
+    }
+
+    pub fn info(&self) -> &DsdFileInfo {
+        &self.info
+    }
+    /// Samples per channel not read yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next samples into one buffer of `sample_type` per channel,
+    /// as many as the shortest buffer holds in whole bytes.
+    ///
+    /// Returns the number of samples per channel, 0 at the end of the file.
+    /// Past the last sample the buffers are left as they were, see
+    /// [`dsd::fill_silence`].
+    pub fn read(
+        &mut self,
+        sample_type: AsioSampleType,
+        channels: &mut [&mut [u8]],
+    ) -> Result<usize, DsdFileError> {
+        let planes = channels
+            .iter_mut()
+            .map(|channel| (sample_type, &mut **channel))
+            .collect();
+        self.read_planes(planes)
+    }
+
+    /// [`DsdReader::read`] into the driver's buffers, one per channel.
+    pub fn read_buffers(
+        &mut self,
+        buffers: &mut [ChannelBuffer<'_>],
+    ) -> Result<usize, DsdFileError> {
+        let planes = buffers
+            .iter_mut()
+            .map(|buffer| (buffer.sample_type(), buffer.as_bytes_mut()))
+            .collect();
+        self.read_planes(planes)
+    }
+
+    fn read_planes(
+        &mut self,
+        mut planes: Vec<(AsioSampleType, &mut [u8])>,
+    ) -> Result<usize, DsdFileError> {
+        check_channels(self.info.channels, planes.len())?;
+        let mut bytes = usize::MAX;
+        for (sample_type, plane) in &planes {
+            bytes = bytes.min(dsd::samples_in(*sample_type, plane.len())? / 8);
+        }
+        let bytes = self.remaining.div_ceil(8).min(bytes as u64) as usize;
+        if bytes == 0 {
+            return Ok(0);
+        }
+
+        let msb = self.read_msb(bytes)?;
+        for ((sample_type, plane), msb) in planes.iter_mut().zip(&msb) {
+            let len = dsd::bytes_for(*sample_type, bytes * 8)?;
+            dsd::convert(
+                AsioSampleType::AsioSTDSDInt8MSB1,
+                msb,
+                *sample_type,
+                &mut plane[..len],
+            )?;
+        }
+        let samples = self.remaining.min(bytes as u64 * 8);
+        self.remaining -= samples;
+        Ok(samples as usize)
+    }
+
+    // The next `bytes` bytes of every channel, MSB first
+    fn read_msb(&mut self, bytes: usize) -> io::Result<Vec<Vec<u8>>> {
+        let channels = self.info.channels;
+        let mut msb = vec![Vec::with_capacity(bytes); channels];
+        match &mut self.dsf {
+            None => {
+                let mut frames = vec![0; bytes * channels];
+                self.reader.read_exact(&mut frames)?;
+                for frame in frames.chunks_exact(channels) {
+                    for (plane, byte) in msb.iter_mut().zip(frame) {
+                        plane.push(*byte);
+                    }
+                }
+            }
+            Some(blocks) => {
+                let mut done = 0;
+                while done < bytes {
+                    if blocks.pos == blocks.block_size {
+                        self.reader.read_exact(&mut blocks.data)?;
+                        blocks.pos = 0;
+                    }
+                    let take = (blocks.block_size - blocks.pos).min(bytes - done);
+                    for (channel, plane) in msb.iter_mut().enumerate() {
+                        let start = channel * blocks.block_size + blocks.pos;
+                        let block = &blocks.data[start..start + take];
+                        if blocks.lsb_first {
+                            plane.extend(block.iter().map(|byte| byte.reverse_bits()));
+                        } else {
+                            plane.extend_from_slice(block);
+                        }
+                    }
+                    blocks.pos += take;
+                    done += take;
+                }
+            }
+        }
+        Ok(msb)
+    }
+}
+
+// Called after the "DSD " magic
+fn open_dsf(
+    reader: &mut (impl Read + Seek),
+    start: u64,
+) -> Result<(DsdFileInfo, Option<DsfBlocks>), DsdFileError> {
+    let header_size = le_u64(reader)?;
+    let _file_size = le_u64(reader)?;
+    let metadata = le_u64(reader)?;
+
+    let fmt_start = offset(start, header_size)?;
+    reader.seek(SeekFrom::Start(fmt_start))?;
+    if &read_array(reader)? != b"fmt " {
+        return Err(DsdFileError::Invalid("missing fmt chunk"));
+    }
+    let fmt_size = le_u64(reader)?;
+    let _version = le_u32(reader)?;
+    if le_u32(reader)? != 0 {
+        return Err(DsdFileError::Unsupported("DSF format other than raw DSD"));
+    }
+    let _channel_type = le_u32(reader)?;
+    let channels = le_u32(reader)? as usize;
+    let sample_rate = le_u32(reader)?;
+    let lsb_first = match le_u32(reader)? {
+        1 => true,
+        8 => false,
+        _ => return Err(DsdFileError::Invalid("bits per sample not 1 or 8")),
+    };
+    let samples = le_u64(reader)?;
+    let block_size = le_u32(reader)? as usize;
+    if channels == 0 || sample_rate == 0 {
+        return Err(DsdFileError::Invalid("no channels or sample rate"));
+    }
+    if channels > 6 {
+        return Err(DsdFileError::Unsupported("DSF holds at most 6 channels"));
+    }
+    if block_size != DSF_BLOCK {
+        return Err(DsdFileError::Invalid("DSF block size not 4096"));
+    }
+
+    reader.seek(SeekFrom::Start(offset(fmt_start, fmt_size)?))?;
+    if &read_array(reader)? != b"data" {
+        return Err(DsdFileError::Invalid("missing data chunk"));
+    }
+    let _data_size = le_u64(reader)?;
+    let data_start = reader.stream_position()?;
+
+    let mut id3 = None;
+    if metadata != 0 {
+        let mut tag = Vec::new();
+        reader.seek(SeekFrom::Start(offset(start, metadata)?))?;
+        reader.read_to_end(&mut tag)?;
+        reader.seek(SeekFrom::Start(data_start))?;
+        id3 = Some(tag).filter(|tag| !tag.is_empty());
+    }
+
+    let info = DsdFileInfo {
+        container: DsdContainer::Dsf,
+        sample_rate,
+        channels,
+        samples,
+        id3,
+    };
+    let blocks = DsfBlocks {
+        lsb_first,
+        block_size,
+        data: vec![0; block_size * channels],
+        pos: block_size,
+    };
+    Ok((info, Some(blocks)))
+}
+
+// `base + size`, rejecting sizes no stream can hold
+fn offset(base: u64, size: u64) -> Result<u64, DsdFileError> {
+    base.checked_add(size)
+        .ok_or(DsdFileError::Invalid("size out of range"))
+}
+
+// The chunk at `pos` if it starts before `end`, as its id, body offset and size
+fn dff_chunk(
+    reader: &mut (impl Read + Seek),
+    pos: u64,
+    end: u64,
+) -> Result<Option<([u8; 4], u64, u64)>, DsdFileError> {
+    let body = offset(pos, 12)?;
+    if body > end {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(pos))?;
+    let id = read_array(reader)?;
+    let size = be_u64(reader)?;
+    if offset(body, size)? > end {
+        return Err(DsdFileError::Invalid("chunk extends past its parent"));
+    }
+    Ok(Some((id, body, size)))
+}
+
+// Bodies are padded to an even size
+fn dff_next(body: u64, size: u64) -> Result<u64, DsdFileError> {
+    offset(body, size.saturating_add(size & 1))
+}
+
+// Called after the "FRM8" magic
+fn open_dff(reader: &mut (impl Read + Seek), start: u64) -> Result<DsdFileInfo, DsdFileError> {
+    let form_size = be_u64(reader)?;
+    if &read_array(reader)? != b"DSD " {
+        return Err(DsdFileError::Invalid("not a DSDIFF DSD form"));
+    }
+    let end = offset(start + 12, form_size)?;
+    let here = reader.stream_position()?;
+    if end > reader.seek(SeekFrom::End(0))? {
+        return Err(DsdFileError::Invalid(
+            "form extends past the end of the stream",
+        ));
+    }
+    reader.seek(SeekFrom::Start(here))?;
+
+    let (mut sample_rate, mut channels, mut data, mut id3) = (0, 0, None, None);
+    let mut pos = start + 16;
+    while let Some((id, body, size)) = dff_chunk(reader, pos, end)? {
+        match &id {
+            b"PROP" if &read_array(reader)? == b"SND " => {
+                let mut pos = body + 4;
+                while let Some((id, body, size)) = dff_chunk(reader, pos, body + size)? {
+                    match &id {
+                        b"FS  " => sample_rate = be_u32(reader)?,
+                        b"CHNL" => channels = be_u16(reader)? as usize,
+                        b"CMPR" if &read_array(reader)? != b"DSD " => {
+                            return Err(DsdFileError::Unsupported("compressed DSDIFF"))
+                        }
+                        _ => {}
+                    }
+                    pos = dff_next(body, size)?;
+                }
+            }
+            b"DSD " => data = Some((body, size)),
+            b"DST " => return Err(DsdFileError::Unsupported("compressed DSDIFF")),
+            b"ID3 " => {
+                let mut tag = vec![0; size as usize];
+                reader.read_exact(&mut tag)?;
+                id3 = Some(tag);
+            }
+            _ => {}
+        }
+        pos = dff_next(body, size)?;
+    }
+
+    if channels == 0 || sample_rate == 0 {
+        return Err(DsdFileError::Invalid("no channels or sample rate"));
+    }
+    let (data_start, data_size) = data.ok_or(DsdFileError::Invalid("missing DSD chunk"))?;
+    reader.seek(SeekFrom::Start(data_start))?;
+    Ok(DsdFileInfo {
+        container: DsdContainer::Dff,
+        sample_rate,
+        channels,
+        samples: data_size / channels as u64 * 8,
+        id3,
+    })
+}
+
+/// Writes samples from channel buffers into a DSF or DSDIFF file.
+///
+/// The header sizes are only filled in by [`DsdWriter::finish`], a writer
+/// dropped before leaves a file readers will reject.
+pub struct DsdWriter<W: Write + Seek> {
+    writer: W,
+    info: DsdFileInfo,
+    start: u64,
+    data_start: u64,
+    samples: u64,
+    // DSF only: each channel's unfinished block, LSB first
+    pending: Vec<Vec<u8>>,
+}
+
+impl<W: Write + Seek> DsdWriter<W> {
+    /// Writes the header for `info` at the current position of `writer`.
+    pub fn create(mut writer: W, info: DsdFileInfo) -> Result<DsdWriter<W>, DsdFileError> {
+        if info.channels == 0 || info.sample_rate == 0 {
+            return Err(DsdFileError::Invalid("no channels or sample rate"));
+        }
+        let start = writer.stream_position()?;
+        let pending = match info.container {
+            DsdContainer::Dsf => {
+                writer.write_all(&dsf_header(&info)?)?;
+                vec![Vec::with_capacity(DSF_BLOCK); info.channels]
+            }
+            DsdContainer::Dff => {
+                writer.write_all(&dff_header(&info)?)?;
+                Vec::new()
+            }
+        };
+        let data_start = writer.stream_position()?;
+        Ok(DsdWriter {
+            writer,
+            info,
+            start,
+            data_start,
+            samples: 0,
+            pending,
+        })
+    }
+
+    pub fn info(&self) -> &DsdFileInfo {
+        &self.info
+    }
+    /// Samples per channel written so far.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Appends one buffer of `sample_type` per channel. The buffers must hold
+    /// the same number of samples, a multiple of 8.
+    pub fn write(
+        &mut self,
+        sample_type: AsioSampleType,
+        channels: &[&[u8]],
+    ) -> Result<(), DsdFileError> {
+        let planes = channels
+            .iter()
+            .map(|channel| (sample_type, *channel))
+            .collect();
+        self.write_planes(planes)
+    }
+
+    /// [`DsdWriter::write`] from the driver's buffers, one per channel.
+    pub fn write_buffers(&mut self, buffers: &[ChannelBuffer<'_>]) -> Result<(), DsdFileError> {
+        let planes = buffers
+            .iter()
+            .map(|buffer| (buffer.sample_type(), buffer.as_bytes()))
+            .collect();
+        self.write_planes(planes)
+    }
+
+    fn write_planes(&mut self, planes: Vec<(AsioSampleType, &[u8])>) -> Result<(), DsdFileError> {
+        check_channels(self.info.channels, planes.len())?;
+        let (sample_type, first) = planes[0];
+        let samples = dsd::samples_in(sample_type, first.len())?;
+        let mut msb = Vec::with_capacity(planes.len());
+        for (sample_type, plane) in planes {
+            let mut bytes = vec![0; samples / 8];
+            dsd::convert(
+                sample_type,
+                plane,
+                AsioSampleType::AsioSTDSDInt8MSB1,
+                &mut bytes,
+            )?;
+            msb.push(bytes);
+        }
+
+        match self.info.container {
+            DsdContainer::Dsf => self.write_dsf(&msb)?,
+            DsdContainer::Dff => {
+                let mut frames = Vec::with_capacity(samples / 8 * msb.len());
+                for index in 0..samples / 8 {
+                    frames.extend(msb.iter().map(|plane| plane[index]));
+                }
+                self.writer.write_all(&frames)?;
+            }
+        }
+        self.samples += samples as u64;
+        Ok(())
+    }
+
+    fn write_dsf(&mut self, msb: &[Vec<u8>]) -> io::Result<()> {
+        let len = msb[0].len();
+        let mut offset = 0;
+        while offset < len {
+            let take = (DSF_BLOCK - self.pending[0].len()).min(len - offset);
+            for (pending, plane) in self.pending.iter_mut().zip(msb) {
+                let bytes = &plane[offset..offset + take];
+                pending.extend(bytes.iter().map(|byte| byte.reverse_bits()));
+            }
+            offset += take;
+            if self.pending[0].len() == DSF_BLOCK {
+                self.flush_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the pending blocks, zero padded
+    fn flush_block(&mut self) -> io::Result<()> {
+        for pending in &mut self.pending {
+            pending.resize(DSF_BLOCK, 0);
+            self.writer.write_all(pending)?;
+            pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the last block and the ID3 tag, and fills in the header sizes.
+    /// Returns the writer positioned at the end of the file.
+    pub fn finish(mut self) -> Result<W, DsdFileError> {
+        match self.info.container {
+            DsdContainer::Dsf => {
+                if !self.pending[0].is_empty() {
+                    self.flush_block()?;
+                }
+                let data_end = self.writer.stream_position()?;
+                let mut metadata = 0;
+                if let Some(tag) = &self.info.id3 {
+                    self.writer.write_all(tag)?;
+                    metadata = data_end - self.start;
+                }
+                let end = self.writer.stream_position()?;
+                self.patch(12, &(end - self.start).to_le_bytes())?;
+                self.patch(20, &metadata.to_le_bytes())?;
+                self.patch(64, &self.samples.to_le_bytes())?;
+                self.patch(84, &(data_end - self.start - 80).to_le_bytes())?;
+                self.writer.seek(SeekFrom::Start(end))?;
+            }
+            DsdContainer::Dff => {
+                let data_size = self.writer.stream_position()? - self.data_start;
+                if data_size & 1 != 0 {
+                    self.writer.write_all(&[0])?;
+                }
+                if let Some(tag) = &self.info.id3 {
+                    self.writer.write_all(b"ID3 ")?;
+                    self.writer.write_all(&(tag.len() as u64).to_be_bytes())?;
+                    self.writer.write_all(tag)?;
+                    if tag.len() & 1 != 0 {
+                        self.writer.write_all(&[0])?;
+                    }
+                }
+                let end = self.writer.stream_position()?;
+                self.patch(4, &(end - self.start - 12).to_be_bytes())?;
+                let data_size_at = self.data_start - self.start - 8;
+                self.patch(data_size_at, &data_size.to_be_bytes())?;
+                self.writer.seek(SeekFrom::Start(end))?;
+            }
+        }
+        Ok(self.writer)
+    }
+
+    fn patch(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(self.start + offset))?;
+        self.writer.write_all(bytes)
+    }
+}
+
+// "DSD ", "fmt " and the start of "data", sizes left zero
+fn dsf_header(info: &DsdFileInfo) -> Result<Vec<u8>, DsdFileError> {
+    // Mono, stereo, 3 channels, quad, 5 channels and 5.1
+    const CHANNEL_TYPES: [u32; 6] = [1, 2, 3, 4, 6, 7];
+    let channel_type = *CHANNEL_TYPES
+        .get(info.channels - 1)
+        .ok_or(DsdFileError::Unsupported("DSF holds at most 6 channels"))?;
+
+    let mut header = Vec::with_capacity(92);
+    header.extend_from_slice(b"DSD ");
+    header.extend_from_slice(&28u64.to_le_bytes());
+    header.extend_from_slice(&[0; 16]);
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&52u64.to_le_bytes());
+    for field in [
+        1,
+        0,
+        channel_type,
+        info.channels as u32,
+        info.sample_rate,
+        1,
+    ] {
+        header.extend_from_slice(&field.to_le_bytes());
+    }
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&(DSF_BLOCK as u32).to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&[0; 8]);
+    Ok(header)
+}
+
+// "FRM8", "FVER", "PROP" and the start of "DSD ", sizes of the form and the
+// samples left zero
+fn dff_header(info: &DsdFileInfo) -> Result<Vec<u8>, DsdFileError> {
+    let ids: Vec<[u8; 4]> = match info.channels {
+        2 => vec![*b"SLFT", *b"SRGT"],
+        5 => vec![*b"MLFT", *b"MRGT", *b"C   ", *b"LS  ", *b"RS  "],
+        6 => vec![*b"MLFT", *b"MRGT", *b"C   ", *b"LFE ", *b"LS  ", *b"RS  "],
+        channels if channels <= 1000 => (0..channels)
+            .map(|channel| {
+                let id = format!("C{:03}", channel);
+                id.as_bytes().try_into().unwrap()
+            })
+            .collect(),
+        _ => {
+            return Err(DsdFileError::Unsupported(
+                "DSDIFF holds at most 1000 channels",
+            ))
+        }
+    };
+
+    let chunk = |header: &mut Vec<u8>, id: &[u8; 4], body: &[u8]| {
+        header.extend_from_slice(id);
+        header.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        header.extend_from_slice(body);
+    };
+    let mut prop = b"SND ".to_vec();
+    chunk(&mut prop, b"FS  ", &info.sample_rate.to_be_bytes());
+    let mut chnl = (info.channels as u16).to_be_bytes().to_vec();
+    chnl.extend(ids.concat());
+    chunk(&mut prop, b"CHNL", &chnl);
+    // Compression name as a Pascal string padded to an even length
+    chunk(&mut prop, b"CMPR", b"DSD \x0enot compressed\0");
+
+    let mut header = b"FRM8\0\0\0\0\0\0\0\0DSD ".to_vec();
+    chunk(&mut header, b"FVER", &0x0105_0000u32.to_be_bytes());
+    chunk(&mut header, b"PROP", &prop);
+    chunk(&mut header, b"DSD ", &[]);
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use AsioSampleType::*;
+
+    fn pattern(len: usize, step: usize, offset: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * step + offset) as u8).collect()
+    }
+
+    fn read_all(reader: &mut DsdReader<Cursor<Vec<u8>>>, chunk: usize) -> Vec<Vec<u8>> {
+        let channels = reader.info().channels;
+        let mut out = vec![Vec::new(); channels];
+        loop {
+            let mut buffers = vec![vec![0; chunk]; channels];
+            let mut planes: Vec<&mut [u8]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+            let samples = reader.read(AsioSTDSDInt8MSB1, &mut planes).unwrap();
+            if samples == 0 {
+                return out;
+            }
+            for (out, buffer) in out.iter_mut().zip(&buffers) {
+                out.extend_from_slice(&buffer[..samples / 8]);
+            }
+        }
+    }
+
+    #[test]
+    fn dsf_round_trip_is_bit_exact() {
+        let left = pattern(5000, 7, 0);
+        let right = pattern(5000, 13, 1);
+        let id3 = b"ID3\x04\0\0\0\0\0\0".to_vec();
+        let info = DsdFileInfo::new(DsdContainer::Dsf, DsdRate::Dsd64, 2).with_id3(id3.clone());
+        let mut writer = DsdWriter::create(Cursor::new(Vec::new()), info).unwrap();
+        writer
+            .write(AsioSTDSDInt8MSB1, &[&left[..3000], &right[..3000]])
+            .unwrap();
+        let (mut lsb_left, mut lsb_right) = (vec![0; 2000], vec![0; 2000]);
+        dsd::convert(
+            AsioSTDSDInt8MSB1,
+            &left[3000..],
+            AsioSTDSDInt8LSB1,
+            &mut lsb_left,
+        )
+        .unwrap();
+        dsd::convert(
+            AsioSTDSDInt8MSB1,
+            &right[3000..],
+            AsioSTDSDInt8LSB1,
+            &mut lsb_right,
+        )
+        .unwrap();
+        writer
+            .write(AsioSTDSDInt8LSB1, &[&lsb_left, &lsb_right])
+            .unwrap();
+        let file = writer.finish().unwrap().into_inner();
+
+        let le_u64_at = |at: usize| u64::from_le_bytes(file[at..at + 8].try_into().unwrap());
+        assert_eq!(file.len(), 92 + 4 * DSF_BLOCK + id3.len());
+        assert_eq!(le_u64_at(12), file.len() as u64);
+        assert_eq!(le_u64_at(20), (92 + 4 * DSF_BLOCK) as u64);
+        assert_eq!(le_u64_at(64), 40000);
+        assert_eq!(le_u64_at(84), (12 + 4 * DSF_BLOCK) as u64);
+        // Blocks of each channel in turn, LSB first and zero padded
+        assert_eq!(file[92], left[0].reverse_bits());
+        assert_eq!(file[92 + DSF_BLOCK], right[0].reverse_bits());
+        assert_eq!(file[92 + 2 * DSF_BLOCK], left[DSF_BLOCK].reverse_bits());
+        assert_eq!(file[92 + 3 * DSF_BLOCK - 1], 0);
+
+        let mut reader = DsdReader::open(Cursor::new(file)).unwrap();
+        assert_eq!(
+            reader.info(),
+            &DsdFileInfo {
+                container: DsdContainer::Dsf,
+                sample_rate: 2_822_400,
+                channels: 2,
+                samples: 40000,
+                id3: Some(id3),
+            }
+        );
+        assert_eq!(reader.info().rate(), Some(DsdRate::Dsd64));
+        // Reads straddle the block boundary
+        assert_eq!(read_all(&mut reader, 999), [left, right]);
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn dff_round_trip_is_bit_exact() {
+        let left = pattern(3, 1, 0x10);
+        let right = pattern(3, 1, 0x20);
+        let info =
+            DsdFileInfo::new(DsdContainer::Dff, DsdRate::Dsd128, 2).with_id3(b"ID3xx".to_vec());
+        let mut writer = DsdWriter::create(Cursor::new(Vec::new()), info).unwrap();
+        let mut ner8 = [vec![0; 24], vec![0; 24]];
+        dsd::convert(AsioSTDSDInt8MSB1, &left, AsioSTDSDInt8NER8, &mut ner8[0]).unwrap();
+        dsd::convert(AsioSTDSDInt8MSB1, &right, AsioSTDSDInt8NER8, &mut ner8[1]).unwrap();
+        writer
+            .write(AsioSTDSDInt8NER8, &[&ner8[0], &ner8[1]])
+            .unwrap();
+        let file = writer.finish().unwrap().into_inner();
+
+        let be_u64_at = |at: usize| u64::from_be_bytes(file[at..at + 8].try_into().unwrap());
+        assert_eq!(&file[..4], b"FRM8");
+        assert_eq!(be_u64_at(4), file.len() as u64 - 12);
+        assert_eq!(&file[78..86], b"SLFTSRGT");
+        assert_eq!(&file[98..102], b"DSD ");
+        // Byte interleaved, MSB first
+        assert_eq!(&file[118..122], b"DSD ");
+        assert_eq!(be_u64_at(122), 6);
+        assert_eq!(file[130..136], [0x10, 0x20, 0x11, 0x21, 0x12, 0x22]);
+        // The odd tag is padded
+        assert_eq!(&file[136..140], b"ID3 ");
+        assert_eq!(be_u64_at(140), 5);
+        assert_eq!(file.len(), 154);
+
+        let mut reader = DsdReader::open(Cursor::new(file)).unwrap();
+        assert_eq!(reader.info().sample_rate, 5_644_800);
+        assert_eq!(reader.info().samples, 24);
+        assert_eq!(reader.info().id3.as_deref(), Some(&b"ID3xx"[..]));
+        let mut out = [[0xFF; 16], [0xFF; 16]];
+        let [a, b] = &mut out;
+        assert_eq!(reader.read(AsioSTDSDInt8NER8, &mut [a, b]).unwrap(), 16);
+        assert_eq!(out[0], ner8[0][..16]);
+        assert_eq!(out[1], ner8[1][..16]);
+        assert_eq!(read_all(&mut reader, 2), [&left[2..], &right[2..]]);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let written = |container, id3: &[u8]| {
+            let info = DsdFileInfo::new(container, DsdRate::Dsd64, 1).with_id3(id3.to_vec());
+            let mut writer = DsdWriter::create(Cursor::new(Vec::new()), info).unwrap();
+            writer.write(AsioSTDSDInt8MSB1, &[&[0x69; 2]]).unwrap();
+            writer.finish().unwrap().into_inner()
+        };
+        let open = |file: Vec<u8>| DsdReader::open(Cursor::new(file)).err();
+        let dsf = written(DsdContainer::Dsf, b"");
+        let dff = written(DsdContainer::Dff, b"ID3x");
+        assert!(open(dsf.clone()).is_none());
+        assert!(open(dff.clone()).is_none());
+
+        // Channel count and block size come before any allocation
+        let mut file = dsf.clone();
+        file[52..56].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Unsupported(_))));
+        let mut file = dsf.clone();
+        file[72..76].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Invalid(_))));
+        let mut file = dsf;
+        file[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Invalid(_))));
+
+        // Forms and chunks must fit in the stream, and chunks in their form
+        let mut file = dff.clone();
+        file[4..12].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Invalid(_))));
+        let mut file = dff.clone();
+        file[4..12].copy_from_slice(&(dff.len() as u64 - 11).to_be_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Invalid(_))));
+        let id3 = dff.len() - 4 - 8;
+        assert_eq!(&dff[id3 - 4..id3], b"ID3 ");
+        let mut file = dff;
+        file[id3..id3 + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(open(file), Some(DsdFileError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_stream() {
+        let error = DsdReader::open(Cursor::new(b"RIFF\0\0\0\0WAVE".to_vec())).err();
+        assert!(matches!(error, Some(DsdFileError::Invalid(_))));
+
+        let info = DsdFileInfo::new(DsdContainer::Dff, DsdRate::Dsd64, 1);
+        let mut writer = DsdWriter::create(Cursor::new(Vec::new()), info).unwrap();
+        assert!(matches!(
+            writer.write(AsioSTDSDInt8NER8, &[&[1; 12]]),
+            Err(DsdFileError::Dsd(DsdError::LengthMismatch {
+                samples: 16,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            writer.write(AsioSTDSDInt8MSB1, &[&[0; 2], &[0; 2]]),
+            Err(DsdFileError::ChannelCount {
+                expected: 1,
+                found: 2
+            })
+        ));
+        writer.write(AsioSTDSDInt8MSB1, &[&[0x69; 2]]).unwrap();
+        let mut file = writer.finish().unwrap().into_inner();
+
+        let mut reader = DsdReader::open(Cursor::new(file.clone())).unwrap();
+        assert!(matches!(
+            reader.read(AsioSTInt32LSB, &mut [&mut [0; 8]]),
+            Err(DsdFileError::Dsd(DsdError::Unsupported(AsioSTInt32LSB)))
+        ));
+
+        // DST compression
+        let cmpr = file.windows(4).position(|id| id == b"CMPR").unwrap();
+        file[cmpr + 12..cmpr + 16].copy_from_slice(b"DST ");
+        assert!(matches!(
+            DsdReader::open(Cursor::new(file)).err(),
+            Some(DsdFileError::Unsupported(_))
+        ));
+
+        let info = DsdFileInfo::new(DsdContainer::Dsf, DsdRate::Dsd64, 7);
+        assert!(matches!(
+            DsdWriter::create(Cursor::new(Vec::new()), info).err(),
+            Some(DsdFileError::Unsupported(_))
+        ));
+    }
+}
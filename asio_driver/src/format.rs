@@ -0,0 +1,132 @@
+//! Switching between PCM and DSD streaming.
+//!
+//! Drivers change the format through the `SetIoFormat` future call while
+//! initialized without buffers, see [`crate::InitializedDriver::switch_format`].
+//! The channels' sample types follow the format, DSD channels reporting one
+//! of the `AsioSTDSDInt8*` types.
+
+use crate::{AsioChannelInfo, AsioError, AsioIoFormat, AsioIoFormatType, AsioSampleType};
+
+/// Shorter name for [`AsioIoFormat`], built with [`AsioIoFormat::pcm`] or
+/// [`AsioIoFormat::dsd`].
+pub type IoFormat = AsioIoFormat;
+
+/// Every channel of the driver, as reported by `get_channel_info`.
+#[derive(Clone)]
+pub struct ChannelLayout {
+    pub format_type: AsioIoFormatType,
+    pub inputs: Vec<AsioChannelInfo>,
+    pub outputs: Vec<AsioChannelInfo>,
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> impl Iterator<Item = &AsioChannelInfo> {
+        self.inputs.iter().chain(&self.outputs)
+    }
+    /// Whether every channel carries DSD, as expected after switching to it.
+    pub fn is_dsd(&self) -> bool {
+        self.channels().all(|info| info.sample_type.is_dsd())
+    }
+    /// The distinct sample types, in channel order.
+    pub fn sample_types(&self) -> Vec<AsioSampleType> {
+        let mut types = Vec::new();
+        for info in self.channels() {
+            if !types.contains(&info.sample_type) {
+                types.push(info.sample_type);
+            }
+        }
+        types
+    }
+}
+
+impl std::fmt::Debug for ChannelLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let types = |infos: &[AsioChannelInfo]| -> Vec<AsioSampleType> {
+            infos.iter().map(|info| info.sample_type).collect()
+        };
+        f.debug_struct("ChannelLayout")
+            .field("format_type", &self.format_type)
+            .field("inputs", &types(&self.inputs))
+            .field("outputs", &types(&self.outputs))
+            .finish()
+    }
+}
+
+/// The step a format switch failed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatError {
+    /// `CanDoIoFormat` refused the format, nothing was changed.
+    Unsupported(AsioIoFormatType, AsioError),
+    Set(AsioError),
+    /// The format changed but the channels could not be read back.
+    ChannelInfo(AsioError),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Unsupported(format_type, error) => {
+                write!(f, "the driver cannot stream {:?}: {}", format_type, error)
+            }
+            FormatError::Set(error) => write!(f, "setting the format failed: {}", error),
+            FormatError::ChannelInfo(error) => {
+                write!(f, "reading the new channel layout failed: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized, MockCall, MockConfig, MockDriver};
+
+    #[test]
+    fn switching_to_dsd_changes_sample_types() {
+        let mock = MockDriver::new(MockConfig {
+            io_formats: vec![AsioIoFormatType::PCM, AsioIoFormatType::DSD],
+            ..MockConfig::default()
+        });
+        let driver = initialized(&mock);
+        assert_eq!(driver.get_io_format(), Ok(AsioIoFormatType::PCM));
+
+        let layout = driver.switch_format(AsioIoFormatType::DSD).unwrap();
+        assert_eq!(layout.format_type, AsioIoFormatType::DSD);
+        assert_eq!((layout.inputs.len(), layout.outputs.len()), (2, 2));
+        assert!(layout.is_dsd());
+        assert_eq!(layout.sample_types(), [AsioSampleType::AsioSTDSDInt8MSB1]);
+        assert_eq!(driver.get_io_format(), Ok(AsioIoFormatType::DSD));
+
+        let layout = driver.switch_format(AsioIoFormatType::PCM).unwrap();
+        assert!(!layout.is_dsd());
+        assert_eq!(layout.sample_types(), [AsioSampleType::AsioSTInt32LSB]);
+    }
+
+    #[test]
+    fn failed_switches_report_their_step() {
+        let mock = MockDriver::new(MockConfig::default());
+        let driver = initialized(&mock);
+        assert_eq!(
+            driver.switch_format(AsioIoFormatType::DSD).unwrap_err(),
+            FormatError::Unsupported(AsioIoFormatType::DSD, AsioError::NotPresent)
+        );
+        mock.fail_once(MockCall::SetIoFormat, AsioError::HwMalfunction);
+        assert_eq!(
+            driver.switch_format(AsioIoFormatType::PCM).unwrap_err(),
+            FormatError::Set(AsioError::HwMalfunction)
+        );
+        assert_eq!(driver.get_io_format(), Ok(AsioIoFormatType::PCM));
+    }
+
+    #[test]
+    fn io_format_constructors() {
+        assert_eq!(IoFormat::pcm().format_type, AsioIoFormatType::PCM);
+        assert_eq!(IoFormat::dsd().format_type, AsioIoFormatType::DSD);
+        assert_eq!(
+            format!("{:?}", IoFormat::dsd()),
+            "AsioIoFormat { format_type: DSD, .. }"
+        );
+    }
+}
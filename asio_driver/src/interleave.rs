@@ -0,0 +1,736 @@
+//! Conversion between interleaved `f32` frames and planar ASIO buffers.
+//!
+//! Every routine converts each channel straight into (or out of) its planar
+//! buffer while walking the interleaved frames. On x86_64 the little-endian
+//! integer formats and `AsioSTFloat32LSB` use SSE2, or AVX2 when the CPU
+//! supports it; other formats and targets fall back to [`crate::convert`].
+//! All paths produce identical bytes as long as the floating point unit is in
+//! its default round-to-nearest mode.
+
+use crate::convert::{self, ClipPolicy, ConvertError, Layout};
+use crate::AsioSampleType;
+
+/// Instruction set used for the conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+impl SimdLevel {
+    /// Best level supported by the running CPU.
+    pub fn detect() -> SimdLevel {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                SimdLevel::Avx2
+            } else {
+                SimdLevel::Sse2
+            }
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        SimdLevel::Scalar
+    }
+}
+
+// Formats with a vectorized path
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+enum Fast {
+    Int { bits: u32, width: usize },
+    Float32,
+}
+
+impl Fast {
+    fn of(sample_type: AsioSampleType) -> Option<Fast> {
+        use AsioSampleType::*;
+        if cfg!(target_endian = "big") {
+            return None;
+        }
+        Some(match sample_type {
+            AsioSTInt16LSB => Fast::Int { bits: 16, width: 2 },
+            AsioSTInt24LSB => Fast::Int { bits: 24, width: 3 },
+            AsioSTInt32LSB => Fast::Int { bits: 32, width: 4 },
+            AsioSTInt32LSB16 => Fast::Int { bits: 16, width: 4 },
+            AsioSTInt32LSB18 => Fast::Int { bits: 18, width: 4 },
+            AsioSTInt32LSB20 => Fast::Int { bits: 20, width: 4 },
+            AsioSTInt32LSB24 => Fast::Int { bits: 24, width: 4 },
+            AsioSTFloat32LSB => Fast::Float32,
+            _ => return None,
+        })
+    }
+}
+
+// Lets the `f32` helpers share the byte based code without allocating
+trait Plane {
+    fn bytes(&self) -> &[u8];
+}
+
+trait PlaneMut {
+    fn bytes_mut(&mut self) -> &mut [u8];
+}
+
+impl Plane for &[u8] {
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Plane for &[f32] {
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.as_ptr() as *const u8, self.len() * 4) }
+    }
+}
+
+impl PlaneMut for &mut [u8] {
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl PlaneMut for &mut [f32] {
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.as_mut_ptr() as *mut u8, self.len() * 4) }
+    }
+}
+
+#[cfg(target_endian = "little")]
+const NATIVE_F32: AsioSampleType = AsioSampleType::AsioSTFloat32LSB;
+#[cfg(target_endian = "big")]
+const NATIVE_F32: AsioSampleType = AsioSampleType::AsioSTFloat32MSB;
+
+fn frames(samples: usize, channels: usize) -> Result<usize, ConvertError> {
+    match channels {
+        0 if samples == 0 => Ok(0),
+        0 => Err(ConvertError::FrameMismatch { samples, channels }),
+        _ if !samples.is_multiple_of(channels) => {
+            Err(ConvertError::FrameMismatch { samples, channels })
+        }
+        _ => Ok(samples / channels),
+    }
+}
+
+fn check_planes(
+    lens: impl Iterator<Item = usize>,
+    frames: usize,
+    sample_type: AsioSampleType,
+) -> Result<(), ConvertError> {
+    for bytes in lens {
+        if bytes != frames * sample_type.bytes_per_sample() {
+            return Err(ConvertError::LengthMismatch {
+                samples: frames,
+                bytes,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Splits interleaved frames into one buffer per channel, encoding every
+/// sample as `sample_type`.
+pub fn deinterleave_encode(
+    src: &[f32],
+    dst: &mut [&mut [u8]],
+    sample_type: AsioSampleType,
+    clip: ClipPolicy,
+) -> Result<(), ConvertError> {
+    deinterleave_encode_with(SimdLevel::detect(), src, dst, sample_type, clip)
+}
+
+/// [`deinterleave_encode`] limited to `level`, levels the CPU lacks fall back
+/// to the best supported one.
+pub fn deinterleave_encode_with(
+    level: SimdLevel,
+    src: &[f32],
+    dst: &mut [&mut [u8]],
+    sample_type: AsioSampleType,
+    clip: ClipPolicy,
+) -> Result<(), ConvertError> {
+    encode_planes(level, src, dst, sample_type, clip)
+}
+
+/// Decodes one buffer per channel into interleaved frames.
+pub fn decode_interleave(
+    src: &[&[u8]],
+    sample_type: AsioSampleType,
+    dst: &mut [f32],
+) -> Result<(), ConvertError> {
+    decode_interleave_with(SimdLevel::detect(), src, sample_type, dst)
+}
+
+/// [`decode_interleave`] limited to `level`, levels the CPU lacks fall back
+/// to the best supported one.
+pub fn decode_interleave_with(
+    level: SimdLevel,
+    src: &[&[u8]],
+    sample_type: AsioSampleType,
+    dst: &mut [f32],
+) -> Result<(), ConvertError> {
+    decode_planes(level, src, sample_type, dst)
+}
+
+/// Splits interleaved frames into one `f32` buffer per channel.
+pub fn deinterleave(src: &[f32], dst: &mut [&mut [f32]]) -> Result<(), ConvertError> {
+    encode_planes(
+        SimdLevel::detect(),
+        src,
+        dst,
+        NATIVE_F32,
+        ClipPolicy::Saturate,
+    )
+}
+
+/// Interleaves one `f32` buffer per channel into frames.
+pub fn interleave(src: &[&[f32]], dst: &mut [f32]) -> Result<(), ConvertError> {
+    decode_planes(SimdLevel::detect(), src, NATIVE_F32, dst)
+}
+
+fn encode_planes<P: PlaneMut>(
+    level: SimdLevel,
+    src: &[f32],
+    dst: &mut [P],
+    sample_type: AsioSampleType,
+    clip: ClipPolicy,
+) -> Result<(), ConvertError> {
+    let layout = convert::layout(sample_type)?;
+    let frames = frames(src.len(), dst.len())?;
+    check_planes(
+        dst.iter_mut().map(|plane| plane.bytes_mut().len()),
+        frames,
+        sample_type,
+    )?;
+    if frames == 0 {
+        return Ok(());
+    }
+    if clip == ClipPolicy::Reject && matches!(layout, Layout::Int { .. }) {
+        if let Some(index) = src.iter().position(|s| !(-1.0..=1.0).contains(s)) {
+            return Err(ConvertError::Clipped { index });
+        }
+    }
+    let done = match (level.min(SimdLevel::detect()), Fast::of(sample_type)) {
+        // Safety: the lengths of all planes were checked above
+        #[cfg(target_arch = "x86_64")]
+        (SimdLevel::Avx2, Some(fast)) => unsafe { x86::encode_avx2(src, dst, fast) },
+        #[cfg(target_arch = "x86_64")]
+        (SimdLevel::Sse2, Some(fast)) => unsafe { x86::encode_sse2(src, dst, fast) },
+        _ => 0,
+    };
+    encode_scalar(src, dst, done, layout, sample_type);
+    Ok(())
+}
+
+fn encode_scalar<P: PlaneMut>(
+    src: &[f32],
+    dst: &mut [P],
+    first_frame: usize,
+    layout: Layout,
+    sample_type: AsioSampleType,
+) {
+    let width = sample_type.bytes_per_sample();
+    let big_endian = sample_type.is_big_endian();
+    for (frame, samples) in src.chunks_exact(dst.len()).enumerate().skip(first_frame) {
+        for (plane, sample) in dst.iter_mut().zip(samples) {
+            let bytes = &mut plane.bytes_mut()[frame * width..(frame + 1) * width];
+            let _ = convert::write_f64(
+                *sample as f64,
+                bytes,
+                layout,
+                big_endian,
+                ClipPolicy::Saturate,
+            );
+        }
+    }
+}
+
+fn decode_planes<P: Plane>(
+    level: SimdLevel,
+    src: &[P],
+    sample_type: AsioSampleType,
+    dst: &mut [f32],
+) -> Result<(), ConvertError> {
+    let layout = convert::layout(sample_type)?;
+    let frames = frames(dst.len(), src.len())?;
+    check_planes(
+        src.iter().map(|plane| plane.bytes().len()),
+        frames,
+        sample_type,
+    )?;
+    if frames == 0 {
+        return Ok(());
+    }
+    // Decoding is load bound, AVX2 gains nothing over SSE2 here
+    let done = match (level.min(SimdLevel::detect()), Fast::of(sample_type)) {
+        // Safety: the lengths of all planes were checked above
+        #[cfg(target_arch = "x86_64")]
+        (SimdLevel::Sse2 | SimdLevel::Avx2, Some(fast)) => unsafe {
+            x86::decode_sse2(src, dst, fast)
+        },
+        _ => 0,
+    };
+    decode_scalar(src, dst, done, layout, sample_type);
+    Ok(())
+}
+
+fn decode_scalar<P: Plane>(
+    src: &[P],
+    dst: &mut [f32],
+    first_frame: usize,
+    layout: Layout,
+    sample_type: AsioSampleType,
+) {
+    let width = sample_type.bytes_per_sample();
+    let big_endian = sample_type.is_big_endian();
+    for (frame, samples) in dst
+        .chunks_exact_mut(src.len())
+        .enumerate()
+        .skip(first_frame)
+    {
+        for (plane, sample) in src.iter().zip(samples) {
+            let bytes = &plane.bytes()[frame * width..(frame + 1) * width];
+            *sample = convert::read_f64(bytes, layout, big_endian) as f32;
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    //! The functions return how many leading frames they converted, the
+    //! caller finishes the rest with the scalar code. Planes must hold exactly
+    //! `src.len() / dst.len()` samples.
+
+    use std::arch::x86_64::*;
+
+    use super::{Fast, Plane, PlaneMut};
+
+    // For 32 bits `max` rounds up to 2^31, `quantize` turns that into i32::MAX
+    fn limits(fast: Fast) -> (f32, f32, usize) {
+        match fast {
+            Fast::Int { bits, width } => {
+                let scale = (1u64 << (bits - 1)) as f32;
+                (scale, scale - 1.0, width)
+            }
+            Fast::Float32 => (1.0, 1.0, 4),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn quantize_sse2(x: __m128, scale: __m128, max: __m128) -> __m128i {
+        // NaN becomes silence, then clamp and round like `convert::write_f64`
+        let x = _mm_and_ps(x, _mm_cmpord_ps(x, x));
+        let y = _mm_mul_ps(x, scale);
+        let y = _mm_min_ps(_mm_max_ps(y, _mm_sub_ps(_mm_setzero_ps(), scale)), max);
+        let overflow = _mm_castps_si128(_mm_cmpge_ps(y, scale));
+        _mm_xor_si128(_mm_cvtps_epi32(y), overflow)
+    }
+
+    #[inline(always)]
+    unsafe fn store_sse2(x: __m128, fast: Fast, scale: __m128, max: __m128, out: *mut u8) {
+        match fast {
+            Fast::Float32 => _mm_storeu_ps(out as *mut f32, x),
+            Fast::Int { width: 4, .. } => {
+                _mm_storeu_si128(out as *mut __m128i, quantize_sse2(x, scale, max))
+            }
+            Fast::Int { width: 2, .. } => {
+                let q = quantize_sse2(x, scale, max);
+                _mm_storel_epi64(out as *mut __m128i, _mm_packs_epi32(q, q))
+            }
+            Fast::Int { .. } => {
+                let mut lanes = [0i32; 4];
+                _mm_storeu_si128(
+                    lanes.as_mut_ptr() as *mut __m128i,
+                    quantize_sse2(x, scale, max),
+                );
+                store_packed24(&lanes, out);
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn store_packed24(lanes: &[i32], out: *mut u8) {
+        for (index, lane) in lanes.iter().enumerate() {
+            std::ptr::copy_nonoverlapping(lane.to_le_bytes().as_ptr(), out.add(index * 3), 3);
+        }
+    }
+
+    pub(super) unsafe fn encode_sse2<P: PlaneMut>(src: &[f32], dst: &mut [P], fast: Fast) -> usize {
+        let channels = dst.len();
+        let blocks = src.len() / channels / 4;
+        let (scale, max, width) = limits(fast);
+        let (scale, max) = (_mm_set1_ps(scale), _mm_set1_ps(max));
+        let src = src.as_ptr();
+        if let [left, right] = dst {
+            let left = left.bytes_mut().as_mut_ptr();
+            let right = right.bytes_mut().as_mut_ptr();
+            for block in 0..blocks {
+                let a = _mm_loadu_ps(src.add(block * 8));
+                let b = _mm_loadu_ps(src.add(block * 8 + 4));
+                let l = _mm_shuffle_ps::<0b10_00_10_00>(a, b);
+                let r = _mm_shuffle_ps::<0b11_01_11_01>(a, b);
+                store_sse2(l, fast, scale, max, left.add(block * 4 * width));
+                store_sse2(r, fast, scale, max, right.add(block * 4 * width));
+            }
+        } else {
+            for block in 0..blocks {
+                let frame = src.add(block * 4 * channels);
+                for (channel, plane) in dst.iter_mut().enumerate() {
+                    let base = frame.add(channel);
+                    let x = _mm_setr_ps(
+                        *base,
+                        *base.add(channels),
+                        *base.add(2 * channels),
+                        *base.add(3 * channels),
+                    );
+                    let out = plane.bytes_mut().as_mut_ptr().add(block * 4 * width);
+                    store_sse2(x, fast, scale, max, out);
+                }
+            }
+        }
+        blocks * 4
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn quantize_avx2(x: __m256, scale: __m256, max: __m256) -> __m256i {
+        let x = _mm256_and_ps(x, _mm256_cmp_ps::<_CMP_ORD_Q>(x, x));
+        let y = _mm256_mul_ps(x, scale);
+        let y = _mm256_min_ps(
+            _mm256_max_ps(y, _mm256_sub_ps(_mm256_setzero_ps(), scale)),
+            max,
+        );
+        let overflow = _mm256_castps_si256(_mm256_cmp_ps::<_CMP_GE_OQ>(y, scale));
+        _mm256_xor_si256(_mm256_cvtps_epi32(y), overflow)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn store_avx2(x: __m256, fast: Fast, scale: __m256, max: __m256, out: *mut u8) {
+        match fast {
+            Fast::Float32 => _mm256_storeu_ps(out as *mut f32, x),
+            Fast::Int { width: 4, .. } => {
+                _mm256_storeu_si256(out as *mut __m256i, quantize_avx2(x, scale, max))
+            }
+            Fast::Int { width: 2, .. } => {
+                let q = quantize_avx2(x, scale, max);
+                // The pack works per 128 bit lane, gather both halves again
+                let packed = _mm256_permute4x64_epi64::<0b00_00_10_00>(_mm256_packs_epi32(q, q));
+                _mm_storeu_si128(out as *mut __m128i, _mm256_castsi256_si128(packed))
+            }
+            Fast::Int { .. } => {
+                let mut lanes = [0i32; 8];
+                _mm256_storeu_si256(
+                    lanes.as_mut_ptr() as *mut __m256i,
+                    quantize_avx2(x, scale, max),
+                );
+                store_packed24(&lanes, out);
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn encode_avx2<P: PlaneMut>(src: &[f32], dst: &mut [P], fast: Fast) -> usize {
+        let channels = dst.len();
+        let blocks = src.len() / channels / 8;
+        let (scale, max, width) = limits(fast);
+        let (scale, max) = (_mm256_set1_ps(scale), _mm256_set1_ps(max));
+        let src = src.as_ptr();
+        if let [left, right] = dst {
+            let left = left.bytes_mut().as_mut_ptr();
+            let right = right.bytes_mut().as_mut_ptr();
+            for block in 0..blocks {
+                let a = _mm256_loadu_ps(src.add(block * 16));
+                let b = _mm256_loadu_ps(src.add(block * 16 + 8));
+                // Shuffles stay within 128 bit lanes, the permute restores frame order
+                let l = _mm256_shuffle_ps::<0b10_00_10_00>(a, b);
+                let r = _mm256_shuffle_ps::<0b11_01_11_01>(a, b);
+                let l =
+                    _mm256_castpd_ps(_mm256_permute4x64_pd::<0b11_01_10_00>(_mm256_castps_pd(l)));
+                let r =
+                    _mm256_castpd_ps(_mm256_permute4x64_pd::<0b11_01_10_00>(_mm256_castps_pd(r)));
+                store_avx2(l, fast, scale, max, left.add(block * 8 * width));
+                store_avx2(r, fast, scale, max, right.add(block * 8 * width));
+            }
+        } else {
+            let stride = _mm256_set1_epi32(channels as i32);
+            let offsets = _mm256_mullo_epi32(_mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7), stride);
+            for block in 0..blocks {
+                let frame = src.add(block * 8 * channels);
+                for (channel, plane) in dst.iter_mut().enumerate() {
+                    let x = _mm256_i32gather_ps::<4>(frame.add(channel), offsets);
+                    let out = plane.bytes_mut().as_mut_ptr().add(block * 8 * width);
+                    store_avx2(x, fast, scale, max, out);
+                }
+            }
+        }
+        blocks * 8
+    }
+
+    #[inline(always)]
+    unsafe fn load_sse2(fast: Fast, inv_scale: __m128, shift: __m128i, ptr: *const u8) -> __m128 {
+        let ints = match fast {
+            Fast::Float32 => return _mm_loadu_ps(ptr as *const f32),
+            Fast::Int { width: 4, .. } => {
+                // Sign-extend from the format's width, ignoring the padding bits
+                let v = _mm_loadu_si128(ptr as *const __m128i);
+                _mm_sra_epi32(_mm_sll_epi32(v, shift), shift)
+            }
+            Fast::Int { width: 2, .. } => {
+                let v = _mm_loadl_epi64(ptr as *const __m128i);
+                _mm_srai_epi32::<16>(_mm_unpacklo_epi16(_mm_setzero_si128(), v))
+            }
+            Fast::Int { .. } => {
+                let mut lanes = [0i32; 4];
+                for (index, lane) in lanes.iter_mut().enumerate() {
+                    let mut bytes = [0u8; 4];
+                    std::ptr::copy_nonoverlapping(ptr.add(index * 3), bytes.as_mut_ptr().add(1), 3);
+                    *lane = i32::from_le_bytes(bytes) >> 8;
+                }
+                _mm_loadu_si128(lanes.as_ptr() as *const __m128i)
+            }
+        };
+        _mm_mul_ps(_mm_cvtepi32_ps(ints), inv_scale)
+    }
+
+    pub(super) unsafe fn decode_sse2<P: Plane>(src: &[P], dst: &mut [f32], fast: Fast) -> usize {
+        let channels = src.len();
+        let blocks = dst.len() / channels / 4;
+        let (scale, _, width) = limits(fast);
+        let inv_scale = _mm_set1_ps(1.0 / scale);
+        let shift = match fast {
+            Fast::Int { bits, .. } => _mm_cvtsi32_si128(32 - bits as i32),
+            Fast::Float32 => _mm_setzero_si128(),
+        };
+        let dst = dst.as_mut_ptr();
+        if let [left, right] = src {
+            let left = left.bytes().as_ptr();
+            let right = right.bytes().as_ptr();
+            for block in 0..blocks {
+                let l = load_sse2(fast, inv_scale, shift, left.add(block * 4 * width));
+                let r = load_sse2(fast, inv_scale, shift, right.add(block * 4 * width));
+                _mm_storeu_ps(dst.add(block * 8), _mm_unpacklo_ps(l, r));
+                _mm_storeu_ps(dst.add(block * 8 + 4), _mm_unpackhi_ps(l, r));
+            }
+        } else {
+            for block in 0..blocks {
+                let frame = dst.add(block * 4 * channels);
+                for (channel, plane) in src.iter().enumerate() {
+                    let ptr = plane.bytes().as_ptr().add(block * 4 * width);
+                    let mut lanes = [0f32; 4];
+                    _mm_storeu_ps(lanes.as_mut_ptr(), load_sse2(fast, inv_scale, shift, ptr));
+                    for (index, lane) in lanes.iter().enumerate() {
+                        *frame.add(index * channels + channel) = *lane;
+                    }
+                }
+            }
+        }
+        blocks * 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AsioSampleType::*;
+
+    const LEVELS: [SimdLevel; 3] = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2];
+
+    fn pcm_types() -> impl Iterator<Item = AsioSampleType> {
+        AsioSampleType::ALL.into_iter().filter(|t| !t.is_dsd())
+    }
+
+    fn noise(len: usize, seed: u64) -> impl Iterator<Item = u64> {
+        let mut state = seed | 1;
+        (0..len).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+    }
+
+    // Random samples mixed with edge cases such as rounding ties and NaN
+    fn samples(len: usize) -> Vec<f32> {
+        let special = [
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            1.5,
+            -1.5,
+            f32::NAN,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            0.5 + f32::EPSILON / 2.0,
+            -0.5 - f32::EPSILON / 2.0,
+            0.999_999_9,
+            1.0e-30,
+        ];
+        noise(len, 7)
+            .enumerate()
+            .map(|(index, value)| match special.get(index % 29) {
+                Some(sample) => *sample,
+                None => (value >> 40) as f32 / (1u64 << 23) as f32 - 1.0,
+            })
+            .collect()
+    }
+
+    fn planes(channels: usize, len: usize) -> Vec<Vec<u8>> {
+        vec![vec![0u8; len]; channels]
+    }
+
+    #[test]
+    fn encode_paths_agree_for_every_format() {
+        for sample_type in pcm_types() {
+            for channels in [1, 2, 3, 8] {
+                let frames = 37;
+                let src = samples(frames * channels);
+                let len = frames * sample_type.bytes_per_sample();
+                let mut expected = planes(channels, len);
+                for (channel, plane) in expected.iter_mut().enumerate() {
+                    let channel: Vec<f32> = src
+                        .iter()
+                        .skip(channel)
+                        .step_by(channels)
+                        .copied()
+                        .collect();
+                    convert::encode_f32(sample_type, &channel, plane, ClipPolicy::Saturate)
+                        .unwrap();
+                }
+                for level in LEVELS {
+                    let mut dst = planes(channels, len);
+                    let mut views: Vec<&mut [u8]> = dst.iter_mut().map(|p| &mut p[..]).collect();
+                    deinterleave_encode_with(
+                        level,
+                        &src,
+                        &mut views,
+                        sample_type,
+                        ClipPolicy::Saturate,
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        dst, expected,
+                        "{:?} {} channels {:?}",
+                        sample_type, channels, level
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_paths_agree_for_every_format() {
+        for sample_type in pcm_types() {
+            for channels in [1, 2, 5] {
+                let frames = 29;
+                let width = sample_type.bytes_per_sample();
+                // Random bits, including the padding of the 32xx16..24 formats
+                let mut src = planes(channels, frames * width);
+                for (plane, seed) in src.iter_mut().zip(1..) {
+                    for (byte, value) in plane.iter_mut().zip(noise(frames * width, seed)) {
+                        *byte = (value >> 32) as u8;
+                    }
+                }
+                if sample_type.is_float() {
+                    // Keep the floats finite so the comparison is meaningful
+                    for plane in &mut src {
+                        for sample in plane.chunks_exact_mut(width) {
+                            let index = if sample_type.is_big_endian() {
+                                0
+                            } else {
+                                width - 1
+                            };
+                            sample[index] &= 0x3f;
+                        }
+                    }
+                }
+                let mut expected = vec![0f32; frames * channels];
+                for (channel, plane) in src.iter().enumerate() {
+                    let mut decoded = vec![0f32; frames];
+                    convert::decode_f32(sample_type, plane, &mut decoded).unwrap();
+                    for (frame, sample) in decoded.into_iter().enumerate() {
+                        expected[frame * channels + channel] = sample;
+                    }
+                }
+                let views: Vec<&[u8]> = src.iter().map(|p| &p[..]).collect();
+                for level in LEVELS {
+                    let mut dst = vec![0f32; frames * channels];
+                    decode_interleave_with(level, &views, sample_type, &mut dst).unwrap();
+                    assert_eq!(
+                        dst, expected,
+                        "{:?} {} channels {:?}",
+                        sample_type, channels, level
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stereo_int24_layout() {
+        let src = [0.5, -0.5, 0.25, 1.0];
+        let mut left = [0u8; 6];
+        let mut right = [0u8; 6];
+        deinterleave_encode(
+            &src,
+            &mut [&mut left, &mut right],
+            AsioSTInt24LSB,
+            ClipPolicy::Saturate,
+        )
+        .unwrap();
+        assert_eq!(left, [0x00, 0x00, 0x40, 0x00, 0x00, 0x20]);
+        assert_eq!(right, [0x00, 0x00, 0xc0, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn f32_planes_round_trip() {
+        for channels in [1, 2, 4, 6] {
+            let src: Vec<f32> = (0..channels * 41).map(|i| i as f32).collect();
+            let mut planes = vec![vec![0f32; 41]; channels];
+            let mut views: Vec<&mut [f32]> = planes.iter_mut().map(|p| &mut p[..]).collect();
+            deinterleave(&src, &mut views).unwrap();
+            for (channel, plane) in planes.iter().enumerate() {
+                assert_eq!(plane[3], (3 * channels + channel) as f32);
+            }
+            let views: Vec<&[f32]> = planes.iter().map(|p| &p[..]).collect();
+            let mut dst = vec![0f32; src.len()];
+            interleave(&views, &mut dst).unwrap();
+            assert_eq!(dst, src);
+        }
+    }
+
+    #[test]
+    fn reject_and_length_errors() {
+        let mut left = [0u8; 8];
+        let mut right = [0u8; 8];
+        assert_eq!(
+            deinterleave_encode(
+                &[0.0, 0.5, 0.25, 1.0, 0.0, -2.0, 0.0, 0.0],
+                &mut [&mut left, &mut right],
+                AsioSTInt16LSB,
+                ClipPolicy::Reject,
+            ),
+            Err(ConvertError::Clipped { index: 5 })
+        );
+        assert_eq!(
+            deinterleave_encode(
+                &[0.0; 7],
+                &mut [&mut left, &mut right],
+                AsioSTInt16LSB,
+                ClipPolicy::Saturate,
+            ),
+            Err(ConvertError::FrameMismatch {
+                samples: 7,
+                channels: 2
+            })
+        );
+        assert_eq!(
+            decode_interleave(&[&left, &right[..6]], AsioSTInt16LSB, &mut [0.0; 8]),
+            Err(ConvertError::LengthMismatch {
+                samples: 4,
+                bytes: 6
+            })
+        );
+        assert_eq!(
+            decode_interleave(&[&left], AsioSTDSDInt8MSB1, &mut [0.0; 8]),
+            Err(ConvertError::Unsupported(AsioSTDSDInt8MSB1))
+        );
+    }
+}
@@ -1,9 +1,126 @@
+#![allow(clippy::missing_safety_doc)]
+
+use std::time::Duration;
+
 use bitflags::bitflags;
 use windows::core::IntoParam;
+
+pub mod buffers;
+pub mod callbacks;
+pub mod controls;
+pub mod convert;
+pub mod driver;
+pub mod dsd;
+pub mod dsdfile;
+pub mod format;
+pub mod interleave;
+pub mod lifecycle;
+pub mod messages;
+pub mod mock;
+pub mod monitor;
+pub mod profiler;
+pub mod registration;
+pub mod registry;
+mod ring;
+pub mod server;
+pub mod supervisor;
+pub mod timing;
+pub mod transport;
+pub mod xrun;
+
+pub use buffers::{BufferSet, ChannelBuffer};
+pub use callbacks::{AsioHandler, CallbackSlot, SlotBusy};
+pub use controls::{ControlError, Direction, Gain, MeterLevel, MeterReadings, MeterService};
+pub use driver::{BufferSizeInfo, ChannelCounts, Driver, InitError, Latencies, SamplePosition};
+pub use dsd::{DopDecoder, DopEncoder, DsdError, DsdRate};
+pub use dsdfile::{DsdContainer, DsdFileError, DsdFileInfo, DsdReader, DsdWriter};
+pub use format::{ChannelLayout, FormatError, IoFormat};
+pub use lifecycle::{
+    InitializedDriver, LoadedDriver, PreparedDriver, RunningDriver, TransitionError,
+};
+pub use messages::{
+    event_queue, EventReceiver, EventSender, HostCapabilities, HostEvent, HostMessage, HostMessages,
+};
+pub use monitor::{
+    ChannelSelector, InputMonitorMatrix, InputMonitorRoute, MonitorError, RouteFailure,
+};
+pub use profiler::{
+    buffer_period, load_meter, CallbackProfiler, LoadMeter, LoadStats, ProfiledHandler,
+};
+pub use registration::{DriverRegistration, RegistrationError};
+pub use registry::{drivers, DriverDescriptor, OpenError};
+pub use server::{AsioDriverImpl, DriverObject, HostCallbacks};
+pub use supervisor::{ResetError, ResetSupervisor, StreamFormat, SupervisorEvent};
+pub use timing::{StreamClock, TimingSnapshot};
+pub use transport::{TrackMask, TransportCommand, TransportError};
+pub use xrun::{xrun_detector, XrunEvent, XrunHandler, XrunMonitor, XrunStats};
+
 pub type GUID = windows::core::GUID;
 
-pub type AsioSamples = i64;
-pub type AsioTimestamp = i64;
+/// Sample count as the SDK passes it, split into 32-bit halves.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct AsioSamples {
+    pub hi: u32,
+    pub lo: u32,
+}
+
+impl AsioSamples {
+    pub const fn from_u64(value: u64) -> Self {
+        AsioSamples {
+            hi: (value >> 32) as u32,
+            lo: value as u32,
+        }
+    }
+    pub const fn to_u64(self) -> u64 {
+        (self.hi as u64) << 32 | self.lo as u64
+    }
+}
+
+impl From<u64> for AsioSamples {
+    fn from(value: u64) -> Self {
+        AsioSamples::from_u64(value)
+    }
+}
+
+impl From<AsioSamples> for u64 {
+    fn from(value: AsioSamples) -> Self {
+        value.to_u64()
+    }
+}
+
+/// System time in nanoseconds as the SDK passes it, split into 32-bit halves.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct AsioTimestamp {
+    pub hi: u32,
+    pub lo: u32,
+}
+
+impl AsioTimestamp {
+    pub const fn from_u64(value: u64) -> Self {
+        AsioTimestamp {
+            hi: (value >> 32) as u32,
+            lo: value as u32,
+        }
+    }
+    pub const fn to_u64(self) -> u64 {
+        (self.hi as u64) << 32 | self.lo as u64
+    }
+}
+
+impl From<u64> for AsioTimestamp {
+    fn from(value: u64) -> Self {
+        AsioTimestamp::from_u64(value)
+    }
+}
+
+impl From<AsioTimestamp> for u64 {
+    fn from(value: AsioTimestamp) -> Self {
+        value.to_u64()
+    }
+}
+
 pub type AsioSampleRate = f64;
 
 #[repr(C)]
@@ -25,19 +142,25 @@ impl AsioName {
         self.inner.as_mut_ptr()
     }
     pub unsafe fn to_str(&self) -> Result<&str, std::str::Utf8Error> {
-        return std::ffi::CStr::from_ptr(self.inner.as_ptr()).to_str();
+        std::ffi::CStr::from_ptr(self.inner.as_ptr()).to_str()
     }
 }
 
 impl From<[std::ffi::c_char; 32]> for AsioName {
     fn from(value: [std::ffi::c_char; 32]) -> Self {
-        return AsioName { inner: value };
+        AsioName { inner: value }
     }
 }
 
-impl Into<[std::ffi::c_char; 32]> for AsioName {
-    fn into(self) -> [std::ffi::c_char; 32] {
-        return self.inner;
+impl From<AsioName> for [std::ffi::c_char; 32] {
+    fn from(value: AsioName) -> Self {
+        value.inner
+    }
+}
+
+impl Default for AsioName {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -60,19 +183,25 @@ impl AsioErrorMsg {
         self.inner.as_mut_ptr()
     }
     pub unsafe fn to_str(&self) -> Result<&str, std::str::Utf8Error> {
-        return std::ffi::CStr::from_ptr(self.inner.as_ptr()).to_str();
+        std::ffi::CStr::from_ptr(self.inner.as_ptr()).to_str()
     }
 }
 
 impl From<[std::ffi::c_char; 124]> for AsioErrorMsg {
     fn from(value: [std::ffi::c_char; 124]) -> Self {
-        return AsioErrorMsg { inner: value };
+        AsioErrorMsg { inner: value }
+    }
+}
+
+impl From<AsioErrorMsg> for [std::ffi::c_char; 124] {
+    fn from(value: AsioErrorMsg) -> Self {
+        value.inner
     }
 }
 
-impl Into<[std::ffi::c_char; 124]> for AsioErrorMsg {
-    fn into(self) -> [std::ffi::c_char; 124] {
-        return self.inner;
+impl Default for AsioErrorMsg {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -99,6 +228,12 @@ impl AsioClockSources {
     }
 }
 
+impl Default for AsioClockSources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(i32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AsioBool {
@@ -112,9 +247,9 @@ impl AsioBool {
     }
 }
 
-impl Into<bool> for AsioBool {
-    fn into(self) -> bool {
-        self == AsioBool::True
+impl From<AsioBool> for bool {
+    fn from(value: AsioBool) -> Self {
+        value == AsioBool::True
     }
 }
 
@@ -163,6 +298,60 @@ pub enum AsioSampleType {
     AsioSTDSDInt8NER8 = 40, // DSD 8 bit data, 1 sample per byte. No Endianness required.
 }
 
+impl AsioSampleType {
+    pub const ALL: [AsioSampleType; 21] = [
+        AsioSampleType::AsioSTInt16MSB,
+        AsioSampleType::AsioSTInt24MSB,
+        AsioSampleType::AsioSTInt32MSB,
+        AsioSampleType::AsioSTFloat32MSB,
+        AsioSampleType::AsioSTFloat64MSB,
+        AsioSampleType::AsioSTInt32MSB16,
+        AsioSampleType::AsioSTInt32MSB18,
+        AsioSampleType::AsioSTInt32MSB20,
+        AsioSampleType::AsioSTInt32MSB24,
+        AsioSampleType::AsioSTInt16LSB,
+        AsioSampleType::AsioSTInt24LSB,
+        AsioSampleType::AsioSTInt32LSB,
+        AsioSampleType::AsioSTFloat32LSB,
+        AsioSampleType::AsioSTFloat64LSB,
+        AsioSampleType::AsioSTInt32LSB16,
+        AsioSampleType::AsioSTInt32LSB18,
+        AsioSampleType::AsioSTInt32LSB20,
+        AsioSampleType::AsioSTInt32LSB24,
+        AsioSampleType::AsioSTDSDInt8LSB1,
+        AsioSampleType::AsioSTDSDInt8MSB1,
+        AsioSampleType::AsioSTDSDInt8NER8,
+    ];
+
+    // DSD buffers hold one byte per frame, i.e. 8 samples for the 1 bit formats
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            AsioSampleType::AsioSTInt16MSB | AsioSampleType::AsioSTInt16LSB => 2,
+            AsioSampleType::AsioSTInt24MSB | AsioSampleType::AsioSTInt24LSB => 3,
+            AsioSampleType::AsioSTFloat64MSB | AsioSampleType::AsioSTFloat64LSB => 8,
+            AsioSampleType::AsioSTDSDInt8LSB1
+            | AsioSampleType::AsioSTDSDInt8MSB1
+            | AsioSampleType::AsioSTDSDInt8NER8 => 1,
+            _ => 4,
+        }
+    }
+    pub fn is_big_endian(&self) -> bool {
+        (*self as i32) < AsioSampleType::AsioSTInt16LSB as i32
+    }
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            AsioSampleType::AsioSTFloat32MSB
+                | AsioSampleType::AsioSTFloat64MSB
+                | AsioSampleType::AsioSTFloat32LSB
+                | AsioSampleType::AsioSTFloat64LSB
+        )
+    }
+    pub fn is_dsd(&self) -> bool {
+        (*self as i32) >= AsioSampleType::AsioSTDSDInt8LSB1 as i32
+    }
+}
+
 #[repr(i32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AsioError {
@@ -177,7 +366,40 @@ pub enum AsioError {
     NoMemory,
 }
 
+impl AsioError {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, AsioError::Ok | AsioError::Success)
+    }
+    pub fn to_result(self) -> Result<(), AsioError> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl std::fmt::Display for AsioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            AsioError::Ok => "no error",
+            AsioError::Success => "success",
+            AsioError::NotPresent => "hardware input or output is not present or available",
+            AsioError::HwMalfunction => "hardware is malfunctioning",
+            AsioError::InvalidParameter => "input parameter invalid",
+            AsioError::InvalidMode => "hardware is in a bad mode or used in a bad mode",
+            AsioError::SpNotAdvancing => "hardware is not running when sample position is inquired",
+            AsioError::NoClock => "sample clock or rate cannot be determined or is not present",
+            AsioError::NoMemory => "not enough memory for completing the request",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for AsioError {}
+
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AsioTimeCode {
     pub speed: f64,
     pub time_code_samples: AsioSamples,
@@ -185,8 +407,42 @@ pub struct AsioTimeCode {
     future: [u8; 64],
 }
 
+impl AsioTimeCode {
+    pub fn new(speed: f64, time_code_samples: AsioSamples, flags: AsioTimeCodeFlags) -> Self {
+        AsioTimeCode {
+            speed,
+            time_code_samples,
+            flags,
+            future: [0; 64],
+        }
+    }
+
+    /// The time code position, if the driver marked it valid.
+    pub fn samples(&self) -> Option<u64> {
+        self.flags
+            .contains(AsioTimeCodeFlags::valid)
+            .then_some(self.time_code_samples.to_u64())
+    }
+}
+
+impl Default for AsioTimeCode {
+    fn default() -> Self {
+        AsioTimeCode::new(0.0, AsioSamples::default(), AsioTimeCodeFlags::empty())
+    }
+}
+
+impl std::fmt::Debug for AsioTimeCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsioTimeCode")
+            .field("speed", &self.speed)
+            .field("time_code_samples", &self.time_code_samples)
+            .field("flags", &self.flags)
+            .finish_non_exhaustive()
+    }
+}
+
 bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
     pub struct AsioTimeCodeFlags :u32 {
         const valid      = 1 << 0;
         const running    = 1 << 1;
@@ -198,17 +454,84 @@ bitflags! {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AsioTimeInfo {
     pub speed: f64,
     pub system_time: AsioTimestamp,
     pub sample_position: AsioSamples,
     pub sample_rate: AsioSampleRate,
-    pub flags: u32,
+    pub flags: AsioTimeInfoFlags,
     reserved: [u8; 12],
 }
 
+impl AsioTimeInfo {
+    pub fn new(
+        speed: f64,
+        system_time: AsioTimestamp,
+        sample_position: AsioSamples,
+        sample_rate: AsioSampleRate,
+        flags: AsioTimeInfoFlags,
+    ) -> Self {
+        AsioTimeInfo {
+            speed,
+            system_time,
+            sample_position,
+            sample_rate,
+            flags,
+            reserved: [0; 12],
+        }
+    }
+
+    /// System time of the first sample in the buffer, if valid.
+    pub fn system_time(&self) -> Option<Duration> {
+        self.flags
+            .contains(AsioTimeInfoFlags::systemTimeValid)
+            .then_some(Duration::from_nanos(self.system_time.to_u64()))
+    }
+    /// Position of the first sample in the buffer, if valid.
+    pub fn sample_position(&self) -> Option<u64> {
+        self.flags
+            .contains(AsioTimeInfoFlags::samplePositionValid)
+            .then_some(self.sample_position.to_u64())
+    }
+    pub fn sample_rate(&self) -> Option<AsioSampleRate> {
+        self.flags
+            .contains(AsioTimeInfoFlags::sampleRateValid)
+            .then_some(self.sample_rate)
+    }
+    pub fn speed(&self) -> Option<f64> {
+        self.flags
+            .contains(AsioTimeInfoFlags::speedValid)
+            .then_some(self.speed)
+    }
+}
+
+impl Default for AsioTimeInfo {
+    fn default() -> Self {
+        AsioTimeInfo::new(
+            1.0,
+            AsioTimestamp::default(),
+            AsioSamples::default(),
+            0.0,
+            AsioTimeInfoFlags::empty(),
+        )
+    }
+}
+
+impl std::fmt::Debug for AsioTimeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsioTimeInfo")
+            .field("speed", &self.speed)
+            .field("system_time", &self.system_time)
+            .field("sample_position", &self.sample_position)
+            .field("sample_rate", &self.sample_rate)
+            .field("flags", &self.flags)
+            .finish_non_exhaustive()
+    }
+}
+
 bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
     pub  struct AsioTimeInfoFlags :u32 {
         const systemTimeValid     = 1 << 0; // must always be valid
         const samplePositionValid = 1 << 1; // must always be valid
@@ -220,19 +543,44 @@ bitflags! {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct AsioTime {
     reserved: [i32; 4],
     pub time_info: AsioTimeInfo,
     pub time_code: AsioTimeCode,
 }
 
+impl AsioTime {
+    pub fn new(time_info: AsioTimeInfo, time_code: AsioTimeCode) -> Self {
+        AsioTime {
+            reserved: [0; 4],
+            time_info,
+            time_code,
+        }
+    }
+
+    /// The fields the driver marked valid.
+    pub fn snapshot(&self) -> TimingSnapshot {
+        TimingSnapshot::from(self)
+    }
+}
+
+impl std::fmt::Debug for AsioTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsioTime")
+            .field("time_info", &self.time_info)
+            .field("time_code", &self.time_code)
+            .finish_non_exhaustive()
+    }
+}
+
 #[rustfmt::skip]
 #[repr(C)]
 pub struct AsioCallbacks {
-    pub buffer_switch: unsafe extern "C" fn(doubleBufferIdx: i32, directProcess: AsioBool),
-    pub sample_rate_did_change: unsafe extern "C" fn(sampleRate: AsioSampleRate),
+    pub buffer_switch: unsafe extern "C" fn(double_buffer_idx: i32, direct_process: AsioBool),
+    pub sample_rate_did_change: unsafe extern "C" fn(sample_rate: AsioSampleRate),
     pub asio_message: unsafe extern "C" fn(selector: AsioMessageSelector, value: i32, message: *mut std::ffi::c_void, opt: *mut f64) -> i32,
-    pub buffer_switch_time_info: unsafe extern "C" fn(params: *mut AsioTime, doubleBufferIndex: i32, directProcess: AsioBool) -> *mut AsioTime,
+    pub buffer_switch_time_info: unsafe extern "C" fn(params: *mut AsioTime, double_buffer_index: i32, direct_process: AsioBool) -> *mut AsioTime,
 }
 
 #[repr(i32)]
@@ -262,7 +610,7 @@ pub enum AsioMessageSelector {
                               // is a request to re-start the engine and
                               // slave devices (sequencer). returns 1 for ok,
                               // 0 if not supported.
-    LatenciesatenciesChanged, // the drivers latencies have changed. The engine
+    LatenciesChanged,         // the drivers latencies have changed. The engine
                               // will refetch the latencies.
     SupportsTimeInfo,         // if host returns true here, it will expect the
                               // callback bufferSwitchTimeInfo to be called instead
@@ -336,6 +684,12 @@ impl AsioClockSource {
     }
 }
 
+impl Default for AsioClockSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct AsioChannelInfo {
@@ -391,6 +745,7 @@ impl AsioBufferInfo {
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AsioInputMonitor {
     pub input: i32,
     pub output: i32,
@@ -400,6 +755,7 @@ pub struct AsioInputMonitor {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AsioChannelControls {
     pub channel: i32,
     pub is_input: AsioBool,
@@ -408,7 +764,20 @@ pub struct AsioChannelControls {
     future: [u8; 32],
 }
 
+impl AsioChannelControls {
+    pub fn new(channel: i32, is_input: bool) -> AsioChannelControls {
+        AsioChannelControls {
+            channel,
+            is_input: AsioBool::from(is_input),
+            gain: 0,
+            meter: 0,
+            future: [0; 32],
+        }
+    }
+}
+
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AsioTransportParameters {
     pub command: AsioTransportCommand,
     pub sample_position: AsioSamples,
@@ -417,6 +786,18 @@ pub struct AsioTransportParameters {
     future: [u8; 64],
 }
 
+impl AsioTransportParameters {
+    pub fn new(command: AsioTransportCommand) -> AsioTransportParameters {
+        AsioTransportParameters {
+            command,
+            sample_position: AsioSamples::default(),
+            track: 0,
+            track_switches: [0; 16],
+            future: [0; 64],
+        }
+    }
+}
+
 #[repr(i32)]
 #[rustfmt::skip]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -445,9 +826,33 @@ pub enum AsioIoFormatType
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AsioIoFormat {
     pub format_type: AsioIoFormatType,
-    future: [u8; 480],
+    future: [u8; 508],
+}
+
+impl AsioIoFormat {
+    pub fn new(format_type: AsioIoFormatType) -> AsioIoFormat {
+        AsioIoFormat {
+            format_type,
+            future: [0; 508],
+        }
+    }
+    pub fn pcm() -> AsioIoFormat {
+        AsioIoFormat::new(AsioIoFormatType::PCM)
+    }
+    pub fn dsd() -> AsioIoFormat {
+        AsioIoFormat::new(AsioIoFormatType::DSD)
+    }
+}
+
+impl std::fmt::Debug for AsioIoFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsioIoFormat")
+            .field("format_type", &self.format_type)
+            .finish_non_exhaustive()
+    }
 }
 
 #[repr(C)]
@@ -459,6 +864,7 @@ pub struct AsioInternalBufferInfo {
 #[repr(transparent)]
 pub struct AsioDriver(windows::core::IUnknown);
 impl AsioDriver {
+    #[cfg(windows)]
     pub unsafe fn new(driver_guid: windows::core::GUID) -> Result<AsioDriver, String> {
         let win_result = windows::Win32::System::Com::CoInitialize(None);
         if let Some(err) = win_result.err() {
@@ -470,10 +876,22 @@ impl AsioDriver {
             windows::Win32::System::Com::CLSCTX_INPROC_SERVER,
             &driver_guid,
         );
-        return match win_result {
+        match win_result {
             Err(err) => Err(err.to_string()),
             Ok(driver) => Ok(driver),
-        };
+        }
+    }
+    // COM drivers can only be instantiated on Windows
+    #[cfg(not(windows))]
+    pub unsafe fn new(_driver_guid: windows::core::GUID) -> Result<AsioDriver, String> {
+        Err(String::from("ASIO drivers can only be loaded on Windows"))
+    }
+    /// Opens the driver registered under `name` in `HKLM\SOFTWARE\ASIO`,
+    /// see [`registry::find_driver`] for the matching rules.
+    pub unsafe fn open_by_name(name: &str) -> Result<AsioDriver, registry::OpenError> {
+        let drivers = registry::drivers()?;
+        let driver = registry::find_driver(&drivers, name)?;
+        AsioDriver::new(driver.clsid).map_err(registry::OpenError::Com)
     }
     #[rustfmt::skip]
     pub unsafe fn init(&self, sys_handle: *mut std::ffi::c_void) -> AsioBool {
@@ -749,9 +1167,24 @@ impl AsioDriver {
     }
 }
 
+impl AsioDriver {
+    // Another reference to the object, without the CoInitialize `new` pairs
+    // with `Drop`
+    pub(crate) fn add_ref(&self) -> AsioDriver {
+        AsioDriver(self.0.clone())
+    }
+    // Releases a reference made by `add_ref` without uninitializing COM
+    pub(crate) unsafe fn release(&mut self) {
+        drop(std::ptr::read(&self.0));
+    }
+}
+
 impl Drop for AsioDriver {
     fn drop(&mut self) {
-        unsafe { windows::Win32::System::Com::CoUninitialize() }
+        #[cfg(windows)]
+        unsafe {
+            windows::Win32::System::Com::CoUninitialize()
+        }
     }
 }
 
@@ -760,7 +1193,7 @@ impl Drop for AsioDriver {
 pub struct AsioDriverVtbl {
     pub base__: windows::core::IUnknown_Vtbl,
     // virtual ASIOBool init(void *sysHandle) = 0;
-    pub init: unsafe extern "system" fn(this: *mut std::ffi::c_void,sysHandle: *mut std::ffi::c_void) -> AsioBool,
+    pub init: unsafe extern "system" fn(this: *mut std::ffi::c_void,sys_handle: *mut std::ffi::c_void) -> AsioBool,
     // virtual void getDriverName(char *name) = 0;
     pub get_driver_name: unsafe extern "system" fn(this: *mut std::ffi::c_void, *mut std::ffi::c_char),
     // virtual long getDriverVersion() = 0;
@@ -816,6 +1249,7 @@ impl std::clone::Clone for AsioDriver {
 pub unsafe trait NonStaticIidComInterface: windows::core::Interface + Clone {}
 unsafe impl NonStaticIidComInterface for AsioDriver {}
 
+#[cfg(windows)]
 pub unsafe fn co_create_instance_non_static_iid<P0, T>(
     rclsid: *const windows::core::GUID,
     punkouter: P0,
@@ -837,3 +1271,219 @@ where
     )
     .from_abi(result__)
 }
+
+// Layouts of the SDK types, any mismatch fails the build. `P` is the pointer
+// size of the target.
+macro_rules! assert_layout {
+    ($ty:ty, size $size:expr, align $align:expr $(, $field:ident @ $offset:expr)* $(,)?) => {
+        const _: () = {
+            assert!(std::mem::size_of::<$ty>() == $size);
+            assert!(std::mem::align_of::<$ty>() == $align);
+            $(assert!(std::mem::offset_of!($ty, $field) == $offset);)*
+        };
+    };
+}
+
+const P: usize = std::mem::size_of::<usize>();
+
+assert_layout!(AsioSamples, size 8, align 4, hi @ 0, lo @ 4);
+assert_layout!(AsioTimestamp, size 8, align 4, hi @ 0, lo @ 4);
+assert_layout!(AsioName, size 32, align 1);
+assert_layout!(AsioErrorMsg, size 124, align 1);
+assert_layout!(AsioBool, size 4, align 4);
+assert_layout!(AsioSampleType, size 4, align 4);
+assert_layout!(AsioError, size 4, align 4);
+assert_layout!(AsioMessageSelector, size 4, align 4);
+assert_layout!(AsioFutureSelector, size 4, align 4);
+assert_layout!(AsioTransportCommand, size 4, align 4);
+assert_layout!(AsioIoFormatType, size 4, align 4);
+assert_layout!(
+    AsioTimeCode,
+    size 88,
+    align 8,
+    speed @ 0,
+    time_code_samples @ 8,
+    flags @ 16,
+    future @ 20,
+);
+assert_layout!(
+    AsioTimeInfo,
+    size 48,
+    align 8,
+    speed @ 0,
+    system_time @ 8,
+    sample_position @ 16,
+    sample_rate @ 24,
+    flags @ 32,
+    reserved @ 36,
+);
+assert_layout!(AsioTime, size 152, align 8, reserved @ 0, time_info @ 16, time_code @ 64);
+assert_layout!(
+    AsioCallbacks,
+    size 4 * P,
+    align P,
+    buffer_switch @ 0,
+    sample_rate_did_change @ P,
+    asio_message @ 2 * P,
+    buffer_switch_time_info @ 3 * P,
+);
+assert_layout!(
+    AsioClockSource,
+    size 48,
+    align 4,
+    index @ 0,
+    associated_channel @ 4,
+    associated_group @ 8,
+    is_current_source @ 12,
+    name @ 16,
+);
+assert_layout!(
+    AsioChannelInfo,
+    size 52,
+    align 4,
+    channel @ 0,
+    is_input @ 4,
+    is_active @ 8,
+    channel_group @ 12,
+    sample_type @ 16,
+    name @ 20,
+);
+assert_layout!(AsioBufferInfo, size 8 + 2 * P, align P, is_input @ 0, channel_num @ 4, buffers @ 8);
+assert_layout!(
+    AsioInputMonitor,
+    size 20,
+    align 4,
+    input @ 0,
+    output @ 4,
+    gain @ 8,
+    state @ 12,
+    pan @ 16,
+);
+assert_layout!(
+    AsioChannelControls,
+    size 48,
+    align 4,
+    channel @ 0,
+    is_input @ 4,
+    gain @ 8,
+    meter @ 12,
+    future @ 16,
+);
+assert_layout!(
+    AsioTransportParameters,
+    size 144,
+    align 4,
+    command @ 0,
+    sample_position @ 4,
+    track @ 12,
+    track_switches @ 16,
+    future @ 80,
+);
+assert_layout!(AsioIoFormat, size 512, align 4, format_type @ 0, future @ 4);
+assert_layout!(
+    AsioInternalBufferInfo,
+    size 8,
+    align 4,
+    input_samples @ 0,
+    output_samples @ 4,
+);
+assert_layout!(AsioDriver, size P, align P);
+assert_layout!(
+    AsioDriverVtbl,
+    size 24 * P,
+    align P,
+    base__ @ 0,
+    init @ 3 * P,
+    get_sample_position @ 17 * P,
+    output_ready @ 23 * P,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{align_of, offset_of, size_of};
+
+    #[test]
+    fn split_values_convert_losslessly() {
+        for value in [
+            0,
+            1,
+            u32::MAX as u64,
+            1 << 32,
+            0x0123_4567_89AB_CDEF,
+            u64::MAX,
+        ] {
+            let samples = AsioSamples::from(value);
+            assert_eq!(samples.hi, (value >> 32) as u32);
+            assert_eq!(samples.lo, value as u32);
+            assert_eq!(u64::from(samples), value);
+            assert_eq!(u64::from(AsioTimestamp::from(value)), value);
+        }
+        let samples = AsioSamples { hi: 2, lo: 3 };
+        assert_eq!(samples.to_u64(), (2 << 32) + 3);
+    }
+
+    #[test]
+    fn time_layouts_are_locked() {
+        assert_eq!(
+            (size_of::<AsioSamples>(), align_of::<AsioSamples>()),
+            (8, 4)
+        );
+        assert_eq!(
+            (size_of::<AsioTimeInfo>(), align_of::<AsioTimeInfo>()),
+            (48, 8)
+        );
+        assert_eq!(
+            (size_of::<AsioTimeCode>(), align_of::<AsioTimeCode>()),
+            (88, 8)
+        );
+        assert_eq!((size_of::<AsioTime>(), align_of::<AsioTime>()), (152, 8));
+        assert_eq!(offset_of!(AsioTimeInfo, system_time), 8);
+        assert_eq!(offset_of!(AsioTimeInfo, sample_position), 16);
+        assert_eq!(offset_of!(AsioTimeInfo, flags), 32);
+        assert_eq!(offset_of!(AsioTimeCode, flags), 16);
+        assert_eq!(offset_of!(AsioTime, time_info), 16);
+        assert_eq!(offset_of!(AsioTime, time_code), 64);
+    }
+
+    #[test]
+    fn struct_layouts_are_locked() {
+        assert_eq!(size_of::<AsioClockSource>(), 48);
+        assert_eq!(offset_of!(AsioClockSource, name), 16);
+        assert_eq!(size_of::<AsioChannelInfo>(), 52);
+        assert_eq!(offset_of!(AsioChannelInfo, name), 20);
+        assert_eq!(size_of::<AsioInputMonitor>(), 20);
+        assert_eq!(size_of::<AsioChannelControls>(), 48);
+        assert_eq!(size_of::<AsioTransportParameters>(), 144);
+        assert_eq!(offset_of!(AsioTransportParameters, sample_position), 4);
+        assert_eq!(offset_of!(AsioTransportParameters, track_switches), 16);
+        assert_eq!(size_of::<AsioIoFormat>(), 512);
+        assert_eq!(size_of::<AsioInternalBufferInfo>(), 8);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn pointer_layouts_are_locked() {
+        assert_eq!(
+            (size_of::<AsioBufferInfo>(), align_of::<AsioBufferInfo>()),
+            (24, 8)
+        );
+        assert_eq!(offset_of!(AsioBufferInfo, buffers), 8);
+        assert_eq!(size_of::<AsioCallbacks>(), 32);
+        assert_eq!(size_of::<AsioDriverVtbl>(), 192);
+        assert_eq!(offset_of!(AsioDriverVtbl, output_ready), 184);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "32")]
+    fn pointer_layouts_are_locked() {
+        assert_eq!(
+            (size_of::<AsioBufferInfo>(), align_of::<AsioBufferInfo>()),
+            (16, 4)
+        );
+        assert_eq!(offset_of!(AsioBufferInfo, buffers), 8);
+        assert_eq!(size_of::<AsioCallbacks>(), 16);
+        assert_eq!(size_of::<AsioDriverVtbl>(), 96);
+        assert_eq!(offset_of!(AsioDriverVtbl, output_ready), 92);
+    }
+}
@@ -0,0 +1,321 @@
+//! Typestate wrappers enforcing the ASIO call order.
+//!
+//! `init` -> `create_buffers` -> `start` -> `stop` -> `dispose_buffers`. Each
+//! transition consumes the current state, so calling e.g. `start` before
+//! `create_buffers` does not compile. All states dereference to [`Driver`] for
+//! queries such as `get_channels` or `get_latencies`.
+
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::buffers::{BufferLiveness, BufferSet};
+use crate::callbacks::CallbackSlot;
+use crate::driver::InitError;
+use crate::format::{ChannelLayout, FormatError};
+use crate::registry::OpenError;
+use crate::{AsioBufferInfo, AsioCallbacks, AsioError, AsioIoFormatType, Driver, GUID};
+
+/// A failed transition, handing back the state the driver remained in.
+pub struct TransitionError<S, E = AsioError> {
+    pub state: S,
+    pub error: E,
+}
+
+impl<S, E: std::fmt::Debug> std::fmt::Debug for TransitionError<S, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransitionError")
+            .field("state", &std::any::type_name::<S>())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<S, E: std::fmt::Display> std::fmt::Display for TransitionError<S, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<S, E: std::fmt::Debug + std::fmt::Display> std::error::Error for TransitionError<S, E> {}
+
+/// A driver that has been instantiated but not initialized.
+pub struct LoadedDriver {
+    driver: Driver,
+}
+
+impl LoadedDriver {
+    pub fn new(driver_guid: GUID) -> Result<LoadedDriver, String> {
+        Driver::new(driver_guid).map(LoadedDriver::from)
+    }
+    pub fn open_by_name(name: &str) -> Result<LoadedDriver, OpenError> {
+        Driver::open_by_name(name).map(LoadedDriver::from)
+    }
+
+    /// Initializes the driver. A refusal carries the driver's
+    /// [`Driver::error_message`].
+    ///
+    /// # Safety
+    ///
+    /// `sys_handle` must be null or the application's main window handle.
+    pub unsafe fn init(
+        self,
+        sys_handle: *mut std::ffi::c_void,
+    ) -> Result<InitializedDriver, TransitionError<LoadedDriver, InitError>> {
+        match self.driver.init(sys_handle) {
+            Ok(()) => Ok(InitializedDriver {
+                driver: self.driver,
+            }),
+            Err(error) => Err(TransitionError { state: self, error }),
+        }
+    }
+}
+
+impl From<Driver> for LoadedDriver {
+    fn from(driver: Driver) -> Self {
+        LoadedDriver { driver }
+    }
+}
+
+impl Deref for LoadedDriver {
+    type Target = Driver;
+    fn deref(&self) -> &Driver {
+        &self.driver
+    }
+}
+
+/// An initialized driver without buffers.
+pub struct InitializedDriver {
+    driver: Driver,
+}
+
+impl InitializedDriver {
+    /// Creates buffers for the channels in `buffer_infos`.
+    ///
+    /// The callbacks are kept at a stable address until the buffers are disposed.
+    pub fn create_buffers(
+        self,
+        buffer_infos: Vec<AsioBufferInfo>,
+        buffer_size: i32,
+        callbacks: AsioCallbacks,
+    ) -> Result<PreparedDriver, TransitionError<InitializedDriver>> {
+        self.create_buffers_inner(buffer_infos, buffer_size, callbacks, None)
+    }
+
+    /// Creates buffers with the callbacks routed to the handler in `slot`.
+    ///
+    /// The slot is held until the buffers are disposed.
+    pub fn create_buffers_with_slot(
+        self,
+        buffer_infos: Vec<AsioBufferInfo>,
+        buffer_size: i32,
+        slot: CallbackSlot,
+    ) -> Result<PreparedDriver, TransitionError<InitializedDriver>> {
+        let callbacks = slot.callbacks();
+        self.create_buffers_inner(buffer_infos, buffer_size, callbacks, Some(slot))
+    }
+
+    /// Switches between PCM and DSD and returns the channels as they are
+    /// now, with their new sample types.
+    ///
+    /// The format is probed with `CanDoIoFormat` first and left alone if the
+    /// driver refuses it.
+    pub fn switch_format(
+        &self,
+        format_type: AsioIoFormatType,
+    ) -> Result<ChannelLayout, FormatError> {
+        self.driver
+            .can_do_io_format(format_type)
+            .map_err(|error| FormatError::Unsupported(format_type, error))?;
+        self.driver
+            .set_io_format(format_type)
+            .map_err(FormatError::Set)?;
+        let mut layout = self
+            .driver
+            .channel_layout()
+            .map_err(FormatError::ChannelInfo)?;
+        layout.format_type = format_type;
+        Ok(layout)
+    }
+
+    fn create_buffers_inner(
+        self,
+        mut buffer_infos: Vec<AsioBufferInfo>,
+        buffer_size: i32,
+        callbacks: AsioCallbacks,
+        slot: Option<CallbackSlot>,
+    ) -> Result<PreparedDriver, TransitionError<InitializedDriver>> {
+        let mut table = Box::new(callbacks);
+        match unsafe {
+            self.driver
+                .create_buffers(&mut buffer_infos, buffer_size, &mut table)
+        } {
+            Ok(()) => Ok(PreparedDriver {
+                driver: self.driver,
+                buffer_infos,
+                buffer_size,
+                buffers_alive: BufferLiveness::new(),
+                callbacks: ManuallyDrop::new(Callbacks { table, slot }),
+            }),
+            Err(error) => Err(TransitionError { state: self, error }),
+        }
+    }
+}
+
+impl Deref for InitializedDriver {
+    type Target = Driver;
+    fn deref(&self) -> &Driver {
+        &self.driver
+    }
+}
+
+/// A driver with buffers allocated, not yet streaming.
+///
+/// Dropping it disposes the buffers.
+pub struct PreparedDriver {
+    driver: Driver,
+    buffer_infos: Vec<AsioBufferInfo>,
+    buffer_size: i32,
+    // Retired before disposing, so views stop being handed out and the ones
+    // in use are dropped first
+    buffers_alive: Arc<BufferLiveness>,
+    // Only freed once the driver has confirmed it released the buffers
+    callbacks: ManuallyDrop<Callbacks>,
+}
+
+// Only held so the driver's pointers stay valid
+#[allow(dead_code)]
+struct Callbacks {
+    table: Box<AsioCallbacks>,
+    slot: Option<CallbackSlot>,
+}
+
+impl PreparedDriver {
+    pub fn buffer_infos(&self) -> &[AsioBufferInfo] {
+        &self.buffer_infos
+    }
+    pub fn buffer_size(&self) -> i32 {
+        self.buffer_size
+    }
+    /// Whether the buffers were created with a [`CallbackSlot`].
+    pub(crate) fn has_slot(&self) -> bool {
+        self.callbacks.slot.is_some()
+    }
+    /// Typed views of the buffers, usually moved into the handler.
+    ///
+    /// Disposing the buffers waits until no [`crate::ChannelBuffer`] taken
+    /// from the set is in use.
+    pub fn buffer_set(&self) -> Result<BufferSet, AsioError> {
+        // Safety: the infos were filled by `create_buffers` and the liveness
+        // is retired before the buffers are disposed
+        unsafe {
+            BufferSet::new(
+                &self.driver,
+                &self.buffer_infos,
+                self.buffer_size,
+                self.buffers_alive.clone(),
+            )
+        }
+    }
+
+    pub fn start(self) -> Result<RunningDriver, TransitionError<PreparedDriver>> {
+        match self.driver.start() {
+            Ok(()) => Ok(RunningDriver {
+                prepared: ManuallyDrop::new(self),
+            }),
+            Err(error) => Err(TransitionError { state: self, error }),
+        }
+    }
+
+    pub fn dispose_buffers(self) -> Result<InitializedDriver, TransitionError<PreparedDriver>> {
+        self.dispose_buffers_with_slot()
+            .map(|(driver, _slot)| driver)
+    }
+
+    /// Disposes the buffers and hands back the slot given to
+    /// [`InitializedDriver::create_buffers_with_slot`], so its handler can
+    /// serve the next stream.
+    pub fn dispose_buffers_with_slot(
+        self,
+    ) -> Result<(InitializedDriver, Option<CallbackSlot>), TransitionError<PreparedDriver>> {
+        self.buffers_alive.retire();
+        if let Err(error) = self.driver.dispose_buffers() {
+            self.buffers_alive.revive();
+            return Err(TransitionError { state: self, error });
+        }
+        let this = ManuallyDrop::new(self);
+        // Safety: `this` is never dropped, so every field is read exactly once.
+        let (driver, _buffer_infos, _buffers_alive, callbacks) = unsafe {
+            (
+                std::ptr::read(&this.driver),
+                std::ptr::read(&this.buffer_infos),
+                std::ptr::read(&this.buffers_alive),
+                ManuallyDrop::into_inner(std::ptr::read(&this.callbacks)),
+            )
+        };
+        Ok((InitializedDriver { driver }, callbacks.slot))
+    }
+}
+
+impl Deref for PreparedDriver {
+    type Target = Driver;
+    fn deref(&self) -> &Driver {
+        &self.driver
+    }
+}
+
+impl Drop for PreparedDriver {
+    fn drop(&mut self) {
+        self.buffers_alive.retire();
+        // If disposing fails the driver may still call back, so the callbacks leak
+        if self.driver.dispose_buffers().is_ok() {
+            unsafe { ManuallyDrop::drop(&mut self.callbacks) };
+        }
+    }
+}
+
+/// A streaming driver.
+///
+/// Dropping it stops the stream and then disposes the buffers.
+pub struct RunningDriver {
+    prepared: ManuallyDrop<PreparedDriver>,
+}
+
+impl RunningDriver {
+    pub fn buffer_infos(&self) -> &[AsioBufferInfo] {
+        self.prepared.buffer_infos()
+    }
+    pub fn buffer_size(&self) -> i32 {
+        self.prepared.buffer_size()
+    }
+    pub(crate) fn has_slot(&self) -> bool {
+        self.prepared.has_slot()
+    }
+    pub fn buffer_set(&self) -> Result<BufferSet, AsioError> {
+        self.prepared.buffer_set()
+    }
+
+    pub fn stop(self) -> Result<PreparedDriver, TransitionError<RunningDriver>> {
+        if let Err(error) = self.prepared.driver.stop() {
+            return Err(TransitionError { state: self, error });
+        }
+        let mut this = ManuallyDrop::new(self);
+        // Safety: `this` is never dropped, so `prepared` is taken exactly once.
+        Ok(unsafe { ManuallyDrop::take(&mut this.prepared) })
+    }
+}
+
+impl Deref for RunningDriver {
+    type Target = Driver;
+    fn deref(&self) -> &Driver {
+        &self.prepared.driver
+    }
+}
+
+impl Drop for RunningDriver {
+    fn drop(&mut self) {
+        let _ = self.prepared.driver.stop();
+        // Safety: `prepared` is not touched again after this.
+        unsafe { ManuallyDrop::drop(&mut self.prepared) };
+    }
+}
@@ -0,0 +1,373 @@
+//! Typed handling of the driver's `asio_message` calls.
+//!
+//! [`HostMessage::decode`] turns the raw arguments into an enum,
+//! [`HostCapabilities`] answers the queries truthfully and [`HostMessages`]
+//! combines both, forwarding accepted driver requests as [`HostEvent`]s over a
+//! lock-free queue. The callback thread must not block, the application
+//! polls the [`EventReceiver`] from its own thread.
+
+use std::ffi::c_void;
+
+use crate::ring::{ring, Consumer, Producer};
+use crate::AsioMessageSelector;
+
+/// A decoded `asio_message` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostMessage {
+    /// Asks whether the host handles the selector in `value`. Selectors
+    /// unknown to this crate are kept as their raw number.
+    SelectorSupported(i32),
+    EngineVersion,
+    /// The driver needs to be reinitialized, e.g. after settings changed in
+    /// its control panel.
+    ResetRequest,
+    /// The driver would like to use a new buffer size.
+    BufferSizeChange(i32),
+    /// The driver lost sync, timestamps are no longer valid.
+    ResyncRequest,
+    LatenciesChanged,
+    SupportsTimeInfo,
+    SupportsTimeCode,
+    MmcCommand {
+        count: i32,
+        commands: *mut c_void,
+    },
+    SupportsInputMonitor,
+    SupportsInputGain,
+    SupportsInputMeter,
+    SupportsOutputGain,
+    SupportsOutputMeter,
+    Overload,
+}
+
+impl HostMessage {
+    /// No selector uses `opt`, it is accepted to mirror the callback.
+    pub fn decode(
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut c_void,
+        _opt: *mut f64,
+    ) -> HostMessage {
+        use AsioMessageSelector::*;
+        match selector {
+            SelectorSupported => HostMessage::SelectorSupported(value),
+            EngineVersion => HostMessage::EngineVersion,
+            ResetRequest => HostMessage::ResetRequest,
+            BufferSizeChange => HostMessage::BufferSizeChange(value),
+            ResyncRequest => HostMessage::ResyncRequest,
+            LatenciesChanged => HostMessage::LatenciesChanged,
+            SupportsTimeInfo => HostMessage::SupportsTimeInfo,
+            SupportsTimeCode => HostMessage::SupportsTimeCode,
+            MMCCommand => HostMessage::MmcCommand {
+                count: value,
+                commands: message,
+            },
+            SupportsInputMonitor => HostMessage::SupportsInputMonitor,
+            SupportsInputGain => HostMessage::SupportsInputGain,
+            SupportsInputMeter => HostMessage::SupportsInputMeter,
+            SupportsOutputGain => HostMessage::SupportsOutputGain,
+            SupportsOutputMeter => HostMessage::SupportsOutputMeter,
+            Overload => HostMessage::Overload,
+        }
+    }
+
+    pub fn selector(&self) -> AsioMessageSelector {
+        use AsioMessageSelector::*;
+        match self {
+            HostMessage::SelectorSupported(_) => SelectorSupported,
+            HostMessage::EngineVersion => EngineVersion,
+            HostMessage::ResetRequest => ResetRequest,
+            HostMessage::BufferSizeChange(_) => BufferSizeChange,
+            HostMessage::ResyncRequest => ResyncRequest,
+            HostMessage::LatenciesChanged => LatenciesChanged,
+            HostMessage::SupportsTimeInfo => SupportsTimeInfo,
+            HostMessage::SupportsTimeCode => SupportsTimeCode,
+            HostMessage::MmcCommand { .. } => MMCCommand,
+            HostMessage::SupportsInputMonitor => SupportsInputMonitor,
+            HostMessage::SupportsInputGain => SupportsInputGain,
+            HostMessage::SupportsInputMeter => SupportsInputMeter,
+            HostMessage::SupportsOutputGain => SupportsOutputGain,
+            HostMessage::SupportsOutputMeter => SupportsOutputMeter,
+            HostMessage::Overload => Overload,
+        }
+    }
+
+    /// The request the application has to act on, if any.
+    pub fn event(&self) -> Option<HostEvent> {
+        match *self {
+            HostMessage::ResetRequest => Some(HostEvent::ResetRequest),
+            HostMessage::BufferSizeChange(size) => Some(HostEvent::BufferSizeChange(size)),
+            HostMessage::ResyncRequest => Some(HostEvent::ResyncRequest),
+            HostMessage::LatenciesChanged => Some(HostEvent::LatenciesChanged),
+            HostMessage::Overload => Some(HostEvent::Overload),
+            _ => None,
+        }
+    }
+}
+
+/// The selectors a host handles and the engine version it reports.
+///
+/// The default supports `SelectorSupported` and `EngineVersion` only and
+/// reports version 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostCapabilities {
+    pub engine_version: i32,
+    // Bit n set for selector n
+    supported: u32,
+}
+
+impl Default for HostCapabilities {
+    fn default() -> Self {
+        HostCapabilities {
+            engine_version: 2,
+            supported: 0,
+        }
+        .with(AsioMessageSelector::SelectorSupported)
+        .with(AsioMessageSelector::EngineVersion)
+    }
+}
+
+impl HostCapabilities {
+    pub fn with(mut self, selector: AsioMessageSelector) -> Self {
+        self.supported |= 1 << selector as u32;
+        self
+    }
+    pub fn without(mut self, selector: AsioMessageSelector) -> Self {
+        self.supported &= !(1 << selector as u32);
+        self
+    }
+    pub fn supports(&self, selector: AsioMessageSelector) -> bool {
+        self.supports_raw(selector as i32)
+    }
+    fn supports_raw(&self, selector: i32) -> bool {
+        (0..32).contains(&selector) && self.supported & (1 << selector) != 0
+    }
+
+    /// The value to return from `asio_message`: 1 for supported queries and
+    /// accepted requests, the version for `EngineVersion` and 0 otherwise.
+    pub fn reply(&self, message: &HostMessage) -> i32 {
+        match message {
+            HostMessage::SelectorSupported(selector) => self.supports_raw(*selector) as i32,
+            HostMessage::EngineVersion if self.supports(AsioMessageSelector::EngineVersion) => {
+                self.engine_version
+            }
+            message => self.supports(message.selector()) as i32,
+        }
+    }
+}
+
+/// A driver request for the application, see [`HostMessage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostEvent {
+    ResetRequest,
+    BufferSizeChange(i32),
+    ResyncRequest,
+    LatenciesChanged,
+    Overload,
+}
+
+/// Creates a queue holding up to `capacity` events, rounded up to a power of
+/// two.
+pub fn event_queue(capacity: usize) -> (EventSender, EventReceiver) {
+    let (producer, consumer) = ring(capacity);
+    (EventSender { producer }, EventReceiver { consumer })
+}
+
+/// Producing end of [`event_queue`], wait-free and allocation free.
+pub struct EventSender {
+    producer: Producer<HostEvent>,
+}
+
+impl EventSender {
+    /// Returns `false` and counts the event as dropped if the queue is full.
+    pub fn push(&mut self, event: HostEvent) -> bool {
+        self.producer.push(event)
+    }
+}
+
+/// Consuming end of [`event_queue`].
+pub struct EventReceiver {
+    consumer: Consumer<HostEvent>,
+}
+
+impl EventReceiver {
+    pub fn pop(&mut self) -> Option<HostEvent> {
+        self.consumer.pop()
+    }
+
+    /// Pops every event currently queued.
+    pub fn drain(&mut self) -> impl Iterator<Item = HostEvent> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+
+    /// Number of events lost to a full queue so far.
+    pub fn dropped(&self) -> u64 {
+        self.consumer.dropped()
+    }
+}
+
+/// Answers `asio_message` according to a [`HostCapabilities`] and forwards
+/// the requests it accepts to an [`EventReceiver`].
+pub struct HostMessages {
+    capabilities: HostCapabilities,
+    events: EventSender,
+}
+
+impl HostMessages {
+    pub fn new(capabilities: HostCapabilities, events: EventSender) -> HostMessages {
+        HostMessages {
+            capabilities,
+            events,
+        }
+    }
+
+    pub fn capabilities(&self) -> &HostCapabilities {
+        &self.capabilities
+    }
+
+    /// Handles one `asio_message` call, for use in
+    /// [`AsioHandler::asio_message`](crate::AsioHandler::asio_message).
+    pub fn handle(
+        &mut self,
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut c_void,
+        opt: *mut f64,
+    ) -> i32 {
+        let message = HostMessage::decode(selector, value, message, opt);
+        let reply = self.capabilities.reply(&message);
+        if reply != 0 {
+            if let Some(event) = message.event() {
+                self.events.push(event);
+            }
+        }
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AsioMessageSelector::*;
+
+    fn handle(messages: &mut HostMessages, selector: AsioMessageSelector, value: i32) -> i32 {
+        messages.handle(selector, value, std::ptr::null_mut(), std::ptr::null_mut())
+    }
+
+    #[test]
+    fn default_capabilities_claim_only_the_mandatory_selectors() {
+        let caps = HostCapabilities::default();
+        assert_eq!(caps.reply(&HostMessage::SelectorSupported(1)), 1);
+        assert_eq!(caps.reply(&HostMessage::SelectorSupported(2)), 1);
+        for selector in 3..=15 {
+            assert_eq!(caps.reply(&HostMessage::SelectorSupported(selector)), 0);
+        }
+        assert_eq!(caps.reply(&HostMessage::SelectorSupported(-7)), 0);
+        assert_eq!(caps.reply(&HostMessage::SelectorSupported(1000)), 0);
+        assert_eq!(caps.reply(&HostMessage::EngineVersion), 2);
+        assert_eq!(caps.reply(&HostMessage::ResetRequest), 0);
+        assert_eq!(caps.reply(&HostMessage::SupportsTimeInfo), 0);
+    }
+
+    #[test]
+    fn accepted_requests_are_forwarded() {
+        let (sender, mut receiver) = event_queue(8);
+        let caps = HostCapabilities {
+            engine_version: 3,
+            ..HostCapabilities::default()
+        }
+        .with(ResetRequest)
+        .with(ResyncRequest)
+        .with(LatenciesChanged)
+        .with(Overload)
+        .with(SupportsTimeInfo);
+        let mut messages = HostMessages::new(caps, sender);
+        assert_eq!(
+            handle(&mut messages, SelectorSupported, ResetRequest as i32),
+            1
+        );
+        assert_eq!(
+            handle(&mut messages, SelectorSupported, BufferSizeChange as i32),
+            0
+        );
+        assert_eq!(handle(&mut messages, EngineVersion, 0), 3);
+        assert_eq!(handle(&mut messages, SupportsTimeInfo, 0), 1);
+        assert_eq!(handle(&mut messages, SupportsTimeCode, 0), 0);
+        assert_eq!(handle(&mut messages, ResyncRequest, 0), 1);
+        assert_eq!(handle(&mut messages, BufferSizeChange, 128), 0);
+        assert_eq!(handle(&mut messages, LatenciesChanged, 0), 1);
+        assert_eq!(handle(&mut messages, Overload, 0), 1);
+        assert_eq!(handle(&mut messages, ResetRequest, 0), 1);
+        let events: Vec<_> = receiver.drain().collect();
+        assert_eq!(
+            events,
+            [
+                HostEvent::ResyncRequest,
+                HostEvent::LatenciesChanged,
+                HostEvent::Overload,
+                HostEvent::ResetRequest,
+            ]
+        );
+        assert_eq!(receiver.pop(), None);
+    }
+
+    #[test]
+    fn decodes_message_arguments() {
+        let mut commands = [0u8; 4];
+        let pointer = commands.as_mut_ptr().cast();
+        assert_eq!(
+            HostMessage::decode(MMCCommand, 2, pointer, std::ptr::null_mut()),
+            HostMessage::MmcCommand {
+                count: 2,
+                commands: pointer
+            }
+        );
+        let change = HostMessage::decode(BufferSizeChange, -64, pointer, std::ptr::null_mut());
+        assert_eq!(change.event(), Some(HostEvent::BufferSizeChange(-64)));
+        assert_eq!(change.selector(), BufferSizeChange);
+    }
+
+    #[test]
+    fn full_queue_drops_and_counts() {
+        let (mut sender, mut receiver) = event_queue(3);
+        for size in 0..5 {
+            sender.push(HostEvent::BufferSizeChange(size));
+        }
+        assert_eq!(receiver.dropped(), 1);
+        assert_eq!(receiver.pop(), Some(HostEvent::BufferSizeChange(0)));
+        assert!(sender.push(HostEvent::Overload));
+        let events: Vec<_> = receiver.drain().collect();
+        assert_eq!(
+            events,
+            [
+                HostEvent::BufferSizeChange(1),
+                HostEvent::BufferSizeChange(2),
+                HostEvent::BufferSizeChange(3),
+                HostEvent::Overload,
+            ]
+        );
+    }
+
+    #[test]
+    fn events_cross_threads_in_order() {
+        let (mut sender, mut receiver) = event_queue(16);
+        let producer = std::thread::spawn(move || {
+            let mut size = 0;
+            while size < 10_000 {
+                if sender.push(HostEvent::BufferSizeChange(size)) {
+                    size += 1;
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 10_000 {
+            if let Some(event) = receiver.pop() {
+                assert_eq!(event, HostEvent::BufferSizeChange(expected));
+                expected += 1;
+            }
+        }
+        producer.join().unwrap();
+        // Full-queue retries above are counted as drops as well
+        assert_eq!(receiver.pop(), None);
+    }
+}
@@ -0,0 +1,1454 @@
+//! In-process driver with a genuine `AsioDriverVtbl`, for exercising host
+//! code without hardware or COM. It is built on [`crate::server`] like any
+//! other Rust driver.
+//!
+//! [`MockDriver::driver`] hands out an [`AsioDriver`] like `CoCreateInstance`
+//! would, so [`crate::Driver`] and the typestate wrappers drive it unchanged.
+//! Once started, a clock thread fires the host's callbacks every
+//! `buffer_size / sample_rate` seconds.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::server::{write_c_str, AsioDriverImpl, DriverObject, HostCallbacks};
+use crate::{
+    AsioBool, AsioBufferInfo, AsioChannelControls, AsioChannelInfo, AsioClockSource, AsioDriver,
+    AsioError, AsioFutureSelector, AsioInputMonitor, AsioIoFormat, AsioIoFormatType,
+    AsioMessageSelector, AsioSampleRate, AsioSampleType, AsioTime, AsioTimeCode, AsioTimeInfo,
+    AsioTimeInfoFlags, AsioTransportParameters, BufferSizeInfo, ChannelCounts, Latencies,
+    SamplePosition, TransportCommand,
+};
+
+/// What the mock reports about itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockConfig {
+    pub name: String,
+    pub version: i32,
+    pub inputs: i32,
+    pub outputs: i32,
+    pub sample_type: AsioSampleType,
+    pub sample_rate: AsioSampleRate,
+    pub sample_rates: Vec<AsioSampleRate>,
+    pub buffer_size: BufferSizeInfo,
+    pub latencies: Latencies,
+    pub clock_sources: Vec<String>,
+    /// `CanXXX` selectors answered with `AsioError::Success`.
+    pub capabilities: Vec<AsioFutureSelector>,
+    /// Formats accepted by `CanDoIoFormat` and `SetIoFormat`, starting out
+    /// in PCM.
+    pub io_formats: Vec<AsioIoFormatType>,
+    /// Sample type of every channel while streaming DSD.
+    pub dsd_sample_type: AsioSampleType,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            name: String::from("Mock ASIO"),
+            version: 1,
+            inputs: 2,
+            outputs: 2,
+            sample_type: AsioSampleType::AsioSTInt32LSB,
+            sample_rate: 48000.0,
+            sample_rates: vec![44100.0, 48000.0, 96000.0],
+            buffer_size: BufferSizeInfo {
+                min: 32,
+                max: 1024,
+                preferred: 256,
+                granularity: -1,
+            },
+            latencies: Latencies {
+                input: 256,
+                output: 512,
+            },
+            clock_sources: vec![String::from("Internal")],
+            capabilities: vec![AsioFutureSelector::CanTimeInfo],
+            io_formats: vec![AsioIoFormatType::PCM],
+            dsd_sample_type: AsioSampleType::AsioSTDSDInt8MSB1,
+        }
+    }
+}
+
+/// Snapshot of the mock's state.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MockStatus {
+    pub initialized: bool,
+    pub buffers_created: bool,
+    pub running: bool,
+    pub sample_rate: AsioSampleRate,
+    pub buffer_size: i32,
+    pub clock_source: i32,
+    pub sample_position: u64,
+    pub buffer_switches: u64,
+    pub control_panel_calls: u32,
+}
+
+/// Driver calls that can be scripted to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockCall {
+    /// Fails `init`, which returns `AsioBool::False`.
+    Init,
+    CreateBuffers,
+    DisposeBuffers,
+    Start,
+    Stop,
+    GetBufferSize,
+    GetSamplePosition,
+    SetSampleRate,
+    SetInputMonitor,
+    /// `SetInputGain` and `SetOutputGain`.
+    SetGain,
+    /// `GetInputMeter` and `GetOutputMeter`.
+    GetMeter,
+    Transport,
+    SetIoFormat,
+}
+
+/// Something the driver does on its own initiative.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MockEvent {
+    /// Sent through the host's `asio_message`, e.g. `ResetRequest` or `Overload`.
+    Message {
+        selector: AsioMessageSelector,
+        value: i32,
+    },
+    /// The clock changed underneath the host, reported through
+    /// `sample_rate_did_change` and the next time info.
+    SampleRateChange(AsioSampleRate),
+    /// The given number of buffers pass without a buffer switch, as if the
+    /// host had missed them.
+    Dropout(u64),
+}
+
+/// Handle to a mock driver object, holding one reference to it.
+pub struct MockDriver {
+    object: DriverObject<Mock>,
+}
+
+impl MockDriver {
+    pub fn new(config: MockConfig) -> MockDriver {
+        let state = State {
+            initialized: false,
+            sample_rate: config.sample_rate,
+            clock_source: 0,
+            buffers: None,
+            clock: None,
+            sample_position: 0,
+            timestamp: 0,
+            buffer_switches: 0,
+            control_panel_calls: 0,
+            error_message: String::new(),
+            faults: Vec::new(),
+            scheduled: Vec::new(),
+            replies: Vec::new(),
+            input_monitors: Vec::new(),
+            gains: HashMap::new(),
+            meters: HashMap::new(),
+            transport_commands: Vec::new(),
+            io_format: AsioIoFormatType::PCM,
+            rate_changed: false,
+        };
+        let shared = Arc::new(Shared {
+            config,
+            state: Mutex::new(state),
+            switched: Condvar::new(),
+            epoch: Instant::now(),
+        });
+        MockDriver {
+            object: DriverObject::new(Mock { shared }),
+        }
+    }
+
+    /// A new reference to the driver object, as `CoCreateInstance` returns it.
+    pub fn driver(&self) -> AsioDriver {
+        self.object.driver()
+    }
+
+    pub fn status(&self) -> MockStatus {
+        let shared = self.shared();
+        let state = shared.lock();
+        MockStatus {
+            initialized: state.initialized,
+            buffers_created: state.buffers.is_some(),
+            running: state.clock.is_some(),
+            sample_rate: state.sample_rate,
+            buffer_size: state.buffers.as_ref().map_or(0, |b| b.size as i32),
+            clock_source: state.clock_source,
+            sample_position: state.sample_position,
+            buffer_switches: state.buffer_switches,
+            control_panel_calls: state.control_panel_calls,
+        }
+    }
+
+    /// Number of outstanding references, including this handle's.
+    pub fn ref_count(&self) -> u32 {
+        self.object.ref_count()
+    }
+
+    /// Waits until at least `count` buffer switches have completed.
+    pub fn wait_for_buffer_switches(&self, count: u64, timeout: Duration) -> bool {
+        let shared = self.shared();
+        let state = shared.lock();
+        let (state, _) = shared
+            .switched
+            .wait_timeout_while(state, timeout, |state| state.buffer_switches < count)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.buffer_switches >= count
+    }
+
+    /// Copy of an output buffer half. Only consistent while the host is not
+    /// writing to it, e.g. after `stop`.
+    pub fn output(&self, channel: i32, double_buffer_idx: i32) -> Option<Vec<u8>> {
+        let shared = self.shared();
+        let state = shared.lock();
+        let buffer = state.buffers.as_ref()?.find(false, channel)?;
+        Some(buffer.half(double_buffer_idx)?.to_vec())
+    }
+
+    /// Overwrites the start of an input buffer half, as the hardware would.
+    pub fn write_input(&self, channel: i32, double_buffer_idx: i32, bytes: &[u8]) -> bool {
+        let shared = self.shared();
+        let mut state = shared.lock();
+        let Some(buffer) = state
+            .buffers
+            .as_mut()
+            .and_then(|b| b.find_mut(true, channel))
+        else {
+            return false;
+        };
+        match buffer.half_mut(double_buffer_idx) {
+            Some(half) if bytes.len() <= half.len() => {
+                half[..bytes.len()].copy_from_slice(bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes every `call` fail with `error` until [`MockDriver::clear_faults`].
+    pub fn fail(&self, call: MockCall, error: AsioError) {
+        self.inject(call, error, false);
+    }
+    /// Makes the next `call` fail with `error`.
+    pub fn fail_once(&self, call: MockCall, error: AsioError) {
+        self.inject(call, error, true);
+    }
+    pub fn clear_faults(&self) {
+        self.shared().lock().faults.clear();
+    }
+    fn inject(&self, call: MockCall, error: AsioError, once: bool) {
+        let shared = self.shared();
+        let mut state = shared.lock();
+        state.faults.retain(|fault| fault.call != call);
+        state.faults.push(Fault { call, error, once });
+    }
+
+    /// Delivers `event` from the calling thread right away.
+    ///
+    /// Returns the host's reply to a message, 0 for other events, and
+    /// `None` while no callbacks are registered.
+    pub fn fire(&self, event: MockEvent) -> Option<i32> {
+        let shared = self.shared();
+        let callbacks = shared.lock().buffers.as_ref()?.callbacks;
+        Some(shared.fire(event, &callbacks))
+    }
+
+    /// Has the clock thread deliver `event` right before the buffer switch
+    /// following the first `after_buffer_switches` switches.
+    pub fn schedule(&self, after_buffer_switches: u64, event: MockEvent) {
+        let shared = self.shared();
+        shared.lock().scheduled.push((after_buffer_switches, event));
+    }
+
+    /// The host's replies to every message sent so far.
+    pub fn replies(&self) -> Vec<(AsioMessageSelector, i32)> {
+        self.shared().lock().replies.clone()
+    }
+
+    /// Every input monitor setting accepted so far.
+    pub fn input_monitors(&self) -> Vec<AsioInputMonitor> {
+        self.shared().lock().input_monitors.clone()
+    }
+
+    /// The last gain the host set on a channel.
+    pub fn gain(&self, is_input: bool, channel: i32) -> Option<i32> {
+        self.shared()
+            .lock()
+            .gains
+            .get(&(is_input, channel))
+            .copied()
+    }
+
+    /// Sets what the meter of a channel reads, 0 until then.
+    pub fn set_meter(&self, is_input: bool, channel: i32, level: i32) {
+        let shared = self.shared();
+        shared.lock().meters.insert((is_input, channel), level);
+    }
+
+    /// Every transport command accepted so far.
+    pub fn transport_commands(&self) -> Vec<TransportCommand> {
+        self.shared().lock().transport_commands.clone()
+    }
+
+    fn shared(&self) -> &Shared {
+        &self.object.get().shared
+    }
+}
+
+// Stops the clock once the last reference is released
+struct Mock {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        self.shared.stop_clock();
+    }
+}
+
+struct Shared {
+    config: MockConfig,
+    state: Mutex<State>,
+    switched: Condvar,
+    epoch: Instant,
+}
+
+struct State {
+    initialized: bool,
+    sample_rate: AsioSampleRate,
+    clock_source: i32,
+    buffers: Option<Buffers>,
+    clock: Option<Clock>,
+    sample_position: u64,
+    timestamp: u64,
+    buffer_switches: u64,
+    control_panel_calls: u32,
+    error_message: String,
+    faults: Vec<Fault>,
+    scheduled: Vec<(u64, MockEvent)>,
+    replies: Vec<(AsioMessageSelector, i32)>,
+    input_monitors: Vec<AsioInputMonitor>,
+    // Keyed by (is_input, channel)
+    gains: HashMap<(bool, i32), i32>,
+    meters: HashMap<(bool, i32), i32>,
+    transport_commands: Vec<TransportCommand>,
+    io_format: AsioIoFormatType,
+    // Reported once through the next time info
+    rate_changed: bool,
+}
+
+struct Fault {
+    call: MockCall,
+    error: AsioError,
+    once: bool,
+}
+
+struct Buffers {
+    size: usize,
+    channels: Vec<Buffer>,
+    callbacks: HostCallbacks,
+    time_info: bool,
+}
+
+impl Buffers {
+    fn find(&self, is_input: bool, channel: i32) -> Option<&Buffer> {
+        self.channels
+            .iter()
+            .find(|b| b.is_input == is_input && b.channel == channel)
+    }
+    fn find_mut(&mut self, is_input: bool, channel: i32) -> Option<&mut Buffer> {
+        self.channels
+            .iter_mut()
+            .find(|b| b.is_input == is_input && b.channel == channel)
+    }
+}
+
+struct Buffer {
+    is_input: bool,
+    channel: i32,
+    bytes: usize,
+    // u64 words keep the halves aligned for any sample type
+    halves: [Box<[u64]>; 2],
+}
+
+impl Buffer {
+    fn half(&self, double_buffer_idx: i32) -> Option<&[u8]> {
+        let half = self.halves.get(usize::try_from(double_buffer_idx).ok()?)?;
+        Some(unsafe { std::slice::from_raw_parts(half.as_ptr().cast(), self.bytes) })
+    }
+    fn half_mut(&mut self, double_buffer_idx: i32) -> Option<&mut [u8]> {
+        let half = self
+            .halves
+            .get_mut(usize::try_from(double_buffer_idx).ok()?)?;
+        Some(unsafe { std::slice::from_raw_parts_mut(half.as_mut_ptr().cast(), self.bytes) })
+    }
+}
+
+struct Clock {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn fail(&self, error: AsioError, message: &str) -> AsioError {
+        self.lock().error_message = String::from(message);
+        error
+    }
+
+    // Takes the fault scripted for `call`, if any
+    fn injected(&self, call: MockCall) -> Option<AsioError> {
+        let mut state = self.lock();
+        let index = state.faults.iter().position(|fault| fault.call == call)?;
+        let fault = &state.faults[index];
+        let error = fault.error;
+        if fault.once {
+            state.faults.remove(index);
+        }
+        state.error_message = format!("injected fault: {}", error);
+        Some(error)
+    }
+
+    // The channels follow the current io format
+    fn sample_type(&self) -> AsioSampleType {
+        match self.lock().io_format {
+            AsioIoFormatType::DSD => self.config.dsd_sample_type,
+            _ => self.config.sample_type,
+        }
+    }
+
+    fn fire(&self, event: MockEvent, callbacks: &HostCallbacks) -> i32 {
+        match event {
+            MockEvent::Message { selector, value } => {
+                let reply = callbacks.message(selector, value);
+                self.lock().replies.push((selector, reply));
+                reply
+            }
+            MockEvent::SampleRateChange(sample_rate) => {
+                {
+                    let mut state = self.lock();
+                    state.sample_rate = sample_rate;
+                    state.rate_changed = true;
+                }
+                callbacks.sample_rate_did_change(sample_rate);
+                0
+            }
+            MockEvent::Dropout(buffers) => {
+                let mut state = self.lock();
+                let size = state.buffers.as_ref().map_or(0, |b| b.size as u64);
+                state.sample_position += buffers * size;
+                0
+            }
+        }
+    }
+
+    fn stop_clock(&self) {
+        let clock = self.lock().clock.take();
+        if let Some(clock) = clock {
+            clock.stop.store(true, Ordering::Release);
+            // `stop` may be called from within a callback
+            if clock.thread.thread().id() != std::thread::current().id() {
+                let _ = clock.thread.join();
+            }
+        }
+    }
+
+    fn run_clock(&self, stop: &AtomicBool) {
+        let mut deadline = Instant::now();
+        let mut index = 0;
+        loop {
+            let period = {
+                let state = self.lock();
+                match &state.buffers {
+                    Some(buffers) => {
+                        Duration::from_secs_f64(buffers.size as f64 / state.sample_rate)
+                    }
+                    None => return,
+                }
+            };
+            deadline += period;
+            let now = Instant::now();
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            } else if now - deadline > period {
+                // Fell behind, e.g. under a debugger, do not burst to catch up
+                deadline = now;
+            }
+            if stop.load(Ordering::Acquire) {
+                return;
+            }
+            let (callbacks, events) = {
+                let mut state = self.lock();
+                let Some(buffers) = &state.buffers else {
+                    return;
+                };
+                let callbacks = buffers.callbacks;
+                let switches = state.buffer_switches;
+                let (due, pending) = std::mem::take(&mut state.scheduled)
+                    .into_iter()
+                    .partition(|(after, _)| *after <= switches);
+                state.scheduled = pending;
+                (callbacks, due)
+            };
+            for (_, event) in events {
+                self.fire(event, &callbacks);
+            }
+            let time = {
+                let mut state = self.lock();
+                state.timestamp = self.epoch.elapsed().as_nanos() as u64;
+                let time_info = state.buffers.as_ref().is_some_and(|b| b.time_info);
+                time_info.then(|| time_for(&mut state))
+            };
+            match time {
+                Some(mut time) => callbacks.buffer_switch_time_info(&mut time, index, true),
+                None => callbacks.buffer_switch(index, true),
+            }
+            {
+                let mut state = self.lock();
+                let size = state.buffers.as_ref().map_or(0, |b| b.size as u64);
+                state.sample_position += size;
+                state.buffer_switches += 1;
+            }
+            self.switched.notify_all();
+            index ^= 1;
+        }
+    }
+}
+
+impl AsioDriverImpl for Mock {
+    fn init(&self, _sys_handle: *mut c_void) -> bool {
+        if self.shared.injected(MockCall::Init).is_some() {
+            return false;
+        }
+        let mut state = self.shared.lock();
+        state.initialized = true;
+        state.error_message.clear();
+        true
+    }
+
+    fn driver_name(&self) -> String {
+        self.shared.config.name.clone()
+    }
+
+    fn driver_version(&self) -> i32 {
+        self.shared.config.version
+    }
+
+    fn error_message(&self) -> String {
+        self.shared.lock().error_message.clone()
+    }
+
+    fn start(&self) -> Result<(), AsioError> {
+        if let Some(error) = self.shared.injected(MockCall::Start) {
+            return Err(error);
+        }
+        let mut state = self.shared.lock();
+        if state.buffers.is_none() {
+            drop(state);
+            return Err(self
+                .shared
+                .fail(AsioError::NotPresent, "no buffers created"));
+        }
+        if state.clock.is_none() {
+            let stop = Arc::new(AtomicBool::new(false));
+            let shared = self.shared.clone();
+            let thread_stop = stop.clone();
+            let thread = std::thread::Builder::new()
+                .name(String::from("mock-asio-clock"))
+                .spawn(move || shared.run_clock(&thread_stop))
+                .expect("failed to spawn the mock clock thread");
+            state.clock = Some(Clock { stop, thread });
+        }
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), AsioError> {
+        if let Some(error) = self.shared.injected(MockCall::Stop) {
+            return Err(error);
+        }
+        self.shared.stop_clock();
+        Ok(())
+    }
+
+    fn get_channels(&self) -> Result<ChannelCounts, AsioError> {
+        if !self.shared.lock().initialized {
+            return Err(AsioError::NotPresent);
+        }
+        Ok(ChannelCounts {
+            inputs: self.shared.config.inputs,
+            outputs: self.shared.config.outputs,
+        })
+    }
+
+    fn get_latencies(&self) -> Result<Latencies, AsioError> {
+        Ok(self.shared.config.latencies)
+    }
+
+    fn get_buffer_size(&self) -> Result<BufferSizeInfo, AsioError> {
+        if let Some(error) = self.shared.injected(MockCall::GetBufferSize) {
+            return Err(error);
+        }
+        Ok(self.shared.config.buffer_size)
+    }
+
+    fn can_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+        if self.shared.config.sample_rates.contains(&sample_rate) {
+            Ok(())
+        } else {
+            Err(AsioError::NoClock)
+        }
+    }
+
+    fn get_sample_rate(&self) -> Result<AsioSampleRate, AsioError> {
+        Ok(self.shared.lock().sample_rate)
+    }
+
+    fn set_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+        if !self.shared.config.sample_rates.contains(&sample_rate) {
+            return Err(self
+                .shared
+                .fail(AsioError::NoClock, "unsupported sample rate"));
+        }
+        if let Some(error) = self.shared.injected(MockCall::SetSampleRate) {
+            return Err(error);
+        }
+        self.shared.lock().sample_rate = sample_rate;
+        Ok(())
+    }
+
+    fn get_clock_sources(&self, clocks: &mut [AsioClockSource]) -> Result<usize, AsioError> {
+        let current = self.shared.lock().clock_source;
+        let names = &self.shared.config.clock_sources;
+        for (index, (name, clock)) in names.iter().zip(clocks.iter_mut()).enumerate() {
+            *clock = AsioClockSource::new();
+            clock.index = index as i32;
+            clock.associated_channel = -1;
+            clock.associated_group = -1;
+            clock.is_current_source = AsioBool::from(index as i32 == current);
+            write_c_str(&mut clock.name.inner, name);
+        }
+        Ok(names.len().min(clocks.len()))
+    }
+
+    fn set_clock_source(&self, reference: i32) -> Result<(), AsioError> {
+        if reference < 0 || reference as usize >= self.shared.config.clock_sources.len() {
+            return Err(AsioError::InvalidParameter);
+        }
+        self.shared.lock().clock_source = reference;
+        Ok(())
+    }
+
+    fn get_sample_position(&self) -> Result<SamplePosition, AsioError> {
+        if let Some(error) = self.shared.injected(MockCall::GetSamplePosition) {
+            return Err(error);
+        }
+        let state = self.shared.lock();
+        if !state.initialized {
+            return Err(AsioError::NotPresent);
+        }
+        Ok(SamplePosition {
+            samples: state.sample_position,
+            timestamp: state.timestamp,
+        })
+    }
+
+    fn get_channel_info(&self, channel: i32, is_input: bool) -> Result<AsioChannelInfo, AsioError> {
+        let config = &self.shared.config;
+        let count = if is_input {
+            config.inputs
+        } else {
+            config.outputs
+        };
+        if channel < 0 || channel >= count {
+            return Err(AsioError::InvalidParameter);
+        }
+        let mut info = AsioChannelInfo::new(channel, is_input);
+        let active = self
+            .shared
+            .lock()
+            .buffers
+            .as_ref()
+            .is_some_and(|b| b.find(is_input, channel).is_some());
+        info.is_active = AsioBool::from(active);
+        info.sample_type = self.shared.sample_type();
+        let name = format!(
+            "{} {}",
+            if is_input { "Input" } else { "Output" },
+            channel + 1
+        );
+        write_c_str(&mut info.name.inner, &name);
+        Ok(info)
+    }
+
+    fn create_buffers(
+        &self,
+        infos: &mut [AsioBufferInfo],
+        buffer_size: i32,
+        callbacks: HostCallbacks,
+    ) -> Result<(), AsioError> {
+        let shared = &self.shared;
+        let config = &shared.config;
+        {
+            let state = shared.lock();
+            if !state.initialized {
+                return Err(AsioError::NotPresent);
+            }
+            if state.buffers.is_some() {
+                return Err(AsioError::InvalidMode);
+            }
+        }
+        if !valid_buffer_size(&config.buffer_size, buffer_size) {
+            return Err(shared.fail(AsioError::InvalidMode, "unsupported buffer size"));
+        }
+        if let Some(error) = shared.injected(MockCall::CreateBuffers) {
+            return Err(error);
+        }
+        let bytes = buffer_size as usize * shared.sample_type().bytes_per_sample();
+        let mut channels = Vec::with_capacity(infos.len());
+        for info in infos.iter_mut() {
+            let is_input = info.is_input.to_bool();
+            let count = if is_input {
+                config.inputs
+            } else {
+                config.outputs
+            };
+            let duplicate = channels
+                .iter()
+                .any(|b: &Buffer| b.is_input == is_input && b.channel == info.channel_num);
+            if info.channel_num < 0 || info.channel_num >= count || duplicate {
+                return Err(shared.fail(AsioError::InvalidParameter, "invalid channel"));
+            }
+            let words = bytes.div_ceil(8);
+            let mut buffer = Buffer {
+                is_input,
+                channel: info.channel_num,
+                bytes,
+                halves: [vec![0u64; words].into(), vec![0u64; words].into()],
+            };
+            info.buffers = [
+                buffer.halves[0].as_mut_ptr().cast(),
+                buffer.halves[1].as_mut_ptr().cast(),
+            ];
+            channels.push(buffer);
+        }
+        // Real drivers negotiate time info here, outside of any lock
+        let time_info = config
+            .capabilities
+            .contains(&AsioFutureSelector::CanTimeInfo)
+            && callbacks.supports(AsioMessageSelector::SupportsTimeInfo)
+            && callbacks.message(AsioMessageSelector::SupportsTimeInfo, 0) == 1;
+        shared.lock().buffers = Some(Buffers {
+            size: buffer_size as usize,
+            channels,
+            callbacks,
+            time_info,
+        });
+        Ok(())
+    }
+
+    fn dispose_buffers(&self) -> Result<(), AsioError> {
+        if let Some(error) = self.shared.injected(MockCall::DisposeBuffers) {
+            return Err(error);
+        }
+        self.shared.stop_clock();
+        match self.shared.lock().buffers.take() {
+            Some(_) => Ok(()),
+            None => Err(AsioError::InvalidMode),
+        }
+    }
+
+    fn control_panel(&self) -> Result<(), AsioError> {
+        self.shared.lock().control_panel_calls += 1;
+        Ok(())
+    }
+
+    unsafe fn future(&self, selector: i32, opt: *mut c_void) -> AsioError {
+        use AsioFutureSelector::*;
+        let config = &self.shared.config;
+        let Some(selector) = future_selector(selector) else {
+            return AsioError::NotPresent;
+        };
+        if let SetIoFormat | GetIoFormat | CanDoIoFormat = selector {
+            return self.io_format(selector, opt as *mut AsioIoFormat);
+        }
+        let gated_by = match selector {
+            SetInputMonitor => CanInputMonitor,
+            Transport => CanTransport,
+            SetInputGain => CanInputGain,
+            SetOutputGain => CanOutputGain,
+            GetInputMeter => CanInputMeter,
+            GetOutputMeter => CanOutputMeter,
+            selector => selector,
+        };
+        if !config.capabilities.contains(&gated_by) {
+            return AsioError::NotPresent;
+        }
+        match selector {
+            SetInputMonitor => self.set_input_monitor(opt as *const AsioInputMonitor),
+            Transport => self.transport(opt as *const AsioTransportParameters),
+            SetInputGain | SetOutputGain | GetInputMeter | GetOutputMeter => {
+                self.channel_control(selector, opt as *mut AsioChannelControls)
+            }
+            _ => AsioError::Success,
+        }
+    }
+}
+
+impl Mock {
+    fn channel_exists(&self, is_input: bool, channel: i32) -> bool {
+        let config = &self.shared.config;
+        let count = if is_input {
+            config.inputs
+        } else {
+            config.outputs
+        };
+        (0..count).contains(&channel)
+    }
+
+    unsafe fn set_input_monitor(&self, monitor: *const AsioInputMonitor) -> AsioError {
+        if let Some(error) = self.shared.injected(MockCall::SetInputMonitor) {
+            return error;
+        }
+        let Some(monitor) = monitor.as_ref() else {
+            return AsioError::InvalidParameter;
+        };
+        let input_valid = monitor.input == -1 || self.channel_exists(true, monitor.input);
+        if !input_valid || !self.channel_exists(false, monitor.output) {
+            return AsioError::InvalidParameter;
+        }
+        self.shared.lock().input_monitors.push(*monitor);
+        AsioError::Success
+    }
+
+    unsafe fn io_format(
+        &self,
+        selector: AsioFutureSelector,
+        format: *mut AsioIoFormat,
+    ) -> AsioError {
+        let Some(format) = format.as_mut() else {
+            return AsioError::InvalidParameter;
+        };
+        if selector == AsioFutureSelector::GetIoFormat {
+            format.format_type = self.shared.lock().io_format;
+            return AsioError::Success;
+        }
+        if selector == AsioFutureSelector::SetIoFormat {
+            if let Some(error) = self.shared.injected(MockCall::SetIoFormat) {
+                return error;
+            }
+        }
+        if !self.shared.config.io_formats.contains(&format.format_type) {
+            return AsioError::NotPresent;
+        }
+        if selector == AsioFutureSelector::SetIoFormat {
+            let mut state = self.shared.lock();
+            if state.buffers.is_some() {
+                return AsioError::InvalidMode;
+            }
+            state.io_format = format.format_type;
+        }
+        AsioError::Success
+    }
+
+    unsafe fn transport(&self, params: *const AsioTransportParameters) -> AsioError {
+        if let Some(error) = self.shared.injected(MockCall::Transport) {
+            return error;
+        }
+        let Some(params) = params.as_ref() else {
+            return AsioError::InvalidParameter;
+        };
+        let command = TransportCommand::from_raw(params);
+        self.shared.lock().transport_commands.push(command);
+        AsioError::Success
+    }
+
+    unsafe fn channel_control(
+        &self,
+        selector: AsioFutureSelector,
+        controls: *mut AsioChannelControls,
+    ) -> AsioError {
+        use AsioFutureSelector::*;
+        let call = match selector {
+            SetInputGain | SetOutputGain => MockCall::SetGain,
+            _ => MockCall::GetMeter,
+        };
+        if let Some(error) = self.shared.injected(call) {
+            return error;
+        }
+        let Some(controls) = controls.as_mut() else {
+            return AsioError::InvalidParameter;
+        };
+        let is_input = matches!(selector, SetInputGain | GetInputMeter);
+        if !self.channel_exists(is_input, controls.channel) {
+            return AsioError::InvalidParameter;
+        }
+        let key = (is_input, controls.channel);
+        let mut state = self.shared.lock();
+        if call == MockCall::SetGain {
+            state.gains.insert(key, controls.gain);
+        } else {
+            controls.meter = state.meters.get(&key).copied().unwrap_or(0);
+        }
+        AsioError::Success
+    }
+}
+
+fn valid_buffer_size(info: &BufferSizeInfo, size: i32) -> bool {
+    if size < info.min || size > info.max {
+        return false;
+    }
+    match info.granularity {
+        -1 => size.count_ones() == 1,
+        0 => size == info.preferred,
+        granularity => (size - info.min) % granularity == 0,
+    }
+}
+
+fn time_for(state: &mut State) -> AsioTime {
+    let mut flags = AsioTimeInfoFlags::systemTimeValid
+        | AsioTimeInfoFlags::samplePositionValid
+        | AsioTimeInfoFlags::sampleRateValid;
+    if std::mem::take(&mut state.rate_changed) {
+        flags |= AsioTimeInfoFlags::sampleRateChanged;
+    }
+    AsioTime::new(
+        AsioTimeInfo::new(
+            1.0,
+            state.timestamp.into(),
+            state.sample_position.into(),
+            state.sample_rate,
+            flags,
+        ),
+        AsioTimeCode::default(),
+    )
+}
+
+// The vtable passes selectors as plain integers, unknown ones must not become enums
+fn future_selector(selector: i32) -> Option<AsioFutureSelector> {
+    use AsioFutureSelector::*;
+    [
+        EnableTimeCodeRead,
+        DisableTimeCodeRead,
+        SetInputMonitor,
+        Transport,
+        SetInputGain,
+        GetInputMeter,
+        SetOutputGain,
+        GetOutputMeter,
+        CanInputMonitor,
+        CanTimeInfo,
+        CanTimeCode,
+        CanTransport,
+        CanInputGain,
+        CanInputMeter,
+        CanOutputGain,
+        CanOutputMeter,
+        OptionalOne,
+        SetIoFormat,
+        GetIoFormat,
+        CanDoIoFormat,
+        CanReportOverload,
+        GetInternalBufferSamples,
+    ]
+    .into_iter()
+    .find(|known| *known as i32 == selector)
+}
+
+#[cfg(test)]
+impl MockConfig {
+    /// Buffers of 32 to 256 samples, preferring 32, for tests that wait for
+    /// buffer switches.
+    pub(crate) fn small() -> MockConfig {
+        MockConfig {
+            buffer_size: BufferSizeInfo {
+                min: 32,
+                max: 256,
+                preferred: 32,
+                granularity: -1,
+            },
+            ..MockConfig::default()
+        }
+    }
+}
+
+/// Handler for tests only watching the driver side, optionally spending some
+/// time in every buffer switch.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct Quiet {
+    busy: Duration,
+}
+
+#[cfg(test)]
+impl Quiet {
+    pub(crate) fn busy(busy: Duration) -> Quiet {
+        Quiet { busy }
+    }
+}
+
+#[cfg(test)]
+impl crate::AsioHandler for Quiet {
+    fn buffer_switch(&mut self, _double_buffer_idx: i32, _direct_process: bool) {
+        if !self.busy.is_zero() {
+            std::thread::sleep(self.busy);
+        }
+    }
+}
+
+/// Loads and initializes the mock, where most tests start.
+#[cfg(test)]
+pub(crate) fn initialized(mock: &MockDriver) -> crate::InitializedDriver {
+    let driver = crate::LoadedDriver::from(crate::Driver::from_raw(mock.driver()));
+    unsafe { driver.init(std::ptr::null_mut()) }.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::TEST_SLOT;
+    use crate::convert::ClipPolicy;
+    use crate::{AsioHandler, BufferSet, CallbackSlot, Driver, LoadedDriver};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn buffer_infos(inputs: i32, outputs: i32) -> Vec<AsioBufferInfo> {
+        (0..inputs)
+            .map(AsioBufferInfo::new_input)
+            .chain((0..outputs).map(AsioBufferInfo::new_output))
+            .collect()
+    }
+
+    #[test]
+    fn answers_queries_through_driver() {
+        let mock = MockDriver::new(MockConfig::default());
+        let driver = Driver::from_raw(mock.driver());
+        assert_eq!(driver.get_channels(), Err(AsioError::NotPresent));
+        unsafe { driver.init(std::ptr::null_mut()) }.unwrap();
+        assert_eq!(driver.driver_name(), "Mock ASIO");
+        assert_eq!(driver.driver_version(), 1);
+        assert_eq!(driver.get_channels().unwrap().total(), 4);
+        assert_eq!(driver.get_latencies().unwrap().output, 512);
+        assert_eq!(driver.get_buffer_size().unwrap().preferred, 256);
+        assert_eq!(driver.can_sample_rate(96000.0), Ok(()));
+        assert_eq!(driver.can_sample_rate(8000.0), Err(AsioError::NoClock));
+        driver.set_sample_rate(44100.0).unwrap();
+        assert_eq!(driver.get_sample_rate(), Ok(44100.0));
+        assert_eq!(driver.set_sample_rate(1.0), Err(AsioError::NoClock));
+        assert_eq!(driver.error_message(), "unsupported sample rate");
+        let clocks = driver.get_clock_sources().unwrap();
+        assert_eq!(clocks.length, 1);
+        assert_eq!(unsafe { clocks.array[0].name.to_str() }, Ok("Internal"));
+        assert_eq!(clocks.array[0].is_current_source, AsioBool::True);
+        assert_eq!(driver.set_clock_source(3), Err(AsioError::InvalidParameter));
+        let info = driver.output_channel_info(1).unwrap();
+        assert_eq!(info.sample_type, AsioSampleType::AsioSTInt32LSB);
+        assert_eq!(unsafe { info.name.to_str() }, Ok("Output 2"));
+        assert_eq!(
+            driver.input_channel_info(2).err(),
+            Some(AsioError::InvalidParameter)
+        );
+        assert!(driver.can_time_info());
+        assert!(!driver.can_transport());
+        assert_eq!(driver.output_ready(), Err(AsioError::NotPresent));
+        driver.control_panel().unwrap();
+        assert_eq!(mock.status().control_panel_calls, 1);
+    }
+
+    #[test]
+    fn counts_references() {
+        let mock = MockDriver::new(MockConfig::default());
+        assert_eq!(mock.ref_count(), 1);
+        let driver = mock.driver();
+        let copy = driver.clone();
+        assert_eq!(mock.ref_count(), 3);
+        drop(driver);
+        assert_eq!(mock.ref_count(), 2);
+        drop(copy);
+        assert_eq!(mock.ref_count(), 1);
+    }
+
+    #[test]
+    fn rejects_calls_out_of_order() {
+        let mock = MockDriver::new(MockConfig::small());
+        let raw = mock.driver();
+        let mut infos = buffer_infos(0, 2);
+        let slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let slot = CallbackSlot::acquire(Counter::default()).unwrap();
+        let mut callbacks = slot.callbacks();
+        unsafe {
+            assert_eq!(raw.start(), AsioError::NotPresent);
+            assert_eq!(
+                raw.create_buffers(infos.as_mut_ptr(), 2, 32, &mut callbacks),
+                AsioError::NotPresent
+            );
+            raw.init(std::ptr::null_mut());
+            assert_eq!(
+                raw.create_buffers(infos.as_mut_ptr(), 2, 48, &mut callbacks),
+                AsioError::InvalidMode
+            );
+            assert_eq!(raw.dispose_buffers(), AsioError::InvalidMode);
+            let mut bad = vec![AsioBufferInfo::new_output(2)];
+            assert_eq!(
+                raw.create_buffers(bad.as_mut_ptr(), 1, 32, &mut callbacks),
+                AsioError::InvalidParameter
+            );
+            assert_eq!(
+                raw.create_buffers(infos.as_mut_ptr(), 2, 32, &mut callbacks),
+                AsioError::Ok
+            );
+            assert!(infos.iter().all(|info| !info.buffers[0].is_null()));
+            assert_eq!(
+                raw.create_buffers(infos.as_mut_ptr(), 2, 32, &mut callbacks),
+                AsioError::InvalidMode
+            );
+            assert_eq!(raw.dispose_buffers(), AsioError::Ok);
+        }
+        drop(slot);
+        drop(slot_lock);
+    }
+
+    // Writes half scale to every output and records the buffer indices
+    #[derive(Default)]
+    struct Counter {
+        indices: Arc<Mutex<Vec<i32>>>,
+        buffers: Arc<Mutex<Option<BufferSet>>>,
+    }
+
+    impl AsioHandler for Counter {
+        fn buffer_switch(&mut self, double_buffer_idx: i32, _direct_process: bool) {
+            self.indices.lock().unwrap().push(double_buffer_idx);
+            if let Some(buffers) = self.buffers.lock().unwrap().as_mut() {
+                for mut output in buffers.outputs(double_buffer_idx) {
+                    let samples = vec![0.5; output.len()];
+                    output.write_f32(&samples, ClipPolicy::Saturate).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn streams_through_the_lifecycle() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let counter = Counter::default();
+        let (indices, buffers) = (counter.indices.clone(), counter.buffers.clone());
+        let slot = CallbackSlot::acquire(counter).unwrap();
+        let driver = initialized(&mock);
+        let prepared = driver
+            .create_buffers_with_slot(buffer_infos(2, 2), 32, slot)
+            .unwrap();
+        assert!(mock.status().buffers_created);
+        *buffers.lock().unwrap() = Some(prepared.buffer_set().unwrap());
+        let running = prepared.start().unwrap();
+        assert!(mock.status().running);
+        assert!(mock.wait_for_buffer_switches(6, TIMEOUT));
+        let prepared = running.stop().unwrap();
+        let status = mock.status();
+        assert!(!status.running);
+        assert_eq!(status.sample_position, status.buffer_switches * 32);
+        let indices = indices.lock().unwrap().clone();
+        assert!(indices.len() >= 6);
+        assert!(indices
+            .iter()
+            .enumerate()
+            .all(|(n, idx)| *idx == n as i32 % 2));
+        for channel in 0..2 {
+            for half in 0..2 {
+                let bytes = mock.output(channel, half).unwrap();
+                assert_eq!(bytes.len(), 32 * 4);
+                assert_eq!(bytes[..4], (1i32 << 30).to_le_bytes());
+            }
+        }
+        assert!(mock.write_input(0, 1, &[1, 2, 3, 4]));
+        let mut set = prepared.buffer_set().unwrap();
+        let input = set.inputs(1).next().unwrap();
+        assert_eq!(input.as_i32().unwrap()[0], 0x04030201);
+        drop(input);
+        drop(prepared.dispose_buffers().unwrap());
+        assert!(!mock.status().buffers_created);
+        assert!(!set.is_alive());
+        assert!(buffers
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .channel(0, 0)
+            .is_none());
+        // The slot was freed together with the buffers
+        drop(CallbackSlot::acquire(Counter::default()).unwrap());
+    }
+
+    #[test]
+    fn fires_time_info_when_the_host_supports_it() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        struct Timed(std::sync::mpsc::Sender<(i32, u64)>);
+        impl AsioHandler for Timed {
+            fn buffer_switch(&mut self, _double_buffer_idx: i32, _direct_process: bool) {
+                panic!("expected buffer_switch_time_info");
+            }
+            fn buffer_switch_time_info(
+                &mut self,
+                time: &mut AsioTime,
+                double_buffer_idx: i32,
+                _direct_process: bool,
+            ) {
+                let _ = self
+                    .0
+                    .send((double_buffer_idx, time.time_info.sample_position.to_u64()));
+            }
+            fn asio_message(
+                &mut self,
+                selector: AsioMessageSelector,
+                _value: i32,
+                _message: *mut c_void,
+                _opt: *mut f64,
+            ) -> i32 {
+                matches!(
+                    selector,
+                    AsioMessageSelector::SelectorSupported | AsioMessageSelector::SupportsTimeInfo
+                ) as i32
+            }
+        }
+        let slot = CallbackSlot::acquire(Timed(sender)).unwrap();
+        let driver = initialized(&mock);
+        let running = driver
+            .create_buffers_with_slot(buffer_infos(0, 1), 64, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        assert!(mock.wait_for_buffer_switches(4, TIMEOUT));
+        drop(running);
+        let switches: Vec<_> = receiver.try_iter().take(4).collect();
+        assert_eq!(switches, [(0, 0), (1, 64), (0, 128), (1, 192)]);
+        let status = mock.status();
+        assert!(status.initialized && !status.buffers_created && !status.running);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Seen {
+        Message(AsioMessageSelector, i32),
+        SampleRate(AsioSampleRate),
+        TimeInfo(AsioSampleRate, AsioTimeInfoFlags),
+    }
+
+    // Accepts reset, resync and overload notifications like a typical host
+    struct Recorder(Arc<Mutex<Vec<Seen>>>);
+
+    impl AsioHandler for Recorder {
+        fn buffer_switch(&mut self, _double_buffer_idx: i32, _direct_process: bool) {}
+        fn buffer_switch_time_info(
+            &mut self,
+            time: &mut AsioTime,
+            _double_buffer_idx: i32,
+            _direct_process: bool,
+        ) {
+            let flags = time.time_info.flags;
+            if flags.contains(AsioTimeInfoFlags::sampleRateChanged) {
+                let seen = Seen::TimeInfo(time.time_info.sample_rate, flags);
+                self.0.lock().unwrap().push(seen);
+            }
+        }
+        fn sample_rate_did_change(&mut self, sample_rate: AsioSampleRate) {
+            self.0.lock().unwrap().push(Seen::SampleRate(sample_rate));
+        }
+        fn asio_message(
+            &mut self,
+            selector: AsioMessageSelector,
+            value: i32,
+            _message: *mut c_void,
+            _opt: *mut f64,
+        ) -> i32 {
+            use AsioMessageSelector::*;
+            match selector {
+                SelectorSupported => 1,
+                SupportsTimeInfo => 1,
+                ResetRequest | ResyncRequest | Overload => {
+                    self.0.lock().unwrap().push(Seen::Message(selector, value));
+                    1
+                }
+                _ => 0,
+            }
+        }
+    }
+
+    #[test]
+    fn init_failure_keeps_the_loaded_state() {
+        let mock = MockDriver::new(MockConfig::small());
+        mock.fail_once(MockCall::Init, AsioError::HwMalfunction);
+        let driver = LoadedDriver::from(Driver::from_raw(mock.driver()));
+        let failed = unsafe { driver.init(std::ptr::null_mut()) }.err().unwrap();
+        assert_eq!(
+            failed.error.message,
+            "injected fault: hardware is malfunctioning"
+        );
+        assert!(unsafe { failed.state.init(std::ptr::null_mut()) }.is_ok());
+    }
+
+    #[test]
+    fn create_buffers_out_of_memory() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        mock.fail_once(MockCall::CreateBuffers, AsioError::NoMemory);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let slot = CallbackSlot::acquire(Recorder(events.clone())).unwrap();
+        let failed = initialized(&mock)
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .err()
+            .unwrap();
+        assert_eq!(failed.error, AsioError::NoMemory);
+        assert!(!mock.status().buffers_created);
+        // The slot went down with the failed attempt and the retry succeeds
+        let slot = CallbackSlot::acquire(Recorder(events)).unwrap();
+        let prepared = failed
+            .state
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .unwrap();
+        assert!(mock.status().buffers_created);
+        drop(prepared);
+        assert!(!mock.status().buffers_created);
+    }
+
+    #[test]
+    fn start_hardware_malfunction() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        mock.fail(MockCall::Start, AsioError::HwMalfunction);
+        let slot = CallbackSlot::acquire(Recorder(Arc::default())).unwrap();
+        let prepared = initialized(&mock)
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .unwrap();
+        let failed = prepared.start().err().unwrap();
+        assert_eq!(failed.error, AsioError::HwMalfunction);
+        assert_eq!(failed.to_string(), "hardware is malfunctioning");
+        assert!(!mock.status().running);
+        let failed = failed.state.start().err().unwrap();
+        mock.clear_faults();
+        let running = failed.state.start().unwrap();
+        assert!(mock.wait_for_buffer_switches(2, TIMEOUT));
+        drop(running);
+        let status = mock.status();
+        assert!(!status.running && !status.buffers_created);
+    }
+
+    #[test]
+    fn sample_position_not_advancing() {
+        let mock = MockDriver::new(MockConfig::small());
+        let driver = initialized(&mock);
+        assert!(driver.get_sample_position().is_ok());
+        mock.fail(MockCall::GetSamplePosition, AsioError::SpNotAdvancing);
+        assert_eq!(driver.get_sample_position(), Err(AsioError::SpNotAdvancing));
+        assert_eq!(driver.get_sample_position(), Err(AsioError::SpNotAdvancing));
+        mock.clear_faults();
+        assert!(driver.get_sample_position().is_ok());
+    }
+
+    #[test]
+    fn failed_dispose_keeps_the_prepared_state() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let slot = CallbackSlot::acquire(Recorder(Arc::default())).unwrap();
+        let prepared = initialized(&mock)
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .unwrap();
+        let set = prepared.buffer_set().unwrap();
+        mock.fail_once(MockCall::DisposeBuffers, AsioError::HwMalfunction);
+        let failed = prepared.dispose_buffers().err().unwrap();
+        assert_eq!(failed.error, AsioError::HwMalfunction);
+        assert!(mock.status().buffers_created);
+        assert!(set.is_alive());
+        failed.state.dispose_buffers().unwrap();
+        assert!(!set.is_alive());
+    }
+
+    #[test]
+    fn spontaneous_messages_reach_the_handler() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        assert_eq!(
+            mock.fire(MockEvent::Message {
+                selector: AsioMessageSelector::Overload,
+                value: 0,
+            }),
+            None
+        );
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let slot = CallbackSlot::acquire(Recorder(events.clone())).unwrap();
+        let running = initialized(&mock)
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        mock.schedule(
+            2,
+            MockEvent::Message {
+                selector: AsioMessageSelector::ResyncRequest,
+                value: 0,
+            },
+        );
+        mock.schedule(
+            4,
+            MockEvent::Message {
+                selector: AsioMessageSelector::ResetRequest,
+                value: 0,
+            },
+        );
+        assert!(mock.wait_for_buffer_switches(6, TIMEOUT));
+        let reply = mock.fire(MockEvent::Message {
+            selector: AsioMessageSelector::Overload,
+            value: 0,
+        });
+        assert_eq!(reply, Some(1));
+        drop(running);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Seen::Message(AsioMessageSelector::ResyncRequest, 0),
+                Seen::Message(AsioMessageSelector::ResetRequest, 0),
+                Seen::Message(AsioMessageSelector::Overload, 0),
+            ]
+        );
+        let replies: Vec<_> = mock
+            .replies()
+            .into_iter()
+            .filter(|(selector, _)| *selector != AsioMessageSelector::SupportsTimeInfo)
+            .collect();
+        assert_eq!(
+            replies,
+            [
+                (AsioMessageSelector::ResyncRequest, 1),
+                (AsioMessageSelector::ResetRequest, 1),
+                (AsioMessageSelector::Overload, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_rate_changes_mid_stream() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let slot = CallbackSlot::acquire(Recorder(events.clone())).unwrap();
+        let running = initialized(&mock)
+            .create_buffers_with_slot(buffer_infos(0, 2), 32, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        mock.schedule(1, MockEvent::SampleRateChange(96000.0));
+        assert!(mock.wait_for_buffer_switches(4, TIMEOUT));
+        assert_eq!(running.get_sample_rate(), Ok(96000.0));
+        drop(running);
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], Seen::SampleRate(96000.0));
+        assert!(matches!(events[1], Seen::TimeInfo(rate, _) if rate == 96000.0));
+        assert_eq!(events.len(), 2);
+    }
+}
@@ -0,0 +1,365 @@
+//! Typed input monitoring on top of the `SetInputMonitor` future call.
+//!
+//! The SDK passes gain as 0..0x7fffffff with 0x20000000 being 0 dB, and pan
+//! as 0 (left) to 0x7fffffff (right). [`InputMonitorRoute`] works in dB and
+//! 0.0..=1.0 instead and is checked against the driver's channel counts
+//! before it is sent. [`InputMonitorMatrix`] applies several routes at once.
+
+use crate::{AsioBool, AsioError, AsioInputMonitor, ChannelCounts, Driver};
+
+/// Raw gain of 0 dB.
+pub const UNITY_GAIN: i32 = 0x2000_0000;
+
+/// Highest gain the SDK can express, about +12 dB.
+pub fn max_gain_db() -> f64 {
+    linear_to_db(i32::MAX as f64 / UNITY_GAIN as f64)
+}
+
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Silence maps to `f64::NEG_INFINITY`.
+pub fn linear_to_db(linear: f64) -> f64 {
+    20.0 * linear.log10()
+}
+
+/// Input side of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelSelector {
+    /// Every input, -1 in the SDK.
+    All,
+    Channel(i32),
+}
+
+impl ChannelSelector {
+    fn to_raw(self) -> i32 {
+        match self {
+            ChannelSelector::All => -1,
+            ChannelSelector::Channel(channel) => channel,
+        }
+    }
+}
+
+impl From<i32> for ChannelSelector {
+    fn from(channel: i32) -> Self {
+        match channel {
+            -1 => ChannelSelector::All,
+            channel => ChannelSelector::Channel(channel),
+        }
+    }
+}
+
+/// Why a route was not applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonitorError {
+    /// The driver does not answer `CanInputMonitor`.
+    Unsupported,
+    InvalidInput(i32),
+    InvalidOutput(i32),
+    /// Not a number or above [`max_gain_db`].
+    InvalidGain(f64),
+    /// Outside 0.0..=1.0.
+    InvalidPan(f64),
+    Driver(AsioError),
+}
+
+impl std::fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorError::Unsupported => {
+                f.write_str("the driver does not support input monitoring")
+            }
+            MonitorError::InvalidInput(channel) => write!(f, "no input channel {}", channel),
+            MonitorError::InvalidOutput(channel) => write!(f, "no output channel {}", channel),
+            MonitorError::InvalidGain(db) => write!(f, "gain of {} dB is out of range", db),
+            MonitorError::InvalidPan(pan) => write!(f, "pan of {} is out of range", pan),
+            MonitorError::Driver(error) => write!(f, "the driver rejected the route: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for MonitorError {}
+
+/// Monitoring of an input on an output, built from [`InputMonitorRoute::new`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputMonitorRoute {
+    pub input: ChannelSelector,
+    pub output: i32,
+    pub gain_db: f64,
+    /// 0.0 is left, 1.0 right.
+    pub pan: f64,
+    pub enabled: bool,
+}
+
+impl InputMonitorRoute {
+    /// Enabled at 0 dB, panned to the center.
+    pub fn new(input: impl Into<ChannelSelector>, output: i32) -> InputMonitorRoute {
+        InputMonitorRoute {
+            input: input.into(),
+            output,
+            gain_db: 0.0,
+            pan: 0.5,
+            enabled: true,
+        }
+    }
+    pub fn gain_db(mut self, gain_db: f64) -> Self {
+        self.gain_db = gain_db;
+        self
+    }
+    pub fn gain_linear(mut self, gain: f64) -> Self {
+        self.gain_db = linear_to_db(gain);
+        self
+    }
+    pub fn pan(mut self, pan: f64) -> Self {
+        self.pan = pan;
+        self
+    }
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn linear_gain(&self) -> f64 {
+        db_to_linear(self.gain_db)
+    }
+
+    /// Checks the route against the driver's channels.
+    pub fn validate(&self, channels: &ChannelCounts) -> Result<(), MonitorError> {
+        if let ChannelSelector::Channel(input) = self.input {
+            if !(0..channels.inputs).contains(&input) {
+                return Err(MonitorError::InvalidInput(input));
+            }
+        }
+        if !(0..channels.outputs).contains(&self.output) {
+            return Err(MonitorError::InvalidOutput(self.output));
+        }
+        if self.gain_db.is_nan() || self.gain_db > max_gain_db() {
+            return Err(MonitorError::InvalidGain(self.gain_db));
+        }
+        if !(0.0..=1.0).contains(&self.pan) {
+            return Err(MonitorError::InvalidPan(self.pan));
+        }
+        Ok(())
+    }
+
+    /// The SDK structure for a route that passed [`InputMonitorRoute::validate`].
+    pub fn to_raw(&self, channels: &ChannelCounts) -> Result<AsioInputMonitor, MonitorError> {
+        self.validate(channels)?;
+        let gain = (self.linear_gain() * UNITY_GAIN as f64).round();
+        Ok(AsioInputMonitor {
+            input: self.input.to_raw(),
+            output: self.output,
+            gain: gain.min(i32::MAX as f64) as i32,
+            state: if self.enabled {
+                AsioBool::True
+            } else {
+                AsioBool::False
+            },
+            pan: (self.pan * i32::MAX as f64).round() as i32,
+        })
+    }
+
+    pub fn from_raw(raw: &AsioInputMonitor) -> InputMonitorRoute {
+        InputMonitorRoute {
+            input: raw.input.into(),
+            output: raw.output,
+            gain_db: linear_to_db(raw.gain.max(0) as f64 / UNITY_GAIN as f64),
+            pan: raw.pan.max(0) as f64 / i32::MAX as f64,
+            enabled: raw.state.to_bool(),
+        }
+    }
+}
+
+/// A route that [`InputMonitorMatrix::apply`] could not apply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteFailure {
+    /// Position in the matrix.
+    pub index: usize,
+    pub route: InputMonitorRoute,
+    pub error: MonitorError,
+}
+
+/// Routes applied together, in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InputMonitorMatrix {
+    routes: Vec<InputMonitorRoute>,
+}
+
+impl InputMonitorMatrix {
+    pub fn new() -> InputMonitorMatrix {
+        InputMonitorMatrix::default()
+    }
+    pub fn route(mut self, route: InputMonitorRoute) -> Self {
+        self.routes.push(route);
+        self
+    }
+    pub fn push(&mut self, route: InputMonitorRoute) {
+        self.routes.push(route);
+    }
+    pub fn routes(&self) -> &[InputMonitorRoute] {
+        &self.routes
+    }
+
+    /// Applies every route, carrying on past failures.
+    pub fn apply(&self, driver: &Driver) -> Result<(), Vec<RouteFailure>> {
+        // Capability and channels are queried once for all routes
+        let channels = driver.input_monitor_channels();
+        let failures: Vec<RouteFailure> = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, route)| {
+                let result = channels
+                    .and_then(|channels| route.to_raw(&channels))
+                    .and_then(|raw| driver.set_input_monitor_raw(raw));
+                result.err().map(|error| RouteFailure {
+                    index,
+                    route: *route,
+                    error,
+                })
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+impl FromIterator<InputMonitorRoute> for InputMonitorMatrix {
+    fn from_iter<I: IntoIterator<Item = InputMonitorRoute>>(iter: I) -> Self {
+        InputMonitorMatrix {
+            routes: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized, MockCall, MockConfig, MockDriver};
+    use crate::AsioFutureSelector;
+
+    const STEREO: ChannelCounts = ChannelCounts {
+        inputs: 2,
+        outputs: 2,
+    };
+
+    #[test]
+    fn routes_convert_to_sdk_units() {
+        assert!((max_gain_db() - 12.0412).abs() < 1e-4);
+        let raw = InputMonitorRoute::new(1, 0).to_raw(&STEREO).unwrap();
+        assert_eq!((raw.input, raw.output), (1, 0));
+        assert_eq!((raw.gain, raw.pan), (UNITY_GAIN, 0x4000_0000));
+        assert_eq!(raw.state, AsioBool::True);
+
+        let route = InputMonitorRoute::new(ChannelSelector::All, 1)
+            .gain_linear(0.5)
+            .pan(0.0)
+            .enabled(false);
+        let raw = route.to_raw(&STEREO).unwrap();
+        assert_eq!((raw.input, raw.gain, raw.pan), (-1, 0x1000_0000, 0));
+        assert_eq!(raw.state, AsioBool::False);
+        let back = InputMonitorRoute::from_raw(&raw);
+        assert_eq!(back.input, ChannelSelector::All);
+        assert!((back.gain_db - route.gain_db).abs() < 1e-9);
+        assert!(!back.enabled);
+
+        let muted = InputMonitorRoute::new(0, 0).gain_linear(0.0);
+        assert_eq!(muted.to_raw(&STEREO).unwrap().gain, 0);
+        let loudest = InputMonitorRoute::new(0, 0).gain_db(max_gain_db());
+        assert_eq!(loudest.to_raw(&STEREO).unwrap().gain, i32::MAX);
+    }
+
+    #[test]
+    fn routes_are_validated() {
+        let route = InputMonitorRoute::new(0, 0);
+        let invalid = [
+            (InputMonitorRoute::new(2, 0), MonitorError::InvalidInput(2)),
+            (
+                InputMonitorRoute::new(-2, 0),
+                MonitorError::InvalidInput(-2),
+            ),
+            (InputMonitorRoute::new(0, 2), MonitorError::InvalidOutput(2)),
+            (route.gain_db(13.0), MonitorError::InvalidGain(13.0)),
+            (route.pan(1.5), MonitorError::InvalidPan(1.5)),
+        ];
+        for (route, error) in invalid {
+            assert_eq!(route.validate(&STEREO), Err(error));
+        }
+        assert!(matches!(
+            route.gain_db(f64::NAN).validate(&STEREO),
+            Err(MonitorError::InvalidGain(_))
+        ));
+    }
+
+    #[test]
+    fn matrix_reports_each_failure() {
+        let mock = MockDriver::new(MockConfig {
+            capabilities: vec![AsioFutureSelector::CanInputMonitor],
+            ..MockConfig::default()
+        });
+        let driver = initialized(&mock);
+        let matrix: InputMonitorMatrix = [
+            InputMonitorRoute::new(0, 0).pan(0.0),
+            InputMonitorRoute::new(1, 5),
+            InputMonitorRoute::new(1, 1).pan(1.0),
+            InputMonitorRoute::new(0, 1).gain_db(-6.0),
+        ]
+        .into_iter()
+        .collect();
+        mock.fail_once(MockCall::SetInputMonitor, AsioError::HwMalfunction);
+        let failures = matrix.apply(&driver).unwrap_err();
+        assert_eq!(
+            failures
+                .iter()
+                .map(|failure| (failure.index, failure.error))
+                .collect::<Vec<_>>(),
+            [
+                (0, MonitorError::Driver(AsioError::HwMalfunction)),
+                (1, MonitorError::InvalidOutput(5)),
+            ]
+        );
+        let applied: Vec<_> = mock
+            .input_monitors()
+            .iter()
+            .map(InputMonitorRoute::from_raw)
+            .map(|route| (route.input, route.output))
+            .collect();
+        assert_eq!(
+            applied,
+            [
+                (ChannelSelector::Channel(1), 1),
+                (ChannelSelector::Channel(0), 1)
+            ]
+        );
+
+        assert_eq!(
+            driver.set_input_monitor(&InputMonitorRoute::new(ChannelSelector::All, 0)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn needs_can_input_monitor() {
+        let mock = MockDriver::new(MockConfig::default());
+        let driver = initialized(&mock);
+        let route = InputMonitorRoute::new(0, 0);
+        assert_eq!(
+            driver.set_input_monitor(&route),
+            Err(MonitorError::Unsupported)
+        );
+        let failures = InputMonitorMatrix::new()
+            .route(route)
+            .route(route)
+            .apply(&driver)
+            .unwrap_err();
+        assert_eq!(failures.len(), 2);
+        assert!(failures
+            .iter()
+            .all(|failure| failure.error == MonitorError::Unsupported));
+        assert!(mock.input_monitors().is_empty());
+    }
+}
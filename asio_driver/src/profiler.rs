@@ -0,0 +1,293 @@
+//! Timing of the host's buffer switch processing against the buffer period.
+//!
+//! [`CallbackProfiler`] records how long each callback took on the audio
+//! thread, [`LoadMeter`] reads percentiles and the DSP load from any other
+//! thread, e.g. for a CPU meter. [`ProfiledHandler`] measures any
+//! [`AsioHandler`].
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::callbacks::AsioHandler;
+use crate::{AsioMessageSelector, AsioSampleRate, AsioTime};
+
+// 1% steps of the buffer period, the last bucket collects everything from 200% up
+const BUCKETS: usize = 201;
+
+/// Time between two buffer switches.
+pub fn buffer_period(buffer_size: i32, sample_rate: AsioSampleRate) -> Duration {
+    if buffer_size <= 0 || sample_rate <= 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs_f64(buffer_size as f64 / sample_rate)
+}
+
+struct Shared {
+    period_nanos: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+    callbacks: AtomicU64,
+    busy_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+    last_nanos: AtomicU64,
+}
+
+/// Creates a profiler measuring against buffers of `period`, see
+/// [`buffer_period`].
+pub fn load_meter(period: Duration) -> (CallbackProfiler, LoadMeter) {
+    let shared = Arc::new(Shared {
+        period_nanos: AtomicU64::new(period.as_nanos() as u64),
+        buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        callbacks: AtomicU64::new(0),
+        busy_nanos: AtomicU64::new(0),
+        max_nanos: AtomicU64::new(0),
+        last_nanos: AtomicU64::new(0),
+    });
+    (
+        CallbackProfiler {
+            shared: shared.clone(),
+        },
+        LoadMeter { shared },
+    )
+}
+
+/// Audio thread side of [`load_meter`], wait-free.
+pub struct CallbackProfiler {
+    shared: Arc<Shared>,
+}
+
+impl CallbackProfiler {
+    pub fn record(&self, elapsed: Duration) {
+        let shared = &*self.shared;
+        let nanos = elapsed.as_nanos() as u64;
+        let period = shared.period_nanos.load(Ordering::Relaxed).max(1);
+        let bucket = (nanos.saturating_mul(100) / period).min(BUCKETS as u64 - 1);
+        shared.buckets[bucket as usize].fetch_add(1, Ordering::Relaxed);
+        shared.callbacks.fetch_add(1, Ordering::Relaxed);
+        shared.busy_nanos.fetch_add(nanos, Ordering::Relaxed);
+        shared.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        shared.last_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Runs `f` and records how long it took.
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record(start.elapsed());
+        result
+    }
+}
+
+/// Callback durations since the last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoadStats {
+    pub callbacks: u64,
+    pub period: Duration,
+    /// Percentiles, resolved to 1% of the period.
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Average time spent in the callback, in percent of the period.
+    pub load: f64,
+    /// The most recent callback, in percent of the period.
+    pub current_load: f64,
+}
+
+/// Reading side of [`load_meter`], usable from any thread.
+#[derive(Clone)]
+pub struct LoadMeter {
+    shared: Arc<Shared>,
+}
+
+impl LoadMeter {
+    pub fn stats(&self) -> LoadStats {
+        let shared = &*self.shared;
+        let counts: Vec<u64> = shared
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let period = shared.period_nanos.load(Ordering::Relaxed);
+        let callbacks = shared.callbacks.load(Ordering::Relaxed);
+        let max = shared.max_nanos.load(Ordering::Relaxed);
+        let percent = |nanos: u64| match period {
+            0 => 0.0,
+            period => nanos as f64 * 100.0 / period as f64,
+        };
+        let percentile = |fraction: f64| {
+            let total: u64 = counts.iter().sum();
+            if total == 0 {
+                return Duration::ZERO;
+            }
+            let rank = ((total as f64 * fraction).ceil() as u64).max(1);
+            let mut seen = 0;
+            let bucket = counts
+                .iter()
+                .position(|count| {
+                    seen += count;
+                    seen >= rank
+                })
+                .unwrap_or(BUCKETS - 1);
+            // Upper edge of the bucket, never beyond the longest callback seen.
+            // The last bucket is open ended.
+            if bucket == BUCKETS - 1 {
+                return Duration::from_nanos(max);
+            }
+            let edge = (bucket as u64 + 1) * period / 100;
+            Duration::from_nanos(edge.min(max))
+        };
+        LoadStats {
+            callbacks,
+            period: Duration::from_nanos(period),
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            max: Duration::from_nanos(max),
+            load: match callbacks {
+                0 => 0.0,
+                callbacks => percent(shared.busy_nanos.load(Ordering::Relaxed)) / callbacks as f64,
+            },
+            current_load: percent(shared.last_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Clears the statistics. Callbacks recorded concurrently may be lost.
+    pub fn reset(&self) {
+        let shared = &*self.shared;
+        for bucket in &shared.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        for counter in [
+            &shared.callbacks,
+            &shared.busy_nanos,
+            &shared.max_nanos,
+            &shared.last_nanos,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Follows a new buffer size or sample rate. Resets the statistics, they
+    /// are relative to the period.
+    pub fn set_period(&self, period: Duration) {
+        let nanos = period.as_nanos() as u64;
+        self.shared.period_nanos.store(nanos, Ordering::Relaxed);
+        self.reset();
+    }
+}
+
+/// Measures the buffer switch callbacks of the wrapped handler.
+pub struct ProfiledHandler<H> {
+    inner: H,
+    profiler: CallbackProfiler,
+}
+
+impl<H: AsioHandler> ProfiledHandler<H> {
+    pub fn new(inner: H, profiler: CallbackProfiler) -> ProfiledHandler<H> {
+        ProfiledHandler { inner, profiler }
+    }
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+}
+
+impl<H: AsioHandler> AsioHandler for ProfiledHandler<H> {
+    fn buffer_switch(&mut self, double_buffer_idx: i32, direct_process: bool) {
+        let inner = &mut self.inner;
+        self.profiler
+            .measure(|| inner.buffer_switch(double_buffer_idx, direct_process))
+    }
+
+    fn buffer_switch_time_info(
+        &mut self,
+        time: &mut AsioTime,
+        double_buffer_idx: i32,
+        direct_process: bool,
+    ) {
+        let inner = &mut self.inner;
+        self.profiler
+            .measure(|| inner.buffer_switch_time_info(time, double_buffer_idx, direct_process))
+    }
+
+    fn sample_rate_did_change(&mut self, sample_rate: AsioSampleRate) {
+        self.inner.sample_rate_did_change(sample_rate)
+    }
+
+    fn asio_message(
+        &mut self,
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut c_void,
+        opt: *mut f64,
+    ) -> i32 {
+        self.inner.asio_message(selector, value, message, opt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::TEST_SLOT;
+    use crate::mock::{initialized, MockConfig, MockDriver, Quiet};
+    use crate::{AsioBufferInfo, CallbackSlot};
+    use std::sync::PoisonError;
+
+    #[test]
+    fn percentiles_and_load() {
+        let period = buffer_period(480, 48000.0);
+        assert_eq!(period, Duration::from_millis(10));
+        assert_eq!(buffer_period(0, 48000.0), Duration::ZERO);
+        let (profiler, meter) = load_meter(period);
+        assert_eq!(meter.stats().p99, Duration::ZERO);
+        for _ in 0..98 {
+            profiler.record(Duration::from_micros(2050));
+        }
+        profiler.record(Duration::from_millis(7));
+        profiler.record(Duration::from_millis(30));
+        let stats = meter.stats();
+        assert_eq!(stats.callbacks, 100);
+        assert_eq!(stats.p50, Duration::from_micros(2100));
+        assert_eq!(stats.p99, Duration::from_micros(7100));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        // (98 * 2.05 + 7 + 30) ms over 100 periods of 10 ms
+        assert!((stats.load - 23.79).abs() < 1e-9, "{}", stats.load);
+        assert!((stats.current_load - 300.0).abs() < 1e-9);
+
+        meter.set_period(Duration::from_millis(5));
+        assert_eq!(meter.stats().callbacks, 0);
+        profiler.record(Duration::from_millis(20));
+        let stats = meter.stats();
+        // In the open ended last bucket, reported as the maximum
+        assert_eq!((stats.p50, stats.p99), (stats.max, stats.max));
+        assert_eq!(stats.period, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn measures_every_buffer_switch() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let driver = initialized(&mock);
+        let period = buffer_period(32, driver.get_sample_rate().unwrap());
+        let (profiler, meter) = load_meter(period);
+        let slot = CallbackSlot::acquire(ProfiledHandler::new(
+            Quiet::busy(Duration::from_micros(100)),
+            profiler,
+        ))
+        .unwrap();
+        let running = driver
+            .create_buffers_with_slot(vec![AsioBufferInfo::new_output(0)], 32, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        assert!(mock.wait_for_buffer_switches(4, Duration::from_secs(5)));
+        drop(running);
+        let stats = meter.stats();
+        assert_eq!(stats.callbacks, mock.status().buffer_switches);
+        assert!(stats.max >= Duration::from_micros(100));
+        assert!(stats.p50 <= stats.p99 && stats.p99 <= stats.max);
+        assert!(stats.load > 0.0);
+    }
+}
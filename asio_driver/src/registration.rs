@@ -0,0 +1,258 @@
+//! Registry entries of drivers implemented with [`crate::server`].
+//!
+//! [`DriverRegistration`] describes what an installer writes, as a list of
+//! [`RegistryOp`]s that can be inspected, applied through
+//! [`crate::registry::apply`] or rendered as a `.reg` script with
+//! [`reg_script`].
+
+use std::path::PathBuf;
+
+use crate::registry::{format_guid, Hive, RegistryOp, ASIO_KEY};
+use crate::GUID;
+
+/// A registration the library refuses to write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistrationError {
+    /// An empty name, or one containing `\`, which would not name a single
+    /// key below `HKLM\SOFTWARE\ASIO`.
+    InvalidName(String),
+}
+
+impl std::fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::InvalidName(name) => write!(f, "invalid driver name \"{}\"", name),
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+/// Everything needed to register a driver DLL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverRegistration {
+    /// Name of the key below `HKLM\SOFTWARE\ASIO`, must not be empty or
+    /// contain `\`.
+    pub name: String,
+    pub clsid: GUID,
+    pub description: String,
+    pub dll_path: PathBuf,
+    /// COM threading model of the class, ASIO drivers use `Apartment`.
+    pub threading_model: String,
+}
+
+impl DriverRegistration {
+    /// Uses `name` as description and the `Apartment` threading model.
+    pub fn new(name: &str, clsid: GUID, dll_path: PathBuf) -> DriverRegistration {
+        DriverRegistration {
+            name: name.to_string(),
+            clsid,
+            description: name.to_string(),
+            dll_path,
+            threading_model: String::from("Apartment"),
+        }
+    }
+
+    /// Checks that the name is a single, non-empty key name.
+    pub fn validate(&self) -> Result<(), RegistrationError> {
+        if self.name.trim().is_empty() || self.name.contains('\\') {
+            return Err(RegistrationError::InvalidName(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Writes the COM class and the ASIO driver entry.
+    pub fn install_ops(&self) -> Result<Vec<RegistryOp>, RegistrationError> {
+        self.validate()?;
+        let clsid = format_guid(&self.clsid);
+        let set = |hive, path: &str, name: Option<&str>, value: &str| RegistryOp::SetString {
+            hive,
+            path: path.to_string(),
+            name: name.map(str::to_string),
+            value: value.to_string(),
+        };
+        let class_key = self.class_key();
+        let server_key = format!("{}\\InprocServer32", class_key);
+        let driver_key = self.driver_key();
+        Ok(vec![
+            set(Hive::ClassesRoot, &class_key, None, &self.description),
+            set(
+                Hive::ClassesRoot,
+                &server_key,
+                None,
+                &self.dll_path.to_string_lossy(),
+            ),
+            set(
+                Hive::ClassesRoot,
+                &server_key,
+                Some("ThreadingModel"),
+                &self.threading_model,
+            ),
+            set(Hive::LocalMachine, &driver_key, Some("CLSID"), &clsid),
+            set(
+                Hive::LocalMachine,
+                &driver_key,
+                Some("Description"),
+                &self.description,
+            ),
+        ])
+    }
+
+    /// Removes what [`DriverRegistration::install_ops`] writes.
+    pub fn uninstall_ops(&self) -> Result<Vec<RegistryOp>, RegistrationError> {
+        self.validate()?;
+        Ok(vec![
+            RegistryOp::DeleteKey {
+                hive: Hive::LocalMachine,
+                path: self.driver_key(),
+            },
+            RegistryOp::DeleteKey {
+                hive: Hive::ClassesRoot,
+                path: self.class_key(),
+            },
+        ])
+    }
+
+    fn class_key(&self) -> String {
+        format!("CLSID\\{}", format_guid(&self.clsid))
+    }
+
+    fn driver_key(&self) -> String {
+        format!("{}\\{}", ASIO_KEY, self.name)
+    }
+}
+
+/// Renders `ops` as a `.reg` script for regedit, with CRLF line endings.
+pub fn reg_script(ops: &[RegistryOp]) -> String {
+    let mut script = String::from("Windows Registry Editor Version 5.00\r\n");
+    let mut current: Option<(Hive, &str)> = None;
+    for op in ops {
+        match op {
+            RegistryOp::SetString {
+                hive,
+                path,
+                name,
+                value,
+            } => {
+                if current != Some((*hive, path)) {
+                    script.push_str(&format!("\r\n[{}\\{}]\r\n", hive.name(), path));
+                    current = Some((*hive, path));
+                }
+                match name {
+                    Some(name) => script.push_str(&format!("\"{}\"", escape(name))),
+                    None => script.push('@'),
+                }
+                script.push_str(&format!("=\"{}\"\r\n", escape(value)));
+            }
+            RegistryOp::DeleteKey { hive, path } => {
+                script.push_str(&format!("\r\n[-{}\\{}]\r\n", hive.name(), path));
+                current = None;
+            }
+        }
+    }
+    script
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{apply_to, drivers_from, RegFile, RegistryReader};
+
+    fn registration() -> DriverRegistration {
+        DriverRegistration {
+            description: String::from("Virtual \"Loopback\" Device"),
+            ..DriverRegistration::new(
+                "Loopback",
+                GUID::from_u128(0x6B3C3A5E_0D2F_4C1A_9F5E_8A1D2B3C4D5E),
+                PathBuf::from("C:\\Program Files\\Loopback\\loopback_asio.dll"),
+            )
+        }
+    }
+
+    #[test]
+    fn install_script_matches_the_operations() {
+        let ops = registration().install_ops().unwrap();
+        assert_eq!(
+            ops[1].to_string(),
+            "set HKEY_CLASSES_ROOT\\CLSID\\{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}\\InprocServer32 @ = \"C:\\Program Files\\Loopback\\loopback_asio.dll\""
+        );
+        let script = reg_script(&ops);
+        assert_eq!(
+            script,
+            "Windows Registry Editor Version 5.00\r\n\
+             \r\n\
+             [HKEY_CLASSES_ROOT\\CLSID\\{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}]\r\n\
+             @=\"Virtual \\\"Loopback\\\" Device\"\r\n\
+             \r\n\
+             [HKEY_CLASSES_ROOT\\CLSID\\{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}\\InprocServer32]\r\n\
+             @=\"C:\\\\Program Files\\\\Loopback\\\\loopback_asio.dll\"\r\n\
+             \"ThreadingModel\"=\"Apartment\"\r\n\
+             \r\n\
+             [HKEY_LOCAL_MACHINE\\SOFTWARE\\ASIO\\Loopback]\r\n\
+             \"CLSID\"=\"{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}\"\r\n\
+             \"Description\"=\"Virtual \\\"Loopback\\\" Device\"\r\n"
+        );
+    }
+
+    #[test]
+    fn installed_driver_is_found() {
+        let registration = registration();
+        let script = RegFile::parse(&reg_script(&registration.install_ops().unwrap())).unwrap();
+        let mut applied = RegFile::default();
+        apply_to(&mut applied, &registration.install_ops().unwrap()).unwrap();
+        for registry in [&script, &applied] {
+            let drivers = drivers_from(registry).unwrap();
+            assert_eq!(drivers.len(), 1);
+            assert_eq!(drivers[0].name, "Loopback");
+            assert_eq!(drivers[0].clsid, registration.clsid);
+            assert_eq!(drivers[0].description, "Virtual \"Loopback\" Device");
+            assert_eq!(drivers[0].dll_path, Some(registration.dll_path.clone()));
+            let threading = registry.string_value(
+                Hive::ClassesRoot,
+                "CLSID\\{6B3C3A5E-0D2F-4C1A-9F5E-8A1D2B3C4D5E}\\InprocServer32",
+                Some("ThreadingModel"),
+            );
+            assert_eq!(threading, Ok(Some(String::from("Apartment"))));
+        }
+    }
+
+    #[test]
+    fn uninstall_removes_only_the_driver() {
+        let registration = registration();
+        let fixture = include_str!("../tests/fixtures/asio_drivers.reg");
+        let mut registry = RegFile::parse(fixture).unwrap();
+        let before = drivers_from(&registry).unwrap();
+        apply_to(&mut registry, &registration.install_ops().unwrap()).unwrap();
+        assert_eq!(drivers_from(&registry).unwrap().len(), before.len() + 1);
+        apply_to(&mut registry, &registration.uninstall_ops().unwrap()).unwrap();
+        assert_eq!(drivers_from(&registry).unwrap(), before);
+
+        // The same through a script appended to the export
+        let script = reg_script(&registration.uninstall_ops().unwrap());
+        let combined = format!(
+            "{}{}",
+            reg_script(&registration.install_ops().unwrap()),
+            script.trim_start_matches("Windows Registry Editor Version 5.00\r\n")
+        );
+        let registry = RegFile::parse(&combined).unwrap();
+        assert_eq!(drivers_from(&registry).unwrap(), Vec::new());
+        assert!(script.contains("[-HKEY_LOCAL_MACHINE\\SOFTWARE\\ASIO\\Loopback]\r\n"));
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_one_key() {
+        for name in ["", " ", "Loopback\\..\\Other"] {
+            let registration = DriverRegistration {
+                name: String::from(name),
+                ..registration()
+            };
+            let invalid = Err(RegistrationError::InvalidName(String::from(name)));
+            assert_eq!(registration.install_ops(), invalid);
+            assert_eq!(registration.uninstall_ops(), invalid);
+        }
+    }
+}
@@ -0,0 +1,1097 @@
+//! Enumeration of the installed ASIO drivers.
+//!
+//! Drivers register a subkey of `HKLM\SOFTWARE\ASIO` holding their `CLSID` and
+//! an optional `Description`. The DLL implementing the class is found under
+//! `HKCR\CLSID\{...}\InprocServer32`. Registry access goes through
+//! [`RegistryReader`] and [`RegistryWriter`] so the resolution logic also runs
+//! against exported `.reg` files via [`RegFile`].
+
+use std::path::PathBuf;
+
+use crate::GUID;
+
+pub const ASIO_KEY: &str = "SOFTWARE\\ASIO";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Hive {
+    LocalMachine,
+    ClassesRoot,
+}
+
+impl Hive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hive::LocalMachine => "HKEY_LOCAL_MACHINE",
+            Hive::ClassesRoot => "HKEY_CLASSES_ROOT",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    NotFound { hive: Hive, path: String },
+    Os { code: u32, path: String },
+    Parse { line: usize, message: String },
+    Unsupported,
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::NotFound { hive, path } => {
+                write!(f, "registry key {}\\{} not found", hive.name(), path)
+            }
+            RegistryError::Os { code, path } => {
+                write!(
+                    f,
+                    "accessing registry key {} failed with error {}",
+                    path, code
+                )
+            }
+            RegistryError::Parse { line, message } => {
+                write!(f, "invalid .reg file at line {}: {}", line, message)
+            }
+            RegistryError::Unsupported => f.write_str("the registry is only available on Windows"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Read access to the parts of the registry needed to locate drivers.
+pub trait RegistryReader {
+    /// Names of the direct subkeys of `path`, in enumeration order.
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>, RegistryError>;
+    /// String value `name` of the key at `path`, `None` selecting the default
+    /// value. Missing keys and values, and values that are not strings, yield
+    /// `Ok(None)`.
+    fn string_value(
+        &self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+    ) -> Result<Option<String>, RegistryError>;
+}
+
+/// Write access, as needed to register drivers.
+pub trait RegistryWriter {
+    /// Sets string value `name` of the key at `path`, creating the key and
+    /// its parents. `None` selects the default value.
+    fn set_string(
+        &mut self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+        value: &str,
+    ) -> Result<(), RegistryError>;
+    /// Deletes the key at `path` with all its subkeys. A missing key is not an
+    /// error.
+    fn delete_key(&mut self, hive: Hive, path: &str) -> Result<(), RegistryError>;
+}
+
+/// A single change to the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryOp {
+    SetString {
+        hive: Hive,
+        path: String,
+        /// `None` for the key's default value.
+        name: Option<String>,
+        value: String,
+    },
+    DeleteKey {
+        hive: Hive,
+        path: String,
+    },
+}
+
+impl std::fmt::Display for RegistryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryOp::SetString {
+                hive,
+                path,
+                name,
+                value,
+            } => write!(
+                f,
+                "set {}\\{} {} = \"{}\"",
+                hive.name(),
+                path,
+                name.as_deref().unwrap_or("@"),
+                value
+            ),
+            RegistryOp::DeleteKey { hive, path } => {
+                write!(f, "delete {}\\{}", hive.name(), path)
+            }
+        }
+    }
+}
+
+/// Applies `ops` to the registry of this machine, stopping at the first
+/// failure.
+#[cfg(windows)]
+pub fn apply(ops: &[RegistryOp]) -> Result<(), RegistryError> {
+    apply_to(&mut SystemRegistry, ops)
+}
+
+#[cfg(not(windows))]
+pub fn apply(_ops: &[RegistryOp]) -> Result<(), RegistryError> {
+    Err(RegistryError::Unsupported)
+}
+
+/// Applies `ops` in order, stopping at the first failure.
+pub fn apply_to<W: RegistryWriter + ?Sized>(
+    registry: &mut W,
+    ops: &[RegistryOp],
+) -> Result<(), RegistryError> {
+    for op in ops {
+        match op {
+            RegistryOp::SetString {
+                hive,
+                path,
+                name,
+                value,
+            } => registry.set_string(*hive, path, name.as_deref(), value)?,
+            RegistryOp::DeleteKey { hive, path } => registry.delete_key(*hive, path)?,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverDescriptor {
+    /// Name of the driver's key below `HKLM\SOFTWARE\ASIO`.
+    pub name: String,
+    pub clsid: GUID,
+    /// The `Description` value, falling back to the key name.
+    pub description: String,
+    /// `None` if the class has no `InprocServer32` entry.
+    pub dll_path: Option<PathBuf>,
+}
+
+/// Lists the drivers registered on this machine.
+#[cfg(windows)]
+pub fn drivers() -> Result<Vec<DriverDescriptor>, RegistryError> {
+    drivers_from(&SystemRegistry)
+}
+
+#[cfg(not(windows))]
+pub fn drivers() -> Result<Vec<DriverDescriptor>, RegistryError> {
+    Err(RegistryError::Unsupported)
+}
+
+/// Lists the drivers registered in `registry`. Entries without a valid
+/// `CLSID` value cannot be instantiated and are skipped.
+pub fn drivers_from<R: RegistryReader + ?Sized>(
+    registry: &R,
+) -> Result<Vec<DriverDescriptor>, RegistryError> {
+    let names = match registry.subkeys(Hive::LocalMachine, ASIO_KEY) {
+        Ok(names) => names,
+        Err(RegistryError::NotFound { .. }) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut drivers = Vec::with_capacity(names.len());
+    for name in names {
+        let key = format!("{}\\{}", ASIO_KEY, name);
+        let clsid = match registry
+            .string_value(Hive::LocalMachine, &key, Some("CLSID"))?
+            .as_deref()
+            .and_then(parse_guid)
+        {
+            Some(clsid) => clsid,
+            None => continue,
+        };
+        let description = registry
+            .string_value(Hive::LocalMachine, &key, Some("Description"))?
+            .filter(|description| !description.is_empty())
+            .unwrap_or_else(|| name.clone());
+        let server_key = format!("CLSID\\{}\\InprocServer32", format_guid(&clsid));
+        let dll_path = registry
+            .string_value(Hive::ClassesRoot, &server_key, None)?
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        drivers.push(DriverDescriptor {
+            name,
+            clsid,
+            description,
+            dll_path,
+        });
+    }
+    Ok(drivers)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupError {
+    NotFound {
+        name: String,
+        candidates: Vec<String>,
+    },
+    Ambiguous {
+        name: String,
+        candidates: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::NotFound { name, candidates } => write!(
+                f,
+                "no ASIO driver named \"{}\", installed drivers: {}",
+                name,
+                list_candidates(candidates)
+            ),
+            LookupError::Ambiguous { name, candidates } => write!(
+                f,
+                "ASIO driver name \"{}\" is ambiguous, it matches: {}",
+                name,
+                list_candidates(candidates)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+fn list_candidates(candidates: &[String]) -> String {
+    if candidates.is_empty() {
+        String::from("(none)")
+    } else {
+        candidates
+            .iter()
+            .map(|candidate| format!("\"{}\"", candidate))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Failure to open a driver by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenError {
+    Registry(RegistryError),
+    Lookup(LookupError),
+    Com(String),
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenError::Registry(err) => err.fmt(f),
+            OpenError::Lookup(err) => err.fmt(f),
+            OpenError::Com(err) => write!(f, "instantiating the driver failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+impl From<RegistryError> for OpenError {
+    fn from(err: RegistryError) -> Self {
+        OpenError::Registry(err)
+    }
+}
+
+impl From<LookupError> for OpenError {
+    fn from(err: LookupError) -> Self {
+        OpenError::Lookup(err)
+    }
+}
+
+/// Picks the driver whose key name matches `name`.
+///
+/// An exact match wins over a case-insensitive one, which wins over a
+/// case-insensitive prefix match. Several matches at the deciding level make
+/// the name ambiguous. Surrounding whitespace is ignored and an empty name
+/// matches nothing.
+pub fn find_driver<'a>(
+    drivers: &'a [DriverDescriptor],
+    name: &str,
+) -> Result<&'a DriverDescriptor, LookupError> {
+    let name = name.trim();
+    let not_found = || LookupError::NotFound {
+        name: name.to_string(),
+        candidates: drivers.iter().map(|d| d.name.clone()).collect(),
+    };
+    if name.is_empty() {
+        return Err(not_found());
+    }
+    let lower = name.to_lowercase();
+    let matchers: [&dyn Fn(&DriverDescriptor) -> bool; 3] = [
+        &|driver| driver.name == name,
+        &|driver| driver.name.to_lowercase() == lower,
+        &|driver| driver.name.to_lowercase().starts_with(&lower),
+    ];
+    for matcher in matchers {
+        let matches: Vec<&DriverDescriptor> = drivers.iter().filter(|d| matcher(d)).collect();
+        match matches.as_slice() {
+            [] => continue,
+            [driver] => return Ok(driver),
+            _ => {
+                return Err(LookupError::Ambiguous {
+                    name: name.to_string(),
+                    candidates: matches.iter().map(|d| d.name.clone()).collect(),
+                })
+            }
+        }
+    }
+    Err(not_found())
+}
+
+/// Parses a GUID in registry form, with or without braces.
+pub fn parse_guid(text: &str) -> Option<GUID> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .unwrap_or(text);
+    let groups: Vec<&str> = text.split('-').collect();
+    if groups.len() != 5
+        || groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .any(|(group, len)| group.len() != len)
+    {
+        return None;
+    }
+    let digits = groups.concat();
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(&digits, 16).ok().map(GUID::from_u128)
+}
+
+/// Formats a GUID in registry form, e.g. `{232685C6-6548-49D8-846D-4141A3EF7560}`.
+pub fn format_guid(guid: &GUID) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+        guid.data1,
+        guid.data2,
+        guid.data3,
+        guid.data4[0],
+        guid.data4[1],
+        guid.data4[2],
+        guid.data4[3],
+        guid.data4[4],
+        guid.data4[5],
+        guid.data4[6],
+        guid.data4[7]
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RegValue {
+    String(String),
+    Dword(u32),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct RegKey {
+    hive: Hive,
+    path: String,
+    values: Vec<(Option<String>, RegValue)>,
+}
+
+/// Registry contents loaded from an exported `.reg` file.
+///
+/// Keys below `HKEY_LOCAL_MACHINE\SOFTWARE\Classes` are also visible through
+/// `HKEY_CLASSES_ROOT`, as they are on a live system.
+#[derive(Debug, Clone, Default)]
+pub struct RegFile {
+    keys: Vec<RegKey>,
+}
+
+impl RegFile {
+    /// Parses the raw file, which regedit writes as UTF-16LE with a BOM.
+    pub fn from_bytes(bytes: &[u8]) -> Result<RegFile, RegistryError> {
+        let text = match bytes {
+            [0xff, 0xfe, rest @ ..] => {
+                let units: Vec<u16> = rest
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+            _ => String::from_utf8_lossy(bytes).into_owned(),
+        };
+        RegFile::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<RegFile, RegistryError> {
+        let mut file = RegFile::default();
+        let mut current: Option<usize> = None;
+        let mut lines = text.lines().enumerate();
+        let mut header_seen = false;
+        while let Some((index, line)) = lines.next() {
+            let line_number = index + 1;
+            let mut line = line.trim().to_string();
+            while line.ends_with('\\') {
+                line.pop();
+                match lines.next() {
+                    Some((_, next)) => line.push_str(next.trim()),
+                    None => break,
+                }
+            }
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if !header_seen {
+                if line != "Windows Registry Editor Version 5.00" && line != "REGEDIT4" {
+                    return Err(parse_error(line_number, "missing registry editor header"));
+                }
+                header_seen = true;
+                continue;
+            }
+            if let Some(key) = line.strip_prefix('[') {
+                let key = key
+                    .strip_suffix(']')
+                    .ok_or_else(|| parse_error(line_number, "unterminated key"))?;
+                let (deleted, key) = match key.strip_prefix('-') {
+                    Some(key) => (true, key),
+                    None => (false, key),
+                };
+                let (hive, path) = split_key(key)
+                    .ok_or_else(|| parse_error(line_number, "unsupported registry hive"))?;
+                current = if deleted {
+                    file.remove_tree(hive, &path);
+                    None
+                } else {
+                    Some(file.key_index(hive, &path))
+                };
+                continue;
+            }
+            let key = current.ok_or_else(|| parse_error(line_number, "value outside of a key"))?;
+            let (name, value) = parse_value_line(&line)
+                .ok_or_else(|| parse_error(line_number, "malformed value"))?;
+            let values = &mut file.keys[key].values;
+            values.retain(|(existing, _)| !names_equal(existing.as_deref(), name.as_deref()));
+            if let Some(value) = value {
+                values.push((name, value));
+            }
+        }
+        Ok(file)
+    }
+
+    fn key_index(&mut self, hive: Hive, path: &str) -> usize {
+        if let Some(index) = self.find(hive, path) {
+            return index;
+        }
+        self.keys.push(RegKey {
+            hive,
+            path: path.to_string(),
+            values: Vec::new(),
+        });
+        self.keys.len() - 1
+    }
+
+    fn find(&self, hive: Hive, path: &str) -> Option<usize> {
+        self.keys
+            .iter()
+            .position(|key| key.hive == hive && key.path.eq_ignore_ascii_case(path))
+    }
+
+    fn remove_tree(&mut self, hive: Hive, path: &str) {
+        let prefix = format!("{}\\", path.to_ascii_lowercase());
+        self.keys.retain(|key| {
+            let lower = key.path.to_ascii_lowercase();
+            key.hive != hive || (lower != prefix[..prefix.len() - 1] && !lower.starts_with(&prefix))
+        });
+    }
+}
+
+impl RegistryReader for RegFile {
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>, RegistryError> {
+        let prefix = format!("{}\\", path.to_ascii_lowercase());
+        let mut found = false;
+        let mut names: Vec<String> = Vec::new();
+        for key in self.keys.iter().filter(|key| key.hive == hive) {
+            let lower = key.path.to_ascii_lowercase();
+            if lower == prefix[..prefix.len() - 1] {
+                found = true;
+            } else if lower.starts_with(&prefix) {
+                found = true;
+                let name = key.path[prefix.len()..].split('\\').next().unwrap_or("");
+                if !names
+                    .iter()
+                    .any(|existing| existing.eq_ignore_ascii_case(name))
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        if found {
+            Ok(names)
+        } else {
+            Err(RegistryError::NotFound {
+                hive,
+                path: path.to_string(),
+            })
+        }
+    }
+
+    fn string_value(
+        &self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+    ) -> Result<Option<String>, RegistryError> {
+        let index = match self.find(hive, path) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        Ok(self.keys[index]
+            .values
+            .iter()
+            .find(|(existing, _)| names_equal(existing.as_deref(), name))
+            .and_then(|(_, value)| match value {
+                RegValue::String(text) => Some(text.clone()),
+                _ => None,
+            }))
+    }
+}
+
+impl RegistryWriter for RegFile {
+    fn set_string(
+        &mut self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+        value: &str,
+    ) -> Result<(), RegistryError> {
+        // Parent keys come into existence too, as they do on a live system
+        let mut parent = String::new();
+        for part in path.split('\\') {
+            if !parent.is_empty() {
+                parent.push('\\');
+            }
+            parent.push_str(part);
+            self.key_index(hive, &parent);
+        }
+        let index = self.key_index(hive, path);
+        let values = &mut self.keys[index].values;
+        values.retain(|(existing, _)| !names_equal(existing.as_deref(), name));
+        values.push((
+            name.map(str::to_string),
+            RegValue::String(value.to_string()),
+        ));
+        Ok(())
+    }
+
+    fn delete_key(&mut self, hive: Hive, path: &str) -> Result<(), RegistryError> {
+        self.remove_tree(hive, path);
+        Ok(())
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> RegistryError {
+    RegistryError::Parse {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn names_equal(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+fn split_key(key: &str) -> Option<(Hive, String)> {
+    let (root, path) = key.split_once('\\').unwrap_or((key, ""));
+    let hive = match root.to_ascii_uppercase().as_str() {
+        "HKEY_LOCAL_MACHINE" | "HKLM" => Hive::LocalMachine,
+        "HKEY_CLASSES_ROOT" | "HKCR" => Hive::ClassesRoot,
+        _ => return None,
+    };
+    // HKCR is a merged view of HKLM\SOFTWARE\Classes
+    const CLASSES: &str = "software\\classes";
+    let lower = path.to_ascii_lowercase();
+    if hive == Hive::LocalMachine && lower.starts_with(CLASSES) {
+        let rest = &path[CLASSES.len()..];
+        if rest.is_empty() || rest.starts_with('\\') {
+            return Some((Hive::ClassesRoot, rest.trim_start_matches('\\').to_string()));
+        }
+    }
+    Some((hive, path.to_string()))
+}
+
+// Returns the value name (`None` for `@`) and the value, `None` for a deletion
+fn parse_value_line(line: &str) -> Option<(Option<String>, Option<RegValue>)> {
+    let (name, rest) = if let Some(rest) = line.strip_prefix('@') {
+        (None, rest)
+    } else {
+        let (name, rest) = parse_quoted(line)?;
+        (Some(name), rest)
+    };
+    let data = rest.trim_start().strip_prefix('=')?.trim();
+    if data == "-" {
+        return Some((name, None));
+    }
+    let value = if data.starts_with('"') {
+        let (text, rest) = parse_quoted(data)?;
+        if !rest.trim().is_empty() {
+            return None;
+        }
+        RegValue::String(text)
+    } else if let Some(hex) = data.strip_prefix("dword:") {
+        RegValue::Dword(u32::from_str_radix(hex.trim(), 16).ok()?)
+    } else if let Some(rest) = data.strip_prefix("hex") {
+        let (kind, bytes) = rest.split_once(':')?;
+        let bytes = parse_hex_bytes(bytes)?;
+        match kind {
+            // REG_EXPAND_SZ and REG_SZ stored as UTF-16LE
+            "(2)" | "(1)" => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .take_while(|unit| *unit != 0)
+                    .collect();
+                RegValue::String(String::from_utf16_lossy(&units))
+            }
+            _ => RegValue::Binary(bytes),
+        }
+    } else {
+        return None;
+    };
+    Some((name, Some(value)))
+}
+
+fn parse_quoted(text: &str) -> Option<(String, &str)> {
+    let body = text.strip_prefix('"')?;
+    let mut out = String::new();
+    let mut chars = body.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next()?.1),
+            '"' => return Some((out, &body[index + 1..])),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect()
+}
+
+/// The live registry of this machine.
+#[cfg(windows)]
+pub struct SystemRegistry;
+
+#[cfg(windows)]
+impl SystemRegistry {
+    fn open(
+        hive: Hive,
+        path: &str,
+    ) -> Result<windows::Win32::System::Registry::HKEY, RegistryError> {
+        use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, NO_ERROR};
+        use windows::Win32::System::Registry::{RegOpenKeyExW, HKEY, KEY_READ};
+        let path_w = to_wide(path);
+        let mut key = HKEY::default();
+        let status = unsafe {
+            RegOpenKeyExW(
+                hive_handle(hive),
+                windows::core::PCWSTR::from_raw(path_w.as_ptr()),
+                0,
+                KEY_READ,
+                &mut key,
+            )
+        };
+        match status {
+            NO_ERROR => Ok(key),
+            ERROR_FILE_NOT_FOUND => Err(RegistryError::NotFound {
+                hive,
+                path: path.to_string(),
+            }),
+            status => Err(RegistryError::Os {
+                code: status.0,
+                path: path.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl RegistryReader for SystemRegistry {
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>, RegistryError> {
+        use windows::Win32::Foundation::{ERROR_NO_MORE_ITEMS, NO_ERROR};
+        use windows::Win32::System::Registry::{RegCloseKey, RegEnumKeyExW};
+        let key = SystemRegistry::open(hive, path)?;
+        let mut names = Vec::new();
+        let mut result = Ok(());
+        for index in 0.. {
+            // Key names are limited to 255 characters
+            let mut name = [0u16; 256];
+            let mut len = name.len() as u32;
+            let status = unsafe {
+                RegEnumKeyExW(
+                    key,
+                    index,
+                    windows::core::PWSTR::from_raw(name.as_mut_ptr()),
+                    &mut len,
+                    None,
+                    windows::core::PWSTR::null(),
+                    None,
+                    None,
+                )
+            };
+            match status {
+                NO_ERROR => names.push(String::from_utf16_lossy(&name[..len as usize])),
+                ERROR_NO_MORE_ITEMS => break,
+                status => {
+                    result = Err(RegistryError::Os {
+                        code: status.0,
+                        path: path.to_string(),
+                    });
+                    break;
+                }
+            }
+        }
+        unsafe { RegCloseKey(key) };
+        result.map(|()| names)
+    }
+
+    fn string_value(
+        &self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+    ) -> Result<Option<String>, RegistryError> {
+        use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_UNSUPPORTED_TYPE, NO_ERROR};
+        use windows::Win32::System::Registry::{RegGetValueW, RRF_RT_REG_SZ};
+        let path_w = to_wide(path);
+        let name_w = name.map(to_wide);
+        let name_ptr = name_w
+            .as_ref()
+            .map_or(std::ptr::null(), |name| name.as_ptr());
+        let query = |data: Option<*mut std::ffi::c_void>, size: &mut u32| unsafe {
+            // REG_EXPAND_SZ values are expanded and returned as REG_SZ
+            RegGetValueW(
+                hive_handle(hive),
+                windows::core::PCWSTR::from_raw(path_w.as_ptr()),
+                windows::core::PCWSTR::from_raw(name_ptr),
+                RRF_RT_REG_SZ,
+                None,
+                data,
+                Some(size),
+            )
+        };
+        let mut size = 0u32;
+        match query(None, &mut size) {
+            NO_ERROR => {}
+            // Missing, or not a string value
+            ERROR_FILE_NOT_FOUND | ERROR_UNSUPPORTED_TYPE => return Ok(None),
+            status => {
+                return Err(RegistryError::Os {
+                    code: status.0,
+                    path: path.to_string(),
+                })
+            }
+        }
+        let mut data = vec![0u16; (size as usize).div_ceil(2)];
+        match query(Some(data.as_mut_ptr().cast()), &mut size) {
+            NO_ERROR => {}
+            ERROR_FILE_NOT_FOUND | ERROR_UNSUPPORTED_TYPE => return Ok(None),
+            status => {
+                return Err(RegistryError::Os {
+                    code: status.0,
+                    path: path.to_string(),
+                })
+            }
+        }
+        data.truncate(size as usize / 2);
+        while data.last() == Some(&0) {
+            data.pop();
+        }
+        Ok(Some(String::from_utf16_lossy(&data)))
+    }
+}
+
+#[cfg(windows)]
+impl RegistryWriter for SystemRegistry {
+    fn set_string(
+        &mut self,
+        hive: Hive,
+        path: &str,
+        name: Option<&str>,
+        value: &str,
+    ) -> Result<(), RegistryError> {
+        use windows::Win32::Foundation::NO_ERROR;
+        use windows::Win32::System::Registry::{
+            RegCloseKey, RegCreateKeyW, RegSetValueExW, HKEY, REG_SZ,
+        };
+        let path_w = to_wide(path);
+        let mut key = HKEY::default();
+        let status = unsafe {
+            RegCreateKeyW(
+                hive_handle(hive),
+                windows::core::PCWSTR::from_raw(path_w.as_ptr()),
+                &mut key,
+            )
+        };
+        if status != NO_ERROR {
+            return Err(RegistryError::Os {
+                code: status.0,
+                path: path.to_string(),
+            });
+        }
+        let name_w = name.map(to_wide);
+        let name_ptr = name_w
+            .as_ref()
+            .map_or(std::ptr::null(), |name| name.as_ptr());
+        let data: Vec<u8> = to_wide(value)
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let status = unsafe {
+            RegSetValueExW(
+                key,
+                windows::core::PCWSTR::from_raw(name_ptr),
+                0,
+                REG_SZ,
+                Some(&data),
+            )
+        };
+        unsafe { RegCloseKey(key) };
+        match status {
+            NO_ERROR => Ok(()),
+            status => Err(RegistryError::Os {
+                code: status.0,
+                path: path.to_string(),
+            }),
+        }
+    }
+
+    fn delete_key(&mut self, hive: Hive, path: &str) -> Result<(), RegistryError> {
+        use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, NO_ERROR};
+        use windows::Win32::System::Registry::RegDeleteTreeW;
+        let path_w = to_wide(path);
+        let status = unsafe {
+            RegDeleteTreeW(
+                hive_handle(hive),
+                windows::core::PCWSTR::from_raw(path_w.as_ptr()),
+            )
+        };
+        match status {
+            NO_ERROR | ERROR_FILE_NOT_FOUND => Ok(()),
+            status => Err(RegistryError::Os {
+                code: status.0,
+                path: path.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn hive_handle(hive: Hive) -> windows::Win32::System::Registry::HKEY {
+    match hive {
+        Hive::LocalMachine => windows::Win32::System::Registry::HKEY_LOCAL_MACHINE,
+        Hive::ClassesRoot => windows::Win32::System::Registry::HKEY_CLASSES_ROOT,
+    }
+}
+
+#[cfg(windows)]
+fn to_wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/asio_drivers.reg");
+
+    fn fixture() -> RegFile {
+        RegFile::parse(FIXTURE).unwrap()
+    }
+
+    #[test]
+    fn lists_drivers_in_registry_order() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        let names: Vec<&str> = drivers.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["ASIO4ALL v2", "FL Studio ASIO", "Realtek ASIO"]);
+    }
+
+    #[test]
+    fn resolves_clsid_description_and_dll() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        let asio4all = &drivers[0];
+        assert_eq!(
+            asio4all.clsid,
+            GUID::from_u128(0x232685C6_6548_49D8_846D_4141A3EF7560)
+        );
+        assert_eq!(asio4all.description, "ASIO4ALL v2");
+        assert_eq!(
+            asio4all.dll_path,
+            Some(PathBuf::from(
+                "C:\\Program Files (x86)\\ASIO4ALL v2\\asio4all64.dll"
+            ))
+        );
+    }
+
+    #[test]
+    fn description_falls_back_to_key_name() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        assert_eq!(drivers[1].description, "FL Studio ASIO");
+    }
+
+    #[test]
+    fn classes_below_hklm_software_classes_resolve() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        assert_eq!(
+            drivers[1].dll_path,
+            Some(PathBuf::from(
+                "C:\\Program Files\\Image-Line\\FL Studio ASIO\\ILWASAPI2ASIO_x64.dll"
+            ))
+        );
+    }
+
+    #[test]
+    fn expand_sz_paths_and_missing_servers() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        let realtek = &drivers[2];
+        assert_eq!(realtek.description, "Realtek ASIO");
+        assert_eq!(
+            realtek.dll_path,
+            Some(PathBuf::from("%SystemRoot%\\System32\\RtkAsio64.dll"))
+        );
+    }
+
+    #[test]
+    fn entries_without_clsid_are_skipped() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        assert!(drivers.iter().all(|d| d.name != "Broken Driver"));
+    }
+
+    #[test]
+    fn utf16_export_matches_utf8() {
+        let mut bytes = vec![0xff, 0xfe];
+        for unit in FIXTURE.encode_utf16() {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        let from_utf16 = drivers_from(&RegFile::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(from_utf16, drivers_from(&fixture()).unwrap());
+    }
+
+    #[test]
+    fn missing_asio_key_means_no_drivers() {
+        let file = RegFile::parse("Windows Registry Editor Version 5.00\r\n").unwrap();
+        assert_eq!(drivers_from(&file).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn rejects_files_without_header() {
+        let err = RegFile::parse("[HKEY_LOCAL_MACHINE\\SOFTWARE\\ASIO]\n").unwrap_err();
+        assert!(matches!(err, RegistryError::Parse { line: 1, .. }));
+    }
+
+    #[test]
+    fn find_prefers_exact_then_case_insensitive_then_prefix() {
+        let mut drivers = drivers_from(&fixture()).unwrap();
+        assert_eq!(
+            find_driver(&drivers, "Realtek ASIO").unwrap().name,
+            "Realtek ASIO"
+        );
+        assert_eq!(
+            find_driver(&drivers, "asio4all V2").unwrap().name,
+            "ASIO4ALL v2"
+        );
+        assert_eq!(
+            find_driver(&drivers, "fl stu").unwrap().name,
+            "FL Studio ASIO"
+        );
+
+        let mut shadow = drivers[0].clone();
+        shadow.name = String::from("asio4all v2");
+        drivers.push(shadow);
+        assert_eq!(
+            find_driver(&drivers, "ASIO4ALL v2").unwrap().name,
+            "ASIO4ALL v2"
+        );
+        assert_eq!(
+            find_driver(&drivers, "asio4all v2").unwrap().name,
+            "asio4all v2"
+        );
+    }
+
+    #[test]
+    fn find_reports_ambiguous_names() {
+        let mut drivers = drivers_from(&fixture()).unwrap();
+        let mut other = drivers[2].clone();
+        other.name = String::from("Realtek ASIO (Secondary)");
+        drivers.push(other);
+        let err = find_driver(&drivers, "realtek").unwrap_err();
+        assert_eq!(
+            err,
+            LookupError::Ambiguous {
+                name: String::from("realtek"),
+                candidates: vec![
+                    String::from("Realtek ASIO"),
+                    String::from("Realtek ASIO (Secondary)")
+                ],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "ASIO driver name \"realtek\" is ambiguous, it matches: \"Realtek ASIO\", \"Realtek ASIO (Secondary)\""
+        );
+    }
+
+    #[test]
+    fn find_lists_installed_drivers_when_not_found() {
+        let drivers = drivers_from(&fixture()).unwrap();
+        let err = find_driver(&drivers, "Focusrite USB ASIO").unwrap_err();
+        assert_eq!(
+            err,
+            LookupError::NotFound {
+                name: String::from("Focusrite USB ASIO"),
+                candidates: vec![
+                    String::from("ASIO4ALL v2"),
+                    String::from("FL Studio ASIO"),
+                    String::from("Realtek ASIO")
+                ],
+            }
+        );
+        assert!(matches!(
+            find_driver(&[], "ASIO4ALL v2"),
+            Err(LookupError::NotFound { candidates, .. }) if candidates.is_empty()
+        ));
+
+        // An empty name would otherwise prefix-match every driver
+        let single = &drivers[..1];
+        for name in ["", "  \t"] {
+            assert!(matches!(
+                find_driver(single, name),
+                Err(LookupError::NotFound { name, .. }) if name.is_empty()
+            ));
+        }
+        assert_eq!(
+            find_driver(single, " asio4all ").unwrap().name,
+            "ASIO4ALL v2"
+        );
+    }
+
+    #[test]
+    fn guid_round_trip() {
+        let text = "{A80362FF-CE76-4DD9-874A-704C57BF0D6A}";
+        let guid = parse_guid(text).unwrap();
+        assert_eq!(
+            guid,
+            GUID::from_u128(0xA80362FF_CE76_4DD9_874A_704C57BF0D6A)
+        );
+        assert_eq!(format_guid(&guid), text);
+        assert_eq!(
+            parse_guid("a80362ff-ce76-4dd9-874a-704c57bf0d6a"),
+            Some(guid)
+        );
+        assert_eq!(parse_guid("{A80362FF-CE76-4DD9-874A}"), None);
+    }
+}
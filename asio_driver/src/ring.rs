@@ -0,0 +1,82 @@
+//! Wait-free single producer, single consumer queue for handing values out
+//! of the audio thread.
+//!
+//! The producer never blocks or allocates, when the queue is full the value
+//! is counted as dropped instead.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Next slot to read, only advanced by the consumer
+    head: AtomicUsize,
+    // Next slot to write, only advanced by the producer
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+// A slot is only accessed by the side the indices currently hand it to
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+/// Creates a queue holding up to `capacity` values, rounded up to a power
+/// of two.
+pub(crate) fn ring<T: Copy + Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+pub(crate) struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Copy + Send> Producer<T> {
+    /// Returns `false` and counts the value as dropped if the queue is full.
+    pub(crate) fn push(&mut self, value: T) -> bool {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.slots.len() {
+            ring.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let slot = &ring.slots[tail & (ring.slots.len() - 1)];
+        // Safety: the consumer is done with the slot, it was released through `head`
+        unsafe { (*slot.get()).write(value) };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+pub(crate) struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Copy + Send> Consumer<T> {
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let slot = &ring.slots[head & (ring.slots.len() - 1)];
+        // Safety: the producer initialized the slot before publishing it through `tail`
+        let value = unsafe { (*slot.get()).assume_init() };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.ring.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,1029 @@
+//! Implementing ASIO drivers in Rust.
+//!
+//! A type implementing [`AsioDriverImpl`] is exposed through a generated
+//! `AsioDriverVtbl`. [`DriverObject`] creates such objects in-process, where
+//! they are driven through the same [`AsioDriver`] wrapper hosts use, and
+//! [`asio_driver_dll!`](crate::asio_driver_dll) emits the `IClassFactory` and
+//! the `Dll*` exports needed to ship the driver as a COM in-process server.
+//!
+//! Calls may arrive from any thread, so implementations take `&self` and keep
+//! their state behind their own locks. Host callbacks must not be invoked while
+//! holding a lock the host could need, as hosts call back into the driver from
+//! within them. A panic escaping a method is caught at the vtable boundary and
+//! reported as `AsioError::HwMalfunction`.
+
+use std::ffi::{c_char, c_void};
+use std::panic::AssertUnwindSafe;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use windows::core::{ComInterface, IUnknown, IUnknown_Vtbl, Interface};
+use windows::Win32::Foundation::{
+    BOOL, CLASS_E_CLASSNOTAVAILABLE, CLASS_E_NOAGGREGATION, E_FAIL, E_NOINTERFACE, E_POINTER,
+    S_FALSE, S_OK,
+};
+use windows::Win32::System::Com::{IClassFactory, IClassFactory_Vtbl};
+
+#[cfg(windows)]
+use crate::registration::DriverRegistration;
+use crate::{
+    AsioBool, AsioBufferInfo, AsioCallbacks, AsioChannelInfo, AsioClockSource, AsioDriver,
+    AsioDriverVtbl, AsioError, AsioMessageSelector, AsioSampleRate, AsioSamples, AsioTime,
+    AsioTimestamp, BufferSizeInfo, ChannelCounts, Latencies, SamplePosition, GUID,
+};
+
+pub use windows::core::HRESULT;
+
+/// The driver side of `AsioDriverVtbl`.
+///
+/// Methods mirror the vtable entries with out-parameters turned into return
+/// values. `Ok(())` is reported to the host as `AsioError::Ok`.
+pub trait AsioDriverImpl: Send + Sync + 'static {
+    /// Returns `false` if the hardware cannot be opened, leaving the reason in
+    /// [`AsioDriverImpl::error_message`].
+    fn init(&self, sys_handle: *mut c_void) -> bool;
+    /// Truncated to 31 bytes.
+    fn driver_name(&self) -> String;
+    fn driver_version(&self) -> i32;
+    /// Truncated to 123 bytes.
+    fn error_message(&self) -> String {
+        String::new()
+    }
+
+    fn start(&self) -> Result<(), AsioError>;
+    fn stop(&self) -> Result<(), AsioError>;
+
+    fn get_channels(&self) -> Result<ChannelCounts, AsioError>;
+    fn get_latencies(&self) -> Result<Latencies, AsioError>;
+    fn get_buffer_size(&self) -> Result<BufferSizeInfo, AsioError>;
+
+    fn can_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError>;
+    fn get_sample_rate(&self) -> Result<AsioSampleRate, AsioError>;
+    fn set_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError>;
+
+    /// Fills the start of `clocks` and returns the number of entries written.
+    fn get_clock_sources(&self, clocks: &mut [AsioClockSource]) -> Result<usize, AsioError>;
+    fn set_clock_source(&self, reference: i32) -> Result<(), AsioError>;
+
+    fn get_sample_position(&self) -> Result<SamplePosition, AsioError>;
+    fn get_channel_info(&self, channel: i32, is_input: bool) -> Result<AsioChannelInfo, AsioError>;
+
+    /// Stores the buffer halves in `infos`. They and `callbacks` must remain
+    /// valid until [`AsioDriverImpl::dispose_buffers`].
+    fn create_buffers(
+        &self,
+        infos: &mut [AsioBufferInfo],
+        buffer_size: i32,
+        callbacks: HostCallbacks,
+    ) -> Result<(), AsioError>;
+    fn dispose_buffers(&self) -> Result<(), AsioError>;
+
+    fn control_panel(&self) -> Result<(), AsioError> {
+        Err(AsioError::NotPresent)
+    }
+
+    /// Answers `CanXXX` selectors with `AsioError::Success`.
+    ///
+    /// # Safety
+    ///
+    /// `opt` points to the structure the host passed for `selector`, or is null.
+    unsafe fn future(&self, _selector: i32, _opt: *mut c_void) -> AsioError {
+        AsioError::NotPresent
+    }
+
+    fn output_ready(&self) -> Result<(), AsioError> {
+        Err(AsioError::NotPresent)
+    }
+}
+
+/// The host's `AsioCallbacks`, as handed to [`AsioDriverImpl::create_buffers`].
+///
+/// Copies the function pointers, the host only keeps its table alive until
+/// `dispose_buffers`.
+#[derive(Clone, Copy)]
+pub struct HostCallbacks {
+    buffer_switch: unsafe extern "C" fn(i32, AsioBool),
+    sample_rate_did_change: unsafe extern "C" fn(AsioSampleRate),
+    asio_message: unsafe extern "C" fn(AsioMessageSelector, i32, *mut c_void, *mut f64) -> i32,
+    buffer_switch_time_info: unsafe extern "C" fn(*mut AsioTime, i32, AsioBool) -> *mut AsioTime,
+}
+
+impl HostCallbacks {
+    pub(crate) fn new(callbacks: &AsioCallbacks) -> HostCallbacks {
+        HostCallbacks {
+            buffer_switch: callbacks.buffer_switch,
+            sample_rate_did_change: callbacks.sample_rate_did_change,
+            asio_message: callbacks.asio_message,
+            buffer_switch_time_info: callbacks.buffer_switch_time_info,
+        }
+    }
+    pub fn buffer_switch(&self, double_buffer_idx: i32, direct_process: bool) {
+        unsafe { (self.buffer_switch)(double_buffer_idx, AsioBool::from(direct_process)) }
+    }
+    pub fn buffer_switch_time_info(
+        &self,
+        time: &mut AsioTime,
+        double_buffer_idx: i32,
+        direct_process: bool,
+    ) {
+        unsafe {
+            (self.buffer_switch_time_info)(time, double_buffer_idx, AsioBool::from(direct_process))
+        };
+    }
+    pub fn sample_rate_did_change(&self, sample_rate: AsioSampleRate) {
+        unsafe { (self.sample_rate_did_change)(sample_rate) }
+    }
+    /// Sends a message without `message` and `opt` arguments.
+    pub fn message(&self, selector: AsioMessageSelector, value: i32) -> i32 {
+        unsafe { self.asio_message(selector, value, std::ptr::null_mut(), std::ptr::null_mut()) }
+    }
+    /// # Safety
+    ///
+    /// `message` and `opt` must be what the host expects for `selector`.
+    pub unsafe fn asio_message(
+        &self,
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut c_void,
+        opt: *mut f64,
+    ) -> i32 {
+        (self.asio_message)(selector, value, message, opt)
+    }
+    /// Asks whether the host handles `selector`, as drivers must before
+    /// sending anything but `SelectorSupported` and `EngineVersion`.
+    pub fn supports(&self, selector: AsioMessageSelector) -> bool {
+        self.message(AsioMessageSelector::SelectorSupported, selector as i32) == 1
+    }
+}
+
+// Live objects and server locks, the DLL may only be unloaded at zero
+static SERVER_REFS: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle to a driver object, holding one reference to it.
+pub struct DriverObject<T: AsioDriverImpl> {
+    object: NonNull<Object<T>>,
+}
+
+// The object is reference counted atomically and `T` is `Send + Sync`
+unsafe impl<T: AsioDriverImpl> Send for DriverObject<T> {}
+unsafe impl<T: AsioDriverImpl> Sync for DriverObject<T> {}
+
+impl<T: AsioDriverImpl> DriverObject<T> {
+    /// Wraps `driver` in a COM object answering every interface ID, like
+    /// ASIO drivers do for their own CLSID.
+    pub fn new(driver: T) -> DriverObject<T> {
+        DriverObject {
+            object: Object::create(driver, None),
+        }
+    }
+
+    /// A new reference to the object, as `CoCreateInstance` returns it.
+    pub fn driver(&self) -> AsioDriver {
+        // `AsioDriver` uninitializes COM when dropped
+        #[cfg(windows)]
+        unsafe {
+            let _ = windows::Win32::System::Com::CoInitialize(None);
+        }
+        let raw = self.object.as_ptr().cast::<c_void>();
+        unsafe {
+            add_ref::<T>(raw);
+            AsioDriver::from_raw(raw)
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &unsafe { self.object.as_ref() }.driver
+    }
+
+    /// Number of outstanding references, including this handle's.
+    pub fn ref_count(&self) -> u32 {
+        unsafe { self.object.as_ref() }.refs.load(Ordering::Acquire)
+    }
+}
+
+impl<T: AsioDriverImpl> Drop for DriverObject<T> {
+    fn drop(&mut self) {
+        unsafe { release::<T>(self.object.as_ptr().cast()) };
+    }
+}
+
+#[repr(C)]
+struct Object<T> {
+    vtable: &'static AsioDriverVtbl,
+    refs: AtomicU32,
+    // `None` answers every interface ID
+    clsid: Option<GUID>,
+    driver: T,
+}
+
+impl<T: AsioDriverImpl> Object<T> {
+    const VTABLE: AsioDriverVtbl = AsioDriverVtbl {
+        base__: IUnknown_Vtbl {
+            QueryInterface: query_interface::<T>,
+            AddRef: add_ref::<T>,
+            Release: release::<T>,
+        },
+        init: init::<T>,
+        get_driver_name: get_driver_name::<T>,
+        get_driver_version: get_driver_version::<T>,
+        get_error_message: get_error_message::<T>,
+        start: start::<T>,
+        stop: stop::<T>,
+        get_channels: get_channels::<T>,
+        get_latencies: get_latencies::<T>,
+        get_buffer_size: get_buffer_size::<T>,
+        can_sample_rate: can_sample_rate::<T>,
+        get_sample_rate: get_sample_rate::<T>,
+        set_sample_rate: set_sample_rate::<T>,
+        get_clock_sources: get_clock_sources::<T>,
+        set_clock_source: set_clock_source::<T>,
+        get_sample_position: get_sample_position::<T>,
+        get_channel_info: get_channel_info::<T>,
+        create_buffers: create_buffers::<T>,
+        dispose_buffers: dispose_buffers::<T>,
+        control_panel: control_panel::<T>,
+        future: future::<T>,
+        output_ready: output_ready::<T>,
+    };
+
+    fn create(driver: T, clsid: Option<GUID>) -> NonNull<Object<T>> {
+        SERVER_REFS.fetch_add(1, Ordering::Relaxed);
+        let object = Box::new(Object {
+            vtable: &Self::VTABLE,
+            refs: AtomicU32::new(1),
+            clsid,
+            driver,
+        });
+        NonNull::from(Box::leak(object))
+    }
+}
+
+// Truncates to the buffer and always nul-terminates
+pub(crate) fn write_c_str(dst: &mut [c_char], value: &str) {
+    let len = value.len().min(dst.len() - 1);
+    for (dst, src) in dst.iter_mut().zip(&value.as_bytes()[..len]) {
+        *dst = *src as c_char;
+    }
+    dst[len] = 0;
+}
+
+// Unwinding out of an `extern "system"` function aborts the host
+fn guard<R>(fallback: R, f: impl FnOnce() -> R) -> R {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+fn status(f: impl FnOnce() -> Result<(), AsioError>) -> AsioError {
+    guard(AsioError::HwMalfunction, || match f() {
+        Ok(()) => AsioError::Ok,
+        Err(error) => error,
+    })
+}
+
+unsafe fn driver<'a, T>(this: *mut c_void) -> &'a T {
+    &(*this.cast::<Object<T>>()).driver
+}
+
+unsafe extern "system" fn query_interface<T: AsioDriverImpl>(
+    this: *mut c_void,
+    iid: &GUID,
+    interface: *mut *const c_void,
+) -> HRESULT {
+    if interface.is_null() {
+        return E_POINTER;
+    }
+    // ASIO drivers use their CLSID as IID
+    let known = match (*this.cast::<Object<T>>()).clsid {
+        Some(clsid) => *iid == clsid || *iid == IUnknown::IID,
+        None => true,
+    };
+    if !known {
+        *interface = std::ptr::null();
+        return E_NOINTERFACE;
+    }
+    add_ref::<T>(this);
+    *interface = this;
+    S_OK
+}
+
+unsafe extern "system" fn add_ref<T: AsioDriverImpl>(this: *mut c_void) -> u32 {
+    (*this.cast::<Object<T>>())
+        .refs
+        .fetch_add(1, Ordering::Relaxed)
+        + 1
+}
+
+unsafe extern "system" fn release<T: AsioDriverImpl>(this: *mut c_void) -> u32 {
+    let refs = (*this.cast::<Object<T>>())
+        .refs
+        .fetch_sub(1, Ordering::Release)
+        - 1;
+    if refs == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this.cast::<Object<T>>()));
+        SERVER_REFS.fetch_sub(1, Ordering::Relaxed);
+    }
+    refs
+}
+
+unsafe extern "system" fn init<T: AsioDriverImpl>(
+    this: *mut c_void,
+    sys_handle: *mut c_void,
+) -> AsioBool {
+    guard(AsioBool::False, || {
+        AsioBool::from(driver::<T>(this).init(sys_handle))
+    })
+}
+
+unsafe extern "system" fn get_driver_name<T: AsioDriverImpl>(this: *mut c_void, name: *mut c_char) {
+    if name.is_null() {
+        return;
+    }
+    let name = std::slice::from_raw_parts_mut(name, 32);
+    let value = guard(String::new(), || driver::<T>(this).driver_name());
+    write_c_str(name, &value);
+}
+
+unsafe extern "system" fn get_driver_version<T: AsioDriverImpl>(this: *mut c_void) -> i32 {
+    guard(0, || driver::<T>(this).driver_version())
+}
+
+unsafe extern "system" fn get_error_message<T: AsioDriverImpl>(
+    this: *mut c_void,
+    message: *mut c_char,
+) {
+    if message.is_null() {
+        return;
+    }
+    let message = std::slice::from_raw_parts_mut(message, 124);
+    let value = guard(String::new(), || driver::<T>(this).error_message());
+    write_c_str(message, &value);
+}
+
+unsafe extern "system" fn start<T: AsioDriverImpl>(this: *mut c_void) -> AsioError {
+    status(|| driver::<T>(this).start())
+}
+
+unsafe extern "system" fn stop<T: AsioDriverImpl>(this: *mut c_void) -> AsioError {
+    status(|| driver::<T>(this).stop())
+}
+
+unsafe extern "system" fn get_channels<T: AsioDriverImpl>(
+    this: *mut c_void,
+    inputs: *mut i32,
+    outputs: *mut i32,
+) -> AsioError {
+    let (Some(inputs), Some(outputs)) = (inputs.as_mut(), outputs.as_mut()) else {
+        return AsioError::InvalidParameter;
+    };
+    status(|| {
+        let counts = driver::<T>(this).get_channels()?;
+        (*inputs, *outputs) = (counts.inputs, counts.outputs);
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn get_latencies<T: AsioDriverImpl>(
+    this: *mut c_void,
+    input: *mut i32,
+    output: *mut i32,
+) -> AsioError {
+    let (Some(input), Some(output)) = (input.as_mut(), output.as_mut()) else {
+        return AsioError::InvalidParameter;
+    };
+    status(|| {
+        let latencies = driver::<T>(this).get_latencies()?;
+        (*input, *output) = (latencies.input, latencies.output);
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn get_buffer_size<T: AsioDriverImpl>(
+    this: *mut c_void,
+    min: *mut i32,
+    max: *mut i32,
+    preferred: *mut i32,
+    granularity: *mut i32,
+) -> AsioError {
+    if min.is_null() || max.is_null() || preferred.is_null() || granularity.is_null() {
+        return AsioError::InvalidParameter;
+    }
+    status(|| {
+        let info = driver::<T>(this).get_buffer_size()?;
+        (*min, *max, *preferred, *granularity) =
+            (info.min, info.max, info.preferred, info.granularity);
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn can_sample_rate<T: AsioDriverImpl>(
+    this: *mut c_void,
+    sample_rate: AsioSampleRate,
+) -> AsioError {
+    status(|| driver::<T>(this).can_sample_rate(sample_rate))
+}
+
+unsafe extern "system" fn get_sample_rate<T: AsioDriverImpl>(
+    this: *mut c_void,
+    sample_rate: *mut AsioSampleRate,
+) -> AsioError {
+    let Some(sample_rate) = sample_rate.as_mut() else {
+        return AsioError::InvalidParameter;
+    };
+    status(|| {
+        *sample_rate = driver::<T>(this).get_sample_rate()?;
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn set_sample_rate<T: AsioDriverImpl>(
+    this: *mut c_void,
+    sample_rate: AsioSampleRate,
+) -> AsioError {
+    status(|| driver::<T>(this).set_sample_rate(sample_rate))
+}
+
+unsafe extern "system" fn get_clock_sources<T: AsioDriverImpl>(
+    this: *mut c_void,
+    clocks: *mut AsioClockSource,
+    num_sources: *mut i32,
+) -> AsioError {
+    if clocks.is_null() || num_sources.is_null() || *num_sources < 0 {
+        return AsioError::InvalidParameter;
+    }
+    let clocks = std::slice::from_raw_parts_mut(clocks, *num_sources as usize);
+    status(|| {
+        let written = driver::<T>(this).get_clock_sources(clocks)?;
+        *num_sources = written.min(clocks.len()) as i32;
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn set_clock_source<T: AsioDriverImpl>(
+    this: *mut c_void,
+    reference: i32,
+) -> AsioError {
+    status(|| driver::<T>(this).set_clock_source(reference))
+}
+
+unsafe extern "system" fn get_sample_position<T: AsioDriverImpl>(
+    this: *mut c_void,
+    samples: *mut AsioSamples,
+    timestamp: *mut AsioTimestamp,
+) -> AsioError {
+    let (Some(samples), Some(timestamp)) = (samples.as_mut(), timestamp.as_mut()) else {
+        return AsioError::InvalidParameter;
+    };
+    status(|| {
+        let position = driver::<T>(this).get_sample_position()?;
+        (*samples, *timestamp) = (position.samples.into(), position.timestamp.into());
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn get_channel_info<T: AsioDriverImpl>(
+    this: *mut c_void,
+    info: *mut AsioChannelInfo,
+) -> AsioError {
+    let Some(info) = info.as_mut() else {
+        return AsioError::InvalidParameter;
+    };
+    status(|| {
+        *info = driver::<T>(this).get_channel_info(info.channel, info.is_input.to_bool())?;
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn create_buffers<T: AsioDriverImpl>(
+    this: *mut c_void,
+    infos: *mut AsioBufferInfo,
+    num_channels: i32,
+    buffer_size: i32,
+    callbacks: *mut AsioCallbacks,
+) -> AsioError {
+    if infos.is_null() || num_channels <= 0 || callbacks.is_null() {
+        return AsioError::InvalidParameter;
+    }
+    let infos = std::slice::from_raw_parts_mut(infos, num_channels as usize);
+    let callbacks = HostCallbacks::new(&*callbacks);
+    status(|| driver::<T>(this).create_buffers(infos, buffer_size, callbacks))
+}
+
+unsafe extern "system" fn dispose_buffers<T: AsioDriverImpl>(this: *mut c_void) -> AsioError {
+    status(|| driver::<T>(this).dispose_buffers())
+}
+
+unsafe extern "system" fn control_panel<T: AsioDriverImpl>(this: *mut c_void) -> AsioError {
+    status(|| driver::<T>(this).control_panel())
+}
+
+unsafe extern "system" fn future<T: AsioDriverImpl>(
+    this: *mut c_void,
+    selector: i32,
+    opt: *mut c_void,
+) -> AsioError {
+    guard(AsioError::HwMalfunction, || {
+        driver::<T>(this).future(selector, opt)
+    })
+}
+
+unsafe extern "system" fn output_ready<T: AsioDriverImpl>(this: *mut c_void) -> AsioError {
+    status(|| driver::<T>(this).output_ready())
+}
+
+#[repr(C)]
+struct ClassFactory<T> {
+    vtable: &'static IClassFactory_Vtbl,
+    refs: AtomicU32,
+    clsid: GUID,
+    create: fn() -> T,
+}
+
+impl<T: AsioDriverImpl> ClassFactory<T> {
+    const VTABLE: IClassFactory_Vtbl = IClassFactory_Vtbl {
+        base__: IUnknown_Vtbl {
+            QueryInterface: factory_query_interface::<T>,
+            AddRef: factory_add_ref::<T>,
+            Release: factory_release::<T>,
+        },
+        CreateInstance: factory_create_instance::<T>,
+        LockServer: factory_lock_server,
+    };
+}
+
+unsafe extern "system" fn factory_query_interface<T: AsioDriverImpl>(
+    this: *mut c_void,
+    iid: &GUID,
+    interface: *mut *const c_void,
+) -> HRESULT {
+    if interface.is_null() {
+        return E_POINTER;
+    }
+    if *iid != IUnknown::IID && *iid != IClassFactory::IID {
+        *interface = std::ptr::null();
+        return E_NOINTERFACE;
+    }
+    factory_add_ref::<T>(this);
+    *interface = this;
+    S_OK
+}
+
+unsafe extern "system" fn factory_add_ref<T: AsioDriverImpl>(this: *mut c_void) -> u32 {
+    (*this.cast::<ClassFactory<T>>())
+        .refs
+        .fetch_add(1, Ordering::Relaxed)
+        + 1
+}
+
+unsafe extern "system" fn factory_release<T: AsioDriverImpl>(this: *mut c_void) -> u32 {
+    let refs = (*this.cast::<ClassFactory<T>>())
+        .refs
+        .fetch_sub(1, Ordering::Release)
+        - 1;
+    if refs == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this.cast::<ClassFactory<T>>()));
+        SERVER_REFS.fetch_sub(1, Ordering::Relaxed);
+    }
+    refs
+}
+
+unsafe extern "system" fn factory_create_instance<T: AsioDriverImpl>(
+    this: *mut c_void,
+    outer: *mut c_void,
+    iid: *const GUID,
+    interface: *mut *mut c_void,
+) -> HRESULT {
+    if interface.is_null() || iid.is_null() {
+        return E_POINTER;
+    }
+    *interface = std::ptr::null_mut();
+    if !outer.is_null() {
+        return CLASS_E_NOAGGREGATION;
+    }
+    let factory = &*this.cast::<ClassFactory<T>>();
+    let Some(driver) = guard(None, || Some((factory.create)())) else {
+        return E_FAIL;
+    };
+    let object = Object::create(driver, Some(factory.clsid)).as_ptr().cast();
+    let result = query_interface::<T>(object, &*iid, interface.cast());
+    release::<T>(object);
+    result
+}
+
+unsafe extern "system" fn factory_lock_server(_this: *mut c_void, lock: BOOL) -> HRESULT {
+    if lock.as_bool() {
+        SERVER_REFS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        SERVER_REFS.fetch_sub(1, Ordering::Relaxed);
+    }
+    S_OK
+}
+
+/// Body of `DllGetClassObject`, handing out a class factory creating drivers
+/// with `create` for `clsid`.
+pub unsafe fn get_class_object<T: AsioDriverImpl>(
+    clsid: &GUID,
+    create: fn() -> T,
+    rclsid: *const GUID,
+    iid: *const GUID,
+    interface: *mut *mut c_void,
+) -> HRESULT {
+    if rclsid.is_null() || iid.is_null() || interface.is_null() {
+        return E_POINTER;
+    }
+    *interface = std::ptr::null_mut();
+    if *rclsid != *clsid {
+        return CLASS_E_CLASSNOTAVAILABLE;
+    }
+    SERVER_REFS.fetch_add(1, Ordering::Relaxed);
+    let factory = Box::new(ClassFactory {
+        vtable: &ClassFactory::<T>::VTABLE,
+        refs: AtomicU32::new(1),
+        clsid: *clsid,
+        create,
+    });
+    let factory = Box::into_raw(factory).cast();
+    let result = factory_query_interface::<T>(factory, &*iid, interface.cast());
+    factory_release::<T>(factory);
+    result
+}
+
+/// Body of `DllCanUnloadNow`.
+pub fn can_unload_now() -> HRESULT {
+    if SERVER_REFS.load(Ordering::Acquire) == 0 {
+        S_OK
+    } else {
+        S_FALSE
+    }
+}
+
+/// Body of `DllRegisterServer`, registering the calling DLL for `clsid` and
+/// as ASIO driver `name`.
+#[cfg(windows)]
+pub fn register_server(clsid: &GUID, name: &str, description: &str) -> HRESULT {
+    let Some(path) = module_path() else {
+        return E_FAIL;
+    };
+    let registration = DriverRegistration {
+        description: description.to_string(),
+        ..DriverRegistration::new(name, *clsid, path)
+    };
+    let Ok(ops) = registration.install_ops() else {
+        return windows::Win32::Foundation::E_INVALIDARG;
+    };
+    registry_result(crate::registry::apply(&ops))
+}
+
+/// Body of `DllUnregisterServer`.
+#[cfg(windows)]
+pub fn unregister_server(clsid: &GUID, name: &str) -> HRESULT {
+    let registration = DriverRegistration::new(name, *clsid, std::path::PathBuf::new());
+    let Ok(ops) = registration.uninstall_ops() else {
+        return windows::Win32::Foundation::E_INVALIDARG;
+    };
+    registry_result(crate::registry::apply(&ops))
+}
+
+// Registration writes to the Windows registry
+#[cfg(not(windows))]
+pub fn register_server(_clsid: &GUID, _name: &str, _description: &str) -> HRESULT {
+    windows::Win32::Foundation::E_NOTIMPL
+}
+
+#[cfg(not(windows))]
+pub fn unregister_server(_clsid: &GUID, _name: &str) -> HRESULT {
+    windows::Win32::Foundation::E_NOTIMPL
+}
+
+#[cfg(windows)]
+fn registry_result(result: Result<(), crate::registry::RegistryError>) -> HRESULT {
+    use crate::registry::RegistryError;
+    match result {
+        Ok(()) => S_OK,
+        Err(RegistryError::Os { code, .. }) => {
+            windows::Win32::Foundation::WIN32_ERROR(code).to_hresult()
+        }
+        Err(_) => E_FAIL,
+    }
+}
+
+// Path of the DLL containing this code, not of the host executable
+#[cfg(windows)]
+fn module_path() -> Option<std::path::PathBuf> {
+    windows_targets::link!("kernel32.dll" "system" fn GetModuleHandleExW(flags: u32, name: *const u16, module: *mut isize) -> BOOL);
+    windows_targets::link!("kernel32.dll" "system" fn GetModuleFileNameW(module: isize, name: *mut u16, size: u32) -> u32);
+    const FROM_ADDRESS: u32 = 0x4;
+    const UNCHANGED_REFCOUNT: u32 = 0x2;
+    let mut module = 0;
+    let address = module_path as fn() -> Option<std::path::PathBuf> as *const u16;
+    let found =
+        unsafe { GetModuleHandleExW(FROM_ADDRESS | UNCHANGED_REFCOUNT, address, &mut module) };
+    if !found.as_bool() {
+        return None;
+    }
+    let mut path = vec![0u16; 32768];
+    let len = unsafe { GetModuleFileNameW(module, path.as_mut_ptr(), path.len() as u32) };
+    (len > 0).then(|| String::from_utf16_lossy(&path[..len as usize]).into())
+}
+
+/// Exports `DllGetClassObject`, `DllCanUnloadNow`, `DllRegisterServer` and
+/// `DllUnregisterServer` for a `cdylib` implementing one ASIO driver.
+///
+/// ```ignore
+/// asio_driver::asio_driver_dll! {
+///     clsid: GUID::from_u128(0x6b3c3a5e_0d2f_4c1a_9f5e_8a1d2b3c4d5e),
+///     name: "My Virtual Device",
+///     description: "My Virtual Device (ASIO)",
+///     create: MyDriver::new,
+/// }
+/// ```
+#[macro_export]
+macro_rules! asio_driver_dll {
+    (
+        clsid: $clsid:expr,
+        name: $name:expr,
+        description: $description:expr,
+        create: $create:expr $(,)?
+    ) => {
+        const ASIO_DRIVER_CLSID: $crate::GUID = $clsid;
+
+        #[no_mangle]
+        pub unsafe extern "system" fn DllGetClassObject(
+            rclsid: *const $crate::GUID,
+            riid: *const $crate::GUID,
+            ppv: *mut *mut ::std::ffi::c_void,
+        ) -> $crate::server::HRESULT {
+            $crate::server::get_class_object(&ASIO_DRIVER_CLSID, $create, rclsid, riid, ppv)
+        }
+
+        #[no_mangle]
+        pub extern "system" fn DllCanUnloadNow() -> $crate::server::HRESULT {
+            $crate::server::can_unload_now()
+        }
+
+        #[no_mangle]
+        pub extern "system" fn DllRegisterServer() -> $crate::server::HRESULT {
+            $crate::server::register_server(&ASIO_DRIVER_CLSID, $name, $description)
+        }
+
+        #[no_mangle]
+        pub extern "system" fn DllUnregisterServer() -> $crate::server::HRESULT {
+            $crate::server::unregister_server(&ASIO_DRIVER_CLSID, $name)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Driver;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    const CLSID: GUID = GUID::from_u128(0x5f1c2d3e_4b5a_4c6d_8e7f_90a1b2c3d4e5);
+
+    // Just enough of a driver to see the calls come through
+    #[derive(Default)]
+    struct Minimal {
+        initialized: AtomicBool,
+        dropped: Option<Arc<AtomicBool>>,
+    }
+
+    impl Drop for Minimal {
+        fn drop(&mut self) {
+            if let Some(dropped) = &self.dropped {
+                dropped.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    impl AsioDriverImpl for Minimal {
+        fn init(&self, _sys_handle: *mut c_void) -> bool {
+            self.initialized.store(true, Ordering::Release);
+            true
+        }
+        fn driver_name(&self) -> String {
+            String::from("A driver name well beyond thirty-one bytes")
+        }
+        fn driver_version(&self) -> i32 {
+            7
+        }
+        fn start(&self) -> Result<(), AsioError> {
+            panic!("start is broken");
+        }
+        fn stop(&self) -> Result<(), AsioError> {
+            Ok(())
+        }
+        fn get_channels(&self) -> Result<ChannelCounts, AsioError> {
+            if !self.initialized.load(Ordering::Acquire) {
+                return Err(AsioError::NotPresent);
+            }
+            Ok(ChannelCounts {
+                inputs: 1,
+                outputs: 2,
+            })
+        }
+        fn get_latencies(&self) -> Result<Latencies, AsioError> {
+            Ok(Latencies {
+                input: 64,
+                output: 128,
+            })
+        }
+        fn get_buffer_size(&self) -> Result<BufferSizeInfo, AsioError> {
+            Ok(BufferSizeInfo {
+                min: 64,
+                max: 64,
+                preferred: 64,
+                granularity: 0,
+            })
+        }
+        fn can_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+            match sample_rate == 48000.0 {
+                true => Ok(()),
+                false => Err(AsioError::NoClock),
+            }
+        }
+        fn get_sample_rate(&self) -> Result<AsioSampleRate, AsioError> {
+            Ok(48000.0)
+        }
+        fn set_sample_rate(&self, sample_rate: AsioSampleRate) -> Result<(), AsioError> {
+            self.can_sample_rate(sample_rate)
+        }
+        fn get_clock_sources(&self, clocks: &mut [AsioClockSource]) -> Result<usize, AsioError> {
+            // Claims more than fit, the glue clamps the count
+            for (index, clock) in clocks.iter_mut().enumerate() {
+                clock.index = index as i32;
+            }
+            Ok(100)
+        }
+        fn set_clock_source(&self, _reference: i32) -> Result<(), AsioError> {
+            Err(AsioError::InvalidMode)
+        }
+        fn get_sample_position(&self) -> Result<SamplePosition, AsioError> {
+            Err(AsioError::SpNotAdvancing)
+        }
+        fn get_channel_info(
+            &self,
+            channel: i32,
+            is_input: bool,
+        ) -> Result<AsioChannelInfo, AsioError> {
+            let mut info = AsioChannelInfo::new(channel, is_input);
+            info.sample_type = crate::AsioSampleType::AsioSTFloat32LSB;
+            Ok(info)
+        }
+        fn create_buffers(
+            &self,
+            _infos: &mut [AsioBufferInfo],
+            _buffer_size: i32,
+            _callbacks: HostCallbacks,
+        ) -> Result<(), AsioError> {
+            Err(AsioError::NoMemory)
+        }
+        fn dispose_buffers(&self) -> Result<(), AsioError> {
+            Err(AsioError::InvalidMode)
+        }
+        unsafe fn future(&self, selector: i32, _opt: *mut c_void) -> AsioError {
+            match selector == crate::AsioFutureSelector::CanTimeInfo as i32 {
+                true => AsioError::Success,
+                false => AsioError::NotPresent,
+            }
+        }
+    }
+
+    crate::asio_driver_dll! {
+        clsid: CLSID,
+        name: "Minimal",
+        description: "Minimal test driver",
+        create: Minimal::default,
+    }
+
+    #[test]
+    fn calls_reach_the_implementation() {
+        let object = DriverObject::new(Minimal::default());
+        let driver = Driver::from_raw(object.driver());
+        assert_eq!(driver.get_channels(), Err(AsioError::NotPresent));
+        unsafe { driver.init(std::ptr::null_mut()) }.unwrap();
+        assert!(object.get().initialized.load(Ordering::Acquire));
+        assert_eq!(driver.driver_name(), "A driver name well beyond thirt");
+        assert_eq!(driver.driver_version(), 7);
+        assert_eq!(driver.error_message(), "");
+        assert_eq!(driver.get_channels().unwrap().total(), 3);
+        assert_eq!(driver.get_latencies().unwrap().output, 128);
+        assert_eq!(driver.get_buffer_size().unwrap().granularity, 0);
+        assert_eq!(driver.set_sample_rate(44100.0), Err(AsioError::NoClock));
+        assert_eq!(driver.get_sample_rate(), Ok(48000.0));
+        assert_eq!(driver.get_clock_sources().unwrap().length, 16);
+        assert_eq!(driver.set_clock_source(0), Err(AsioError::InvalidMode));
+        assert_eq!(driver.get_sample_position(), Err(AsioError::SpNotAdvancing));
+        let info = driver.output_channel_info(1).unwrap();
+        assert_eq!((info.channel, info.is_input), (1, AsioBool::False));
+        assert_eq!(info.sample_type, crate::AsioSampleType::AsioSTFloat32LSB);
+        assert!(driver.can_time_info());
+        assert!(!driver.can_transport());
+        assert_eq!(driver.control_panel(), Err(AsioError::NotPresent));
+        assert_eq!(driver.output_ready(), Err(AsioError::NotPresent));
+        let mut infos = [AsioBufferInfo::new_output(0)];
+        let raw = driver.raw();
+        unsafe {
+            assert_eq!(
+                raw.create_buffers(infos.as_mut_ptr(), 1, 64, std::ptr::null_mut()),
+                AsioError::InvalidParameter
+            );
+            assert_eq!(
+                raw.get_channels(std::ptr::null_mut(), std::ptr::null_mut()),
+                { AsioError::InvalidParameter }
+            );
+        }
+    }
+
+    #[test]
+    fn panics_are_reported_as_malfunction() {
+        let object = DriverObject::new(Minimal::default());
+        let driver = object.driver();
+        assert_eq!(unsafe { driver.start() }, AsioError::HwMalfunction);
+        assert_eq!(unsafe { driver.stop() }, AsioError::Ok);
+    }
+
+    #[test]
+    fn releases_the_implementation_with_the_last_reference() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let object = DriverObject::new(Minimal {
+            initialized: AtomicBool::new(false),
+            dropped: Some(dropped.clone()),
+        });
+        let driver = object.driver();
+        let copy = driver.clone();
+        assert_eq!(object.ref_count(), 3);
+        drop(object);
+        drop(driver);
+        assert!(!dropped.load(Ordering::Acquire));
+        drop(copy);
+        assert!(dropped.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn class_factory_creates_drivers() {
+        let other = GUID::from_u128(1);
+        let mut factory = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                DllGetClassObject(&other, &IClassFactory::IID, &mut factory),
+                CLASS_E_CLASSNOTAVAILABLE
+            );
+            assert!(factory.is_null());
+            assert_eq!(
+                DllGetClassObject(&CLSID, &CLSID, &mut factory),
+                E_NOINTERFACE
+            );
+            assert_eq!(
+                DllGetClassObject(&CLSID, &IClassFactory::IID, &mut factory),
+                S_OK
+            );
+        }
+        let factory = unsafe { IClassFactory::from_raw(factory) };
+        assert_eq!(DllCanUnloadNow(), S_FALSE);
+        let create = factory.vtable().CreateInstance;
+        let mut raw = std::ptr::null_mut();
+        unsafe {
+            let outer = factory.as_raw();
+            assert_eq!(
+                create(factory.as_raw(), outer, &CLSID, &mut raw),
+                CLASS_E_NOAGGREGATION
+            );
+            assert_eq!(
+                create(factory.as_raw(), std::ptr::null_mut(), &other, &mut raw),
+                E_NOINTERFACE
+            );
+            assert!(raw.is_null());
+            assert_eq!(
+                create(factory.as_raw(), std::ptr::null_mut(), &CLSID, &mut raw),
+                S_OK
+            );
+        }
+        drop(factory);
+        let driver = Driver::from_raw(unsafe { AsioDriver::from_raw(raw) });
+        unsafe { driver.init(std::ptr::null_mut()) }.unwrap();
+        assert_eq!(driver.get_channels().unwrap().outputs, 2);
+        // Objects created for a CLSID only answer to it and IUnknown
+        let unknown = &driver.raw().vtable().base__;
+        for (iid, expected) in [(IUnknown::IID, S_OK), (CLSID, S_OK), (other, E_NOINTERFACE)] {
+            let mut interface = std::ptr::null();
+            let this = driver.raw().as_raw();
+            assert_eq!(
+                unsafe { (unknown.QueryInterface)(this, &iid, &mut interface) },
+                expected
+            );
+            if expected == S_OK {
+                assert_eq!(unsafe { (unknown.Release)(this) }, 1);
+            } else {
+                assert!(interface.is_null());
+            }
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn registration_needs_windows() {
+        assert_eq!(DllRegisterServer(), windows::Win32::Foundation::E_NOTIMPL);
+    }
+}
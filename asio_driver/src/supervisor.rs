@@ -0,0 +1,404 @@
+//! Restarting the stream when the driver asks for a reset.
+//!
+//! Drivers send `ResetRequest` after their settings changed, typically from
+//! the control panel. The host then has to tear the stream down and bring it
+//! up again, which must not happen inside the callback. [`ResetSupervisor`]
+//! owns the running stream together with the [`EventReceiver`] fed by
+//! [`crate::HostMessages`] and performs the reset when the application polls
+//! it from its own thread, usually the one that created the driver.
+
+use std::ffi::c_void;
+
+use crate::callbacks::CallbackSlot;
+use crate::driver::InitError;
+use crate::lifecycle::{LoadedDriver, PreparedDriver, RunningDriver};
+use crate::messages::{EventReceiver, HostEvent};
+use crate::{AsioBufferInfo, AsioError, AsioSampleRate, Latencies};
+
+/// Stream parameters after a reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamFormat {
+    pub sample_rate: AsioSampleRate,
+    pub buffer_size: i32,
+    pub latencies: Latencies,
+}
+
+/// What [`ResetSupervisor::poll`] handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SupervisorEvent {
+    /// The stream was restarted.
+    Reset(StreamFormat),
+    /// Any other request, left to the application.
+    Host(HostEvent),
+}
+
+/// The step a reset failed at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResetError {
+    Stop(AsioError),
+    DisposeBuffers(AsioError),
+    Open(String),
+    Init(InitError),
+    /// Querying the new instance's preferred buffer size failed.
+    BufferSize(AsioError),
+    CreateBuffers(AsioError),
+    Start(AsioError),
+    /// The stream was not created with a [`CallbackSlot`], so there is no
+    /// handler to serve the new one.
+    NoHandler,
+}
+
+impl std::fmt::Display for ResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetError::Stop(error) => write!(f, "stopping the driver failed: {}", error),
+            ResetError::DisposeBuffers(error) => {
+                write!(f, "disposing the buffers failed: {}", error)
+            }
+            ResetError::Open(error) => write!(f, "reopening the driver failed: {}", error),
+            ResetError::Init(error) => write!(f, "initializing the driver failed: {}", error),
+            ResetError::BufferSize(error) => {
+                write!(f, "querying the buffer size failed: {}", error)
+            }
+            ResetError::CreateBuffers(error) => {
+                write!(f, "creating the buffers failed: {}", error)
+            }
+            ResetError::Start(error) => write!(f, "starting the driver failed: {}", error),
+            ResetError::NoHandler => f.write_str("the stream has no callback slot"),
+        }
+    }
+}
+
+impl std::error::Error for ResetError {}
+
+type OpenDriver = Box<dyn FnMut() -> Result<LoadedDriver, String>>;
+type PreparedHook = Box<dyn FnMut(&PreparedDriver)>;
+
+/// Owns a stream started with [`crate::InitializedDriver::create_buffers_with_slot`]
+/// and restarts it on `ResetRequest`.
+///
+/// A reset stops the stream, disposes the buffers, releases the driver,
+/// opens a new instance, initializes it and creates buffers for the same
+/// channels at the driver's now preferred size before starting again. The
+/// handler stays in its slot throughout. If a step fails after stopping, the
+/// stream stays down and [`ResetSupervisor::reset`] can be retried.
+pub struct ResetSupervisor {
+    // Dropped before the slot, the driver may call back until disposed
+    running: Option<RunningDriver>,
+    // Taken over from the first stream, later ones only borrow its callbacks
+    slot: Option<CallbackSlot>,
+    events: EventReceiver,
+    channels: Vec<(bool, i32)>,
+    open: OpenDriver,
+    sys_handle: *mut c_void,
+    prepared: Option<PreparedHook>,
+}
+
+impl ResetSupervisor {
+    /// `open` creates a new instance of the driver, e.g. through
+    /// [`LoadedDriver::new`] with the driver's CLSID.
+    ///
+    /// # Safety
+    ///
+    /// `sys_handle` must be null or the application's main window handle, it
+    /// is passed to `init` on every reset.
+    pub unsafe fn new(
+        running: RunningDriver,
+        events: EventReceiver,
+        sys_handle: *mut c_void,
+        open: impl FnMut() -> Result<LoadedDriver, String> + 'static,
+    ) -> ResetSupervisor {
+        let channels = running
+            .buffer_infos()
+            .iter()
+            .map(|info| (info.is_input.to_bool(), info.channel_num))
+            .collect();
+        ResetSupervisor {
+            running: Some(running),
+            slot: None,
+            events,
+            channels,
+            open: Box::new(open),
+            sys_handle,
+            prepared: None,
+        }
+    }
+
+    /// Called with the new buffers before the stream starts again, e.g. to
+    /// hand a fresh [`crate::BufferSet`] to the handler.
+    pub fn on_prepared(mut self, prepared: impl FnMut(&PreparedDriver) + 'static) -> Self {
+        self.prepared = Some(Box::new(prepared));
+        self
+    }
+
+    /// The running stream, `None` while a reset is incomplete.
+    pub fn driver(&self) -> Option<&RunningDriver> {
+        self.running.as_ref()
+    }
+
+    /// Handles the next queued event, resetting the stream on `ResetRequest`.
+    /// Returns `None` once the queue is empty.
+    ///
+    /// Must not be called from a driver callback.
+    pub fn poll(&mut self) -> Option<Result<SupervisorEvent, ResetError>> {
+        match self.events.pop()? {
+            HostEvent::ResetRequest => Some(self.reset().map(SupervisorEvent::Reset)),
+            event => Some(Ok(SupervisorEvent::Host(event))),
+        }
+    }
+
+    /// Tears the stream down and brings it up again.
+    ///
+    /// Fails with [`ResetError::NoHandler`] without touching the stream if it
+    /// was not created with a [`CallbackSlot`].
+    pub fn reset(&mut self) -> Result<StreamFormat, ResetError> {
+        let has_slot = self.running.as_ref().is_some_and(RunningDriver::has_slot);
+        if self.slot.is_none() && !has_slot {
+            return Err(ResetError::NoHandler);
+        }
+        if let Some(running) = self.running.take() {
+            self.tear_down(running)?;
+        }
+        let callbacks = match &self.slot {
+            Some(slot) => slot.callbacks(),
+            None => return Err(ResetError::NoHandler),
+        };
+        let loaded = (self.open)().map_err(ResetError::Open)?;
+        // Safety: guaranteed by the caller of `new`
+        let initialized =
+            unsafe { loaded.init(self.sys_handle) }.map_err(|err| ResetError::Init(err.error))?;
+        let buffer_size = initialized
+            .get_buffer_size()
+            .map_err(ResetError::BufferSize)?
+            .preferred;
+        let buffer_infos = self
+            .channels
+            .iter()
+            .map(|&(is_input, channel)| AsioBufferInfo::new(channel, is_input))
+            .collect();
+        let prepared = initialized
+            .create_buffers(buffer_infos, buffer_size, callbacks)
+            .map_err(|err| ResetError::CreateBuffers(err.error))?;
+        if let Some(hook) = &mut self.prepared {
+            hook(&prepared);
+        }
+        let format = StreamFormat {
+            sample_rate: prepared.get_sample_rate().unwrap_or(0.0),
+            buffer_size,
+            latencies: prepared.get_latencies().unwrap_or_default(),
+        };
+        let running = prepared
+            .start()
+            .map_err(|err| ResetError::Start(err.error))?;
+        self.running = Some(running);
+        Ok(format)
+    }
+
+    fn tear_down(&mut self, running: RunningDriver) -> Result<(), ResetError> {
+        let prepared = match running.stop() {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                self.running = Some(err.state);
+                return Err(ResetError::Stop(err.error));
+            }
+        };
+        // Dropping the driver releases it before the new instance is created
+        match prepared.dispose_buffers_with_slot() {
+            Ok((_driver, slot)) => {
+                if slot.is_some() {
+                    self.slot = slot;
+                }
+                Ok(())
+            }
+            // Dropping retries the disposal and keeps the callbacks if that fails
+            Err(err) => Err(ResetError::DisposeBuffers(err.error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::TEST_SLOT;
+    use crate::messages::{event_queue, HostCapabilities, HostMessages};
+    use crate::mock::{initialized, MockCall, MockConfig, MockDriver, MockEvent};
+    use crate::{AsioHandler, AsioMessageSelector, Driver};
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, PoisonError};
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct Host {
+        messages: HostMessages,
+        switches: Arc<AtomicUsize>,
+    }
+
+    impl AsioHandler for Host {
+        fn buffer_switch(&mut self, _double_buffer_idx: i32, _direct_process: bool) {
+            self.switches.fetch_add(1, Ordering::Relaxed);
+        }
+        fn asio_message(
+            &mut self,
+            selector: AsioMessageSelector,
+            value: i32,
+            message: *mut c_void,
+            opt: *mut f64,
+        ) -> i32 {
+            self.messages.handle(selector, value, message, opt)
+        }
+    }
+
+    fn supervised(mock: &Rc<MockDriver>, switches: Arc<AtomicUsize>) -> ResetSupervisor {
+        let (sender, events) = event_queue(8);
+        let capabilities = HostCapabilities::default()
+            .with(AsioMessageSelector::ResetRequest)
+            .with(AsioMessageSelector::Overload);
+        let host = Host {
+            messages: HostMessages::new(capabilities, sender),
+            switches,
+        };
+        let slot = CallbackSlot::acquire(host).unwrap();
+        let buffer_infos = vec![AsioBufferInfo::new_input(1), AsioBufferInfo::new_output(0)];
+        let running = initialized(mock)
+            .create_buffers_with_slot(buffer_infos, 64, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        let mock = mock.clone();
+        unsafe {
+            ResetSupervisor::new(running, events, std::ptr::null_mut(), move || {
+                Ok(LoadedDriver::from(Driver::from_raw(mock.driver())))
+            })
+        }
+    }
+
+    fn wait_for_event(supervisor: &mut ResetSupervisor) -> Result<SupervisorEvent, ResetError> {
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        loop {
+            if let Some(event) = supervisor.poll() {
+                return event;
+            }
+            assert!(std::time::Instant::now() < deadline, "no event arrived");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn reset_request_restarts_the_stream() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = Rc::new(MockDriver::new(MockConfig::small()));
+        let switches = Arc::new(AtomicUsize::new(0));
+        let prepared = Rc::new(AtomicUsize::new(0));
+        let seen = prepared.clone();
+        let mut supervisor = supervised(&mock, switches.clone()).on_prepared(move |driver| {
+            assert_eq!(driver.buffer_infos().len(), 2);
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(supervisor.driver().unwrap().buffer_size(), 64);
+        mock.schedule(
+            2,
+            MockEvent::Message {
+                selector: AsioMessageSelector::Overload,
+                value: 0,
+            },
+        );
+        mock.schedule(
+            3,
+            MockEvent::Message {
+                selector: AsioMessageSelector::ResetRequest,
+                value: 0,
+            },
+        );
+        assert_eq!(
+            wait_for_event(&mut supervisor),
+            Ok(SupervisorEvent::Host(HostEvent::Overload))
+        );
+        let format = StreamFormat {
+            sample_rate: 48000.0,
+            buffer_size: 32,
+            latencies: Latencies {
+                input: 256,
+                output: 512,
+            },
+        };
+        assert_eq!(
+            wait_for_event(&mut supervisor),
+            Ok(SupervisorEvent::Reset(format))
+        );
+        assert_eq!(prepared.load(Ordering::Relaxed), 1);
+        let running = supervisor.driver().unwrap();
+        assert_eq!(running.buffer_size(), 32);
+        let channels: Vec<_> = running
+            .buffer_infos()
+            .iter()
+            .map(|info| (info.is_input.to_bool(), info.channel_num))
+            .collect();
+        assert_eq!(channels, [(true, 1), (false, 0)]);
+        // The handler keeps receiving buffer switches from the new stream
+        let before = switches.load(Ordering::Relaxed);
+        assert!(mock.wait_for_buffer_switches(mock.status().buffer_switches + 3, TIMEOUT));
+        assert!(switches.load(Ordering::Relaxed) > before);
+        drop(supervisor);
+        assert!(!mock.status().running);
+        assert_eq!(mock.ref_count(), 1);
+    }
+
+    #[test]
+    fn failed_reset_can_be_retried() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = Rc::new(MockDriver::new(MockConfig::small()));
+        let mut supervisor = supervised(&mock, Arc::new(AtomicUsize::new(0)));
+        mock.fail_once(MockCall::Init, AsioError::HwMalfunction);
+        assert_eq!(
+            supervisor.reset(),
+            Err(ResetError::Init(InitError {
+                message: String::from("injected fault: hardware is malfunctioning")
+            }))
+        );
+        assert!(supervisor.driver().is_none());
+        assert!(!mock.status().buffers_created);
+        mock.fail_once(MockCall::GetBufferSize, AsioError::HwMalfunction);
+        assert_eq!(
+            supervisor.reset(),
+            Err(ResetError::BufferSize(AsioError::HwMalfunction))
+        );
+        mock.fail_once(MockCall::Start, AsioError::HwMalfunction);
+        assert_eq!(
+            supervisor.reset(),
+            Err(ResetError::Start(AsioError::HwMalfunction))
+        );
+        assert!(!mock.status().buffers_created);
+        assert_eq!(supervisor.reset().map(|format| format.buffer_size), Ok(32));
+        assert!(mock.status().running);
+    }
+
+    #[test]
+    fn reset_without_slot_keeps_the_stream() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = Rc::new(MockDriver::new(MockConfig::small()));
+        let (sender, events) = event_queue(8);
+        let host = Host {
+            messages: HostMessages::new(HostCapabilities::default(), sender),
+            switches: Arc::new(AtomicUsize::new(0)),
+        };
+        let slot = CallbackSlot::acquire(host).unwrap();
+        let running = initialized(&mock)
+            .create_buffers(vec![AsioBufferInfo::new_output(0)], 64, slot.callbacks())
+            .unwrap()
+            .start()
+            .unwrap();
+        let open_mock = mock.clone();
+        let mut supervisor = unsafe {
+            ResetSupervisor::new(running, events, std::ptr::null_mut(), move || {
+                Ok(LoadedDriver::from(Driver::from_raw(open_mock.driver())))
+            })
+        };
+        assert_eq!(supervisor.reset(), Err(ResetError::NoHandler));
+        assert!(mock.status().running);
+        assert_eq!(supervisor.driver().unwrap().buffer_size(), 64);
+        drop(supervisor);
+        drop(slot);
+    }
+}
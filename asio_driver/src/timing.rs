@@ -0,0 +1,344 @@
+//! Safe view of the timing information passed to `buffer_switch_time_info`
+//! and a clock relating sample positions to system time.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::{
+    AsioError, AsioSampleRate, AsioTime, AsioTimeCodeFlags, AsioTimeInfoFlags, Driver,
+    SamplePosition,
+};
+
+/// The valid parts of an [`AsioTime`], taken inside
+/// [`crate::AsioHandler::buffer_switch_time_info`].
+///
+/// Fields the driver did not mark valid are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimingSnapshot {
+    /// System time of the first sample in the buffer.
+    pub system_time: Option<Duration>,
+    /// Position of the first sample in the buffer.
+    pub sample_position: Option<u64>,
+    pub sample_rate: Option<AsioSampleRate>,
+    pub speed: Option<f64>,
+    pub time_code: Option<u64>,
+    pub time_code_flags: AsioTimeCodeFlags,
+    pub sample_rate_changed: bool,
+    pub clock_source_changed: bool,
+}
+
+impl From<&AsioTime> for TimingSnapshot {
+    fn from(time: &AsioTime) -> Self {
+        let info = &time.time_info;
+        TimingSnapshot {
+            system_time: info.system_time(),
+            sample_position: info.sample_position(),
+            sample_rate: info.sample_rate(),
+            speed: info.speed(),
+            time_code: time.time_code.samples(),
+            time_code_flags: time.time_code.flags,
+            sample_rate_changed: info.flags.contains(AsioTimeInfoFlags::sampleRateChanged),
+            clock_source_changed: info.flags.contains(AsioTimeInfoFlags::clockSourceChanged),
+        }
+    }
+}
+
+/// Sample clock of a running stream, fed with pairs of sample position and
+/// system time from [`Driver::get_sample_position`] or from the
+/// [`TimingSnapshot`]s of `buffer_switch_time_info`.
+///
+/// The position it reports never goes backwards: when the driver's position
+/// jumps back, e.g. after a restart, counting continues from the last value
+/// and the rate estimate starts over. The true rate is estimated by a least
+/// squares fit over the most recent observations, which also maps between
+/// system time and sample position for scheduling.
+#[derive(Debug, Clone)]
+pub struct StreamClock {
+    nominal_rate: AsioSampleRate,
+    capacity: usize,
+    // (monotonic position, system time in nanoseconds), oldest first
+    observations: VecDeque<(u64, u64)>,
+    last_raw: Option<u64>,
+    position: u64,
+}
+
+impl StreamClock {
+    /// Fits over the last `window` observations, at least two.
+    ///
+    /// The window is allocated up front, so observing never allocates and
+    /// can be done from the audio thread.
+    pub fn new(nominal_rate: AsioSampleRate, window: usize) -> StreamClock {
+        let capacity = window.max(2);
+        StreamClock {
+            nominal_rate,
+            capacity,
+            observations: VecDeque::with_capacity(capacity),
+            last_raw: None,
+            position: 0,
+        }
+    }
+
+    pub fn nominal_rate(&self) -> AsioSampleRate {
+        self.nominal_rate
+    }
+
+    /// Switches to a new nominal rate, dropping the estimate of the old one.
+    pub fn set_nominal_rate(&mut self, nominal_rate: AsioSampleRate) {
+        self.nominal_rate = nominal_rate;
+        self.observations.clear();
+    }
+
+    /// Adds an observation of the driver's position `samples` at `time`.
+    ///
+    /// Returns `false` if it was ignored because time did not advance.
+    pub fn observe(&mut self, samples: u64, time: Duration) -> bool {
+        let nanos = time.as_nanos() as u64;
+        if self
+            .observations
+            .back()
+            .is_some_and(|&(_, last)| nanos <= last)
+        {
+            return false;
+        }
+        match self.last_raw {
+            Some(last) if samples >= last => self.position += samples - last,
+            // Discontinuity, the old fit does not describe the new stream
+            Some(_) => self.observations.clear(),
+            None => self.position = samples,
+        }
+        self.last_raw = Some(samples);
+        if self.observations.len() == self.capacity {
+            self.observations.pop_front();
+        }
+        self.observations.push_back((self.position, nanos));
+        true
+    }
+
+    pub fn observe_position(&mut self, position: &SamplePosition) -> bool {
+        self.observe(position.samples, Duration::from_nanos(position.timestamp))
+    }
+
+    /// Returns `false` if the snapshot lacks a valid position or time.
+    pub fn observe_snapshot(&mut self, snapshot: &TimingSnapshot) -> bool {
+        match (snapshot.sample_position, snapshot.system_time) {
+            (Some(samples), Some(time)) => self.observe(samples, time),
+            _ => false,
+        }
+    }
+
+    /// Queries the driver's current position and observes it.
+    pub fn poll(&mut self, driver: &Driver) -> Result<bool, AsioError> {
+        Ok(self.observe_position(&driver.get_sample_position()?))
+    }
+
+    /// Monotonic sample counter as of the last observation.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Rate in samples per second fitted over the window, once two
+    /// observations are available.
+    pub fn estimated_rate(&self) -> Option<f64> {
+        self.fit().map(|(_, _, slope)| slope * 1e9)
+    }
+
+    /// Deviation of the estimated from the nominal rate in parts per million.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        let rate = self.estimated_rate()?;
+        Some((rate / self.nominal_rate - 1.0) * 1e6)
+    }
+
+    /// Sample position at system time `time`, extrapolated with the
+    /// estimated or, before there is one, the nominal rate.
+    pub fn sample_at(&self, time: Duration) -> Option<u64> {
+        let (mean_position, mean_time, slope) = self.line()?;
+        let position = mean_position + slope * (time.as_nanos() as f64 - mean_time);
+        Some(position.max(0.0).round() as u64)
+    }
+
+    /// System time at which the stream reaches `sample`.
+    pub fn time_at(&self, sample: u64) -> Option<Duration> {
+        let (mean_position, mean_time, slope) = self.line()?;
+        let nanos = mean_time + (sample as f64 - mean_position) / slope;
+        Some(Duration::from_nanos(nanos.max(0.0).round() as u64))
+    }
+
+    fn line(&self) -> Option<(f64, f64, f64)> {
+        if let Some(fit) = self.fit() {
+            return Some(fit);
+        }
+        let &(position, nanos) = self.observations.back()?;
+        (self.nominal_rate > 0.0).then_some((
+            position as f64,
+            nanos as f64,
+            self.nominal_rate / 1e9,
+        ))
+    }
+
+    // Mean position, mean time and samples per nanosecond
+    fn fit(&self) -> Option<(f64, f64, f64)> {
+        if self.observations.len() < 2 {
+            return None;
+        }
+        // Relative to the oldest observation to keep the precision of f64
+        let &(first_position, first_time) = self.observations.front()?;
+        let n = self.observations.len() as f64;
+        let relative = || {
+            self.observations.iter().map(move |&(position, nanos)| {
+                (
+                    (nanos - first_time) as f64,
+                    (position - first_position) as f64,
+                )
+            })
+        };
+        let (sum_t, sum_p) = relative().fold((0.0, 0.0), |(t, p), (dt, dp)| (t + dt, p + dp));
+        let (mean_t, mean_p) = (sum_t / n, sum_p / n);
+        let (covariance, variance) = relative().fold((0.0, 0.0), |(c, v), (dt, dp)| {
+            (
+                c + (dt - mean_t) * (dp - mean_p),
+                v + (dt - mean_t) * (dt - mean_t),
+            )
+        });
+        let slope = covariance / variance;
+        (slope > 0.0).then_some((
+            first_position as f64 + mean_p,
+            first_time as f64 + mean_t,
+            slope,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockConfig, MockDriver};
+    use crate::{AsioSamples, AsioTimeCode, AsioTimeInfo, AsioTimestamp};
+
+    #[test]
+    fn only_valid_fields_are_exposed() {
+        let info = AsioTimeInfo::new(
+            1.5,
+            AsioTimestamp::from_u64(2_000_000_123),
+            AsioSamples::from_u64(96_000),
+            48000.0,
+            AsioTimeInfoFlags::systemTimeValid
+                | AsioTimeInfoFlags::samplePositionValid
+                | AsioTimeInfoFlags::clockSourceChanged,
+        );
+        let code = AsioTimeCode::new(1.0, AsioSamples::from_u64(480), AsioTimeCodeFlags::running);
+        let snapshot = AsioTime::new(info, code).snapshot();
+        assert_eq!(
+            snapshot,
+            TimingSnapshot {
+                system_time: Some(Duration::new(2, 123)),
+                sample_position: Some(96_000),
+                sample_rate: None,
+                speed: None,
+                time_code: None,
+                time_code_flags: AsioTimeCodeFlags::running,
+                sample_rate_changed: false,
+                clock_source_changed: true,
+            }
+        );
+
+        let info = AsioTimeInfo::new(
+            1.5,
+            AsioTimestamp { hi: 1, lo: 7 },
+            AsioSamples { hi: 2, lo: 0 },
+            44100.0,
+            AsioTimeInfoFlags::sampleRateValid
+                | AsioTimeInfoFlags::speedValid
+                | AsioTimeInfoFlags::sampleRateChanged,
+        );
+        let code = AsioTimeCode::new(
+            1.0,
+            AsioSamples { hi: 0, lo: 480 },
+            AsioTimeCodeFlags::valid,
+        );
+        let snapshot = AsioTime::new(info, code).snapshot();
+        assert_eq!(snapshot.system_time, None);
+        assert_eq!(snapshot.sample_position, None);
+        assert_eq!(snapshot.sample_rate, Some(44100.0));
+        assert_eq!(snapshot.speed, Some(1.5));
+        assert_eq!(snapshot.time_code, Some(480));
+        assert!(snapshot.sample_rate_changed);
+        assert!(!snapshot.clock_source_changed);
+        assert_eq!(AsioTime::default().snapshot(), TimingSnapshot::default());
+    }
+
+    // Observations of a device running `ppm` fast, one per `period` samples
+    fn feed(clock: &mut StreamClock, from: u64, count: u64, period: u64, ppm: f64) {
+        let rate = 48000.0 * (1.0 + ppm / 1e6);
+        for i in from..from + count {
+            let samples = i * period;
+            let nanos = 1_000_000_000 + (samples as f64 / rate * 1e9).round() as u64;
+            assert!(clock.observe(samples, Duration::from_nanos(nanos)));
+        }
+    }
+
+    #[test]
+    fn estimates_rate_and_drift() {
+        let mut clock = StreamClock::new(48000.0, 32);
+        let allocated = clock.observations.capacity();
+        assert!(allocated >= 32);
+        assert_eq!(clock.estimated_rate(), None);
+        assert_eq!(clock.sample_at(Duration::ZERO), None);
+        feed(&mut clock, 0, 1, 256, 50.0);
+        assert_eq!(clock.estimated_rate(), None);
+        // The nominal rate stands in until there is a fit
+        assert_eq!(clock.sample_at(Duration::from_millis(1010)), Some(480));
+        feed(&mut clock, 1, 99, 256, 50.0);
+        let rate = clock.estimated_rate().unwrap();
+        assert!((rate - 48002.4).abs() < 0.01, "{}", rate);
+        let drift = clock.drift_ppm().unwrap();
+        assert!((drift - 50.0).abs() < 0.2, "{}", drift);
+        assert_eq!(clock.position(), 99 * 256);
+        assert_eq!(clock.observations.capacity(), allocated);
+
+        // One second after the start the device has played 50 ppm more
+        assert_eq!(clock.sample_at(Duration::from_secs(2)), Some(48002));
+        let time = clock.time_at(96_005).unwrap();
+        assert!((time.as_secs_f64() - 3.000_004).abs() < 1e-6, "{:?}", time);
+    }
+
+    #[test]
+    fn position_stays_monotonic() {
+        let mut clock = StreamClock::new(48000.0, 8);
+        feed(&mut clock, 10, 8, 256, 0.0);
+        assert_eq!(clock.position(), 17 * 256);
+        // Time must advance
+        assert!(!clock.observe(20 * 256, Duration::from_secs(1)));
+        // The driver restarted from zero
+        assert!(clock.observe(0, Duration::from_secs(5)));
+        assert_eq!(clock.position(), 17 * 256);
+        assert_eq!(clock.estimated_rate(), None);
+        assert!(clock.observe(480, Duration::from_millis(5010)));
+        assert_eq!(clock.position(), 17 * 256 + 480);
+        assert!((clock.estimated_rate().unwrap() - 48000.0).abs() < 1e-6);
+
+        let snapshot = TimingSnapshot {
+            sample_position: Some(960),
+            ..TimingSnapshot::default()
+        };
+        assert!(!clock.observe_snapshot(&snapshot));
+        let snapshot = TimingSnapshot {
+            system_time: Some(Duration::from_millis(5020)),
+            ..snapshot
+        };
+        assert!(clock.observe_snapshot(&snapshot));
+        assert_eq!(clock.position(), 17 * 256 + 960);
+    }
+
+    #[test]
+    fn polls_the_driver() {
+        let mock = MockDriver::new(MockConfig::default());
+        let driver = Driver::from_raw(mock.driver());
+        let mut clock = StreamClock::new(48000.0, 4);
+        assert_eq!(clock.poll(&driver), Err(AsioError::NotPresent));
+        unsafe { driver.init(std::ptr::null_mut()) }.unwrap();
+        assert_eq!(clock.poll(&driver), Ok(true));
+        assert_eq!(clock.position(), 0);
+        // The position only moves while streaming
+        assert_eq!(clock.poll(&driver), Ok(false));
+    }
+}
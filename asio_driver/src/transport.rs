@@ -0,0 +1,254 @@
+//! Typed transport control on top of the `Transport` future call.
+//!
+//! [`TransportCommand`] fills in `AsioTransportParameters` for each command,
+//! [`TrackMask`] is the 512-bit `trackSwitches` array of `Arm` and `Monitor`.
+
+use crate::{AsioError, AsioSamples, AsioTransportCommand, AsioTransportParameters};
+
+/// Tracks addressable through `trackSwitches`.
+pub const TRACKS: usize = 512;
+
+/// Set of tracks, bit `n % 32` of word `n / 32` standing for track `n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TrackMask([u32; TRACKS / 32]);
+
+impl TrackMask {
+    pub const EMPTY: TrackMask = TrackMask([0; TRACKS / 32]);
+    pub const ALL: TrackMask = TrackMask([u32::MAX; TRACKS / 32]);
+
+    /// Panics if `track` is not below [`TRACKS`].
+    pub fn insert(&mut self, track: usize) {
+        assert!(track < TRACKS, "track {} out of range", track);
+        self.0[track / 32] |= 1 << (track % 32);
+    }
+    /// Panics if `track` is not below [`TRACKS`].
+    pub fn remove(&mut self, track: usize) {
+        assert!(track < TRACKS, "track {} out of range", track);
+        self.0[track / 32] &= !(1 << (track % 32));
+    }
+    pub fn with(mut self, track: usize) -> Self {
+        self.insert(track);
+        self
+    }
+    pub fn contains(&self, track: usize) -> bool {
+        track < TRACKS && self.0[track / 32] & (1 << (track % 32)) != 0
+    }
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        *self == TrackMask::EMPTY
+    }
+    /// Tracks in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..TRACKS).filter(|track| self.contains(*track))
+    }
+
+    pub fn to_raw(&self) -> [i32; TRACKS / 32] {
+        self.0.map(|word| word as i32)
+    }
+    pub fn from_raw(raw: &[i32; TRACKS / 32]) -> TrackMask {
+        TrackMask(raw.map(|word| word as u32))
+    }
+}
+
+impl FromIterator<usize> for TrackMask {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut mask = TrackMask::EMPTY;
+        for track in iter {
+            mask.insert(track);
+        }
+        mask
+    }
+}
+
+/// Transport command for [`crate::Driver::transport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportCommand {
+    Start,
+    Stop,
+    /// Moves to a sample position.
+    Locate(u64),
+    PunchIn,
+    PunchOut,
+    ArmOn(i32),
+    ArmOff(i32),
+    MonitorOn(i32),
+    MonitorOff(i32),
+    /// Arms exactly the tracks in the mask.
+    Arm(TrackMask),
+    /// Monitors exactly the tracks in the mask.
+    Monitor(TrackMask),
+}
+
+impl TransportCommand {
+    pub fn command(&self) -> AsioTransportCommand {
+        match self {
+            TransportCommand::Start => AsioTransportCommand::Start,
+            TransportCommand::Stop => AsioTransportCommand::Stop,
+            TransportCommand::Locate(_) => AsioTransportCommand::Locate,
+            TransportCommand::PunchIn => AsioTransportCommand::PunchIn,
+            TransportCommand::PunchOut => AsioTransportCommand::PunchOut,
+            TransportCommand::ArmOn(_) => AsioTransportCommand::ArmOn,
+            TransportCommand::ArmOff(_) => AsioTransportCommand::ArmOff,
+            TransportCommand::MonitorOn(_) => AsioTransportCommand::MonitorOn,
+            TransportCommand::MonitorOff(_) => AsioTransportCommand::MonitorOff,
+            TransportCommand::Arm(_) => AsioTransportCommand::Arm,
+            TransportCommand::Monitor(_) => AsioTransportCommand::Monitor,
+        }
+    }
+
+    /// The SDK structure, with only the fields the command uses set.
+    pub fn to_raw(&self) -> AsioTransportParameters {
+        let mut params = AsioTransportParameters::new(self.command());
+        match *self {
+            TransportCommand::Locate(position) => {
+                params.sample_position = AsioSamples::from_u64(position)
+            }
+            TransportCommand::ArmOn(track)
+            | TransportCommand::ArmOff(track)
+            | TransportCommand::MonitorOn(track)
+            | TransportCommand::MonitorOff(track) => params.track = track,
+            TransportCommand::Arm(mask) | TransportCommand::Monitor(mask) => {
+                params.track_switches = mask.to_raw()
+            }
+            _ => {}
+        }
+        params
+    }
+
+    pub fn from_raw(params: &AsioTransportParameters) -> TransportCommand {
+        let mask = || TrackMask::from_raw(&params.track_switches);
+        match params.command {
+            AsioTransportCommand::Start => TransportCommand::Start,
+            AsioTransportCommand::Stop => TransportCommand::Stop,
+            AsioTransportCommand::Locate => {
+                TransportCommand::Locate(params.sample_position.to_u64())
+            }
+            AsioTransportCommand::PunchIn => TransportCommand::PunchIn,
+            AsioTransportCommand::PunchOut => TransportCommand::PunchOut,
+            AsioTransportCommand::ArmOn => TransportCommand::ArmOn(params.track),
+            AsioTransportCommand::ArmOff => TransportCommand::ArmOff(params.track),
+            AsioTransportCommand::MonitorOn => TransportCommand::MonitorOn(params.track),
+            AsioTransportCommand::MonitorOff => TransportCommand::MonitorOff(params.track),
+            AsioTransportCommand::Arm => TransportCommand::Arm(mask()),
+            AsioTransportCommand::Monitor => TransportCommand::Monitor(mask()),
+        }
+    }
+}
+
+/// Why a transport command was not carried out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportError {
+    /// The driver does not answer `CanTransport`.
+    Unsupported,
+    Driver(AsioError),
+}
+
+impl std::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Unsupported => {
+                f.write_str("the driver does not support transport control")
+            }
+            TransportError::Driver(error) => write!(f, "the transport command failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized, MockCall, MockConfig, MockDriver};
+    use crate::AsioFutureSelector;
+
+    #[test]
+    fn track_mask_bits() {
+        let mask: TrackMask = [0, 31, 32, 511].into_iter().collect();
+        let raw = mask.to_raw();
+        assert_eq!(raw[0], 1 | i32::MIN);
+        assert_eq!(raw[1], 1);
+        assert_eq!(raw[15], i32::MIN);
+        assert_eq!(TrackMask::from_raw(&raw), mask);
+        assert_eq!(mask.iter().collect::<Vec<_>>(), [0, 31, 32, 511]);
+        assert_eq!(mask.len(), 4);
+        assert!(!mask.contains(512));
+
+        let mut mask = mask;
+        mask.remove(31);
+        assert!(!mask.contains(31) && mask.contains(32));
+        assert_eq!(TrackMask::ALL.len(), TRACKS);
+        assert!(TrackMask::EMPTY.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "track 512 out of range")]
+    fn track_mask_is_bounded() {
+        TrackMask::EMPTY.with(512);
+    }
+
+    #[test]
+    fn commands_fill_their_fields() {
+        let params = TransportCommand::Locate(0x1_0000_0002).to_raw();
+        assert_eq!(params.command, AsioTransportCommand::Locate);
+        assert_eq!(
+            (params.sample_position.hi, params.sample_position.lo),
+            (1, 2)
+        );
+        assert_eq!(params.track, 0);
+
+        let params = TransportCommand::MonitorOff(7).to_raw();
+        assert_eq!(
+            (params.command, params.track),
+            (AsioTransportCommand::MonitorOff, 7)
+        );
+        assert_eq!(params.track_switches, [0; 16]);
+
+        let commands = [
+            TransportCommand::Start,
+            TransportCommand::Stop,
+            TransportCommand::Locate(48000),
+            TransportCommand::PunchIn,
+            TransportCommand::PunchOut,
+            TransportCommand::ArmOn(3),
+            TransportCommand::ArmOff(3),
+            TransportCommand::MonitorOn(4),
+            TransportCommand::MonitorOff(4),
+            TransportCommand::Arm(TrackMask::EMPTY.with(100)),
+            TransportCommand::Monitor(TrackMask::ALL),
+        ];
+        for command in commands {
+            assert_eq!(TransportCommand::from_raw(&command.to_raw()), command);
+        }
+    }
+
+    #[test]
+    fn transport_needs_can_transport() {
+        let mock = MockDriver::new(MockConfig::default());
+        let driver = initialized(&mock);
+        assert_eq!(
+            driver.transport(&TransportCommand::Start),
+            Err(TransportError::Unsupported)
+        );
+
+        let mock = MockDriver::new(MockConfig {
+            capabilities: vec![AsioFutureSelector::CanTransport],
+            ..MockConfig::default()
+        });
+        let driver = initialized(&mock);
+        let arm = TransportCommand::Arm(TrackMask::EMPTY.with(1).with(300));
+        driver.transport(&arm).unwrap();
+        driver.transport(&TransportCommand::Locate(96000)).unwrap();
+        mock.fail_once(MockCall::Transport, AsioError::HwMalfunction);
+        assert_eq!(
+            driver.transport(&TransportCommand::Start),
+            Err(TransportError::Driver(AsioError::HwMalfunction))
+        );
+        assert_eq!(
+            mock.transport_commands(),
+            [arm, TransportCommand::Locate(96000)]
+        );
+    }
+}
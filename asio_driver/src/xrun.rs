@@ -0,0 +1,446 @@
+//! Detection of missed buffer switches.
+//!
+//! Consecutive buffer switches are one buffer apart in sample position, a
+//! larger step means the host missed buffers. [`XrunDetector`] runs on the
+//! audio thread and checks every position it is given, [`XrunMonitor`] hands
+//! counters and [`XrunEvent`]s to the application. [`XrunHandler`] wires a
+//! detector into any [`AsioHandler`], fed from `buffer_switch_time_info` and
+//! the driver's `Overload` messages.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::callbacks::AsioHandler;
+use crate::ring::{ring, Consumer, Producer};
+use crate::{AsioMessageSelector, AsioSampleRate, AsioTime};
+
+/// Buffers the host missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct XrunEvent {
+    /// Position of the first missed sample.
+    pub at_sample: u64,
+    pub missed_buffers: u64,
+}
+
+/// Counters since the detector was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct XrunStats {
+    pub xruns: u64,
+    pub missed_buffers: u64,
+    /// `Overload` messages from the driver.
+    pub overloads: u64,
+    /// Xruns without an `Overload` message within one buffer switch before or
+    /// after the one that revealed them, only counted for drivers that report
+    /// overloads and once that window has passed.
+    pub unreported: u64,
+}
+
+struct Shared {
+    buffer_size: AtomicU32,
+    reports_overload: AtomicBool,
+    xruns: AtomicU64,
+    missed_buffers: AtomicU64,
+    overloads: AtomicU64,
+    unreported: AtomicU64,
+}
+
+/// Creates a detector for buffers of `buffer_size` samples, queueing up to
+/// `capacity` events.
+///
+/// `reports_overload` is the driver's answer to
+/// [`crate::Driver::can_report_overload`].
+pub fn xrun_detector(
+    buffer_size: i32,
+    reports_overload: bool,
+    capacity: usize,
+) -> (XrunDetector, XrunMonitor) {
+    let shared = Arc::new(Shared {
+        buffer_size: AtomicU32::new(buffer_size.max(0) as u32),
+        reports_overload: AtomicBool::new(reports_overload),
+        xruns: AtomicU64::new(0),
+        missed_buffers: AtomicU64::new(0),
+        overloads: AtomicU64::new(0),
+        unreported: AtomicU64::new(0),
+    });
+    let (producer, consumer) = ring(capacity);
+    let detector = XrunDetector {
+        shared: shared.clone(),
+        events: producer,
+        last: None,
+        switches: 0,
+        overload_at: None,
+        xrun_at: None,
+    };
+    (
+        detector,
+        XrunMonitor {
+            shared,
+            events: consumer,
+        },
+    )
+}
+
+/// Audio thread side of [`xrun_detector`], wait-free.
+pub struct XrunDetector {
+    shared: Arc<Shared>,
+    events: Producer<XrunEvent>,
+    last: Option<u64>,
+    // Buffer switches observed so far
+    switches: u64,
+    // Switch after which the latest overload not matched to an xrun arrived
+    overload_at: Option<u64>,
+    // Switch that revealed the latest xrun still waiting for an overload
+    xrun_at: Option<u64>,
+}
+
+impl XrunDetector {
+    /// Checks the sample position of the current buffer switch against the
+    /// previous one.
+    ///
+    /// A position going backwards is taken as a restart and only resyncs.
+    pub fn observe(&mut self, sample_position: u64) -> Option<XrunEvent> {
+        let size = self.shared.buffer_size.load(Ordering::Relaxed) as u64;
+        self.switches += 1;
+        // An overload may still arrive up to the switch after the xrun
+        if self.xrun_at.is_some_and(|xrun| self.switches >= xrun + 2) {
+            self.settle_unreported();
+        }
+        let last = self.last.replace(sample_position)?;
+        let expected = last + size;
+        if size == 0 || sample_position <= expected {
+            return None;
+        }
+        let event = XrunEvent {
+            at_sample: expected,
+            missed_buffers: (sample_position - expected).div_ceil(size),
+        };
+        let shared = &*self.shared;
+        shared.xruns.fetch_add(1, Ordering::Relaxed);
+        shared
+            .missed_buffers
+            .fetch_add(event.missed_buffers, Ordering::Relaxed);
+        let reported = self
+            .overload_at
+            .take()
+            .is_some_and(|overload| overload + 2 >= self.switches);
+        if !reported {
+            if self.xrun_at.is_some() {
+                self.settle_unreported();
+            }
+            self.xrun_at = Some(self.switches);
+        }
+        self.events.push(event);
+        Some(event)
+    }
+
+    /// Records an `Overload` message from the driver.
+    ///
+    /// It accounts for an xrun revealed by the current or the previous
+    /// buffer switch, or else by one of the next two.
+    pub fn overload(&mut self) {
+        if self.xrun_at.take().is_none() {
+            self.overload_at = Some(self.switches);
+        }
+        self.shared.overloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Forgets the previous position, e.g. after the sample rate changed.
+    pub fn resync(&mut self) {
+        self.last = None;
+    }
+
+    fn settle_unreported(&mut self) {
+        self.xrun_at = None;
+        if self.shared.reports_overload.load(Ordering::Relaxed) {
+            self.shared.unreported.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Application side of [`xrun_detector`].
+pub struct XrunMonitor {
+    shared: Arc<Shared>,
+    events: Consumer<XrunEvent>,
+}
+
+impl XrunMonitor {
+    pub fn stats(&self) -> XrunStats {
+        let shared = &*self.shared;
+        XrunStats {
+            xruns: shared.xruns.load(Ordering::Relaxed),
+            missed_buffers: shared.missed_buffers.load(Ordering::Relaxed),
+            overloads: shared.overloads.load(Ordering::Relaxed),
+            unreported: shared.unreported.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<XrunEvent> {
+        self.events.pop()
+    }
+
+    /// Pops every event currently queued.
+    pub fn drain(&mut self) -> impl Iterator<Item = XrunEvent> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+
+    /// Number of events lost to a full queue so far, they are still counted
+    /// in [`XrunMonitor::stats`].
+    pub fn dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+
+    /// Follows a new buffer size, e.g. after a reset.
+    pub fn set_buffer_size(&self, buffer_size: i32) {
+        let size = buffer_size.max(0) as u32;
+        self.shared.buffer_size.store(size, Ordering::Relaxed);
+    }
+}
+
+/// Feeds an [`XrunDetector`] from the callbacks before passing them on.
+///
+/// It answers `SupportsTimeInfo`, as positions only arrive with the time
+/// info. Handlers that do not override `buffer_switch_time_info` still get
+/// `buffer_switch` through its default.
+pub struct XrunHandler<H> {
+    inner: H,
+    detector: XrunDetector,
+}
+
+impl<H: AsioHandler> XrunHandler<H> {
+    pub fn new(inner: H, detector: XrunDetector) -> XrunHandler<H> {
+        XrunHandler { inner, detector }
+    }
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+}
+
+impl<H: AsioHandler> AsioHandler for XrunHandler<H> {
+    fn buffer_switch(&mut self, double_buffer_idx: i32, direct_process: bool) {
+        self.inner.buffer_switch(double_buffer_idx, direct_process)
+    }
+
+    fn buffer_switch_time_info(
+        &mut self,
+        time: &mut AsioTime,
+        double_buffer_idx: i32,
+        direct_process: bool,
+    ) {
+        if let Some(position) = time.time_info.sample_position() {
+            self.detector.observe(position);
+        }
+        self.inner
+            .buffer_switch_time_info(time, double_buffer_idx, direct_process)
+    }
+
+    fn sample_rate_did_change(&mut self, sample_rate: AsioSampleRate) {
+        self.detector.resync();
+        self.inner.sample_rate_did_change(sample_rate)
+    }
+
+    fn asio_message(
+        &mut self,
+        selector: AsioMessageSelector,
+        value: i32,
+        message: *mut c_void,
+        opt: *mut f64,
+    ) -> i32 {
+        let reply = self.inner.asio_message(selector, value, message, opt);
+        match selector {
+            AsioMessageSelector::SelectorSupported
+                if value == AsioMessageSelector::SupportsTimeInfo as i32
+                    || value == AsioMessageSelector::Overload as i32 =>
+            {
+                1
+            }
+            AsioMessageSelector::SupportsTimeInfo => 1,
+            AsioMessageSelector::Overload => {
+                self.detector.overload();
+                1
+            }
+            _ => reply,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbacks::TEST_SLOT;
+    use crate::mock::{initialized, MockConfig, MockDriver, MockEvent, Quiet};
+    use crate::{AsioBufferInfo, AsioFutureSelector, CallbackSlot};
+    use std::sync::PoisonError;
+    use std::time::Duration;
+
+    #[test]
+    fn gaps_are_counted_in_buffers() {
+        let (mut detector, mut monitor) = xrun_detector(256, true, 8);
+        assert_eq!(detector.observe(1024), None);
+        assert_eq!(detector.observe(1280), None);
+        assert_eq!(
+            detector.observe(2048),
+            Some(XrunEvent {
+                at_sample: 1536,
+                missed_buffers: 2
+            })
+        );
+        detector.overload();
+        // Not a whole number of buffers, still one missed
+        assert_eq!(
+            detector.observe(2048 + 300),
+            Some(XrunEvent {
+                at_sample: 2304,
+                missed_buffers: 1
+            })
+        );
+        // A restart resyncs silently
+        assert_eq!(detector.observe(0), None);
+        assert_eq!(detector.observe(256), None);
+        monitor.set_buffer_size(128);
+        assert_eq!(detector.observe(384), None);
+        detector.resync();
+        assert_eq!(detector.observe(8192), None);
+        assert_eq!(
+            monitor.stats(),
+            XrunStats {
+                xruns: 2,
+                missed_buffers: 3,
+                overloads: 1,
+                unreported: 1,
+            }
+        );
+        assert_eq!(monitor.drain().count(), 2);
+        assert_eq!(monitor.dropped(), 0);
+    }
+
+    #[test]
+    fn overloads_match_xruns_one_switch_apart() {
+        let (mut detector, monitor) = xrun_detector(256, true, 8);
+        // Right before the switch ahead of the gap
+        detector.observe(0);
+        detector.overload();
+        detector.observe(256);
+        assert!(detector.observe(1024).is_some());
+        // One switch after the one revealing the gap
+        detector.observe(1280);
+        assert!(detector.observe(2048).is_some());
+        detector.observe(2304);
+        detector.overload();
+        assert_eq!(monitor.stats().unreported, 0);
+        // Two switches after is too late
+        assert!(detector.observe(3072).is_some());
+        detector.observe(3328);
+        detector.observe(3584);
+        assert_eq!(monitor.stats().unreported, 1);
+        detector.overload();
+        // And too early for the next gap
+        detector.observe(3840);
+        detector.observe(4096);
+        assert!(detector.observe(4864).is_some());
+        detector.observe(5120);
+        assert_eq!(monitor.stats().unreported, 1);
+        detector.observe(5376);
+        assert_eq!(
+            monitor.stats(),
+            XrunStats {
+                xruns: 4,
+                missed_buffers: 8,
+                overloads: 3,
+                unreported: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn unreported_needs_a_reporting_driver() {
+        let (mut detector, monitor) = xrun_detector(64, false, 1);
+        for position in [0, 256, 512, 1024] {
+            detector.observe(position);
+        }
+        let stats = monitor.stats();
+        assert_eq!((stats.xruns, stats.missed_buffers), (3, 3 + 3 + 7));
+        assert_eq!(stats.unreported, 0);
+        assert_eq!(monitor.dropped(), 2);
+    }
+
+    #[test]
+    fn dropouts_reach_the_monitor() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig::small());
+        let driver = initialized(&mock);
+        let (detector, mut monitor) = xrun_detector(32, driver.can_report_overload(), 8);
+        let slot = CallbackSlot::acquire(XrunHandler::new(Quiet::default(), detector)).unwrap();
+        let buffer_infos = vec![AsioBufferInfo::new_output(0)];
+        let running = driver
+            .create_buffers_with_slot(buffer_infos, 32, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        mock.schedule(2, MockEvent::Dropout(3));
+        mock.schedule(
+            4,
+            MockEvent::Message {
+                selector: AsioMessageSelector::Overload,
+                value: 0,
+            },
+        );
+        assert!(mock.wait_for_buffer_switches(6, Duration::from_secs(5)));
+        drop(running);
+        assert_eq!(
+            monitor.drain().collect::<Vec<_>>(),
+            [XrunEvent {
+                at_sample: 64,
+                missed_buffers: 3
+            }]
+        );
+        let stats = monitor.stats();
+        assert_eq!((stats.xruns, stats.overloads, stats.unreported), (1, 1, 0));
+    }
+
+    #[test]
+    fn driver_overloads_after_the_dropout_are_matched() {
+        let _slot_lock = TEST_SLOT.lock().unwrap_or_else(PoisonError::into_inner);
+        let mock = MockDriver::new(MockConfig {
+            capabilities: vec![
+                AsioFutureSelector::CanTimeInfo,
+                AsioFutureSelector::CanReportOverload,
+            ],
+            ..MockConfig::small()
+        });
+        let driver = initialized(&mock);
+        assert!(driver.can_report_overload());
+        let (detector, monitor) = xrun_detector(32, driver.can_report_overload(), 8);
+        let slot = CallbackSlot::acquire(XrunHandler::new(Quiet::default(), detector)).unwrap();
+        let buffer_infos = vec![AsioBufferInfo::new_output(0)];
+        let running = driver
+            .create_buffers_with_slot(buffer_infos, 32, slot)
+            .unwrap()
+            .start()
+            .unwrap();
+        // The driver reports the first dropout after the buffer it missed
+        mock.schedule(2, MockEvent::Dropout(1));
+        mock.schedule(
+            3,
+            MockEvent::Message {
+                selector: AsioMessageSelector::Overload,
+                value: 0,
+            },
+        );
+        mock.schedule(6, MockEvent::Dropout(2));
+        assert!(mock.wait_for_buffer_switches(10, Duration::from_secs(5)));
+        drop(running);
+        assert_eq!(
+            monitor.stats(),
+            XrunStats {
+                xruns: 2,
+                missed_buffers: 3,
+                overloads: 1,
+                unreported: 1,
+            }
+        );
+    }
+}